@@ -1,15 +1,28 @@
 use serde::{Deserialize, Serialize};
-use rusqlite::{params, Connection, Result};
+use rusqlite::{params, Connection, Result, Row};
 use uuid::Uuid;
 use chrono::Utc;
+use crate::crypto::{is_encrypted, TokenCipher};
+use crate::error::AppError;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Agent {
     pub id: String,
     pub name: String,
     pub api_url: String,
+    // Encrypted when loaded from the database, masked when sent to the frontend
     pub auth_token: String,
     pub project_id: String,
+    // Number of earlier messages sent with each query; 0 sends only the new message
+    #[serde(default = "default_history_window")]
+    pub history_window: i64,
+    // Request overrides; None uses the defaults in `http`
+    #[serde(default)]
+    pub connect_timeout_secs: Option<i64>,
+    #[serde(default)]
+    pub read_timeout_secs: Option<i64>,
+    #[serde(default)]
+    pub max_retries: Option<i64>,
     pub created_at: i64,
 }
 
@@ -19,6 +32,19 @@ pub struct CreateAgentInput {
     pub api_url: String,
     pub auth_token: String,
     pub project_id: String,
+    pub history_window: Option<i64>,
+    #[serde(default)]
+    pub connect_timeout_secs: Option<i64>,
+    #[serde(default)]
+    pub read_timeout_secs: Option<i64>,
+    #[serde(default)]
+    pub max_retries: Option<i64>,
+}
+
+pub const DEFAULT_HISTORY_WINDOW: i64 = 10;
+
+fn default_history_window() -> i64 {
+    DEFAULT_HISTORY_WINDOW
 }
 
 impl Agent {
@@ -29,20 +55,29 @@ impl Agent {
             api_url: input.api_url,
             auth_token: input.auth_token,
             project_id: input.project_id,
+            history_window: input.history_window.unwrap_or(DEFAULT_HISTORY_WINDOW).max(0),
+            connect_timeout_secs: input.connect_timeout_secs,
+            read_timeout_secs: input.read_timeout_secs,
+            max_retries: input.max_retries,
             created_at: Utc::now().timestamp(),
         }
     }
     
     pub fn save(&self, conn: &Connection) -> Result<()> {
         conn.execute(
-            "INSERT INTO agents (id, name, api_url, auth_token, project_id, created_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            "INSERT INTO agents (id, name, api_url, auth_token, project_id, history_window,
+                                 connect_timeout_secs, read_timeout_secs, max_retries, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
             params![
                 &self.id,
                 &self.name,
                 &self.api_url,
                 &self.auth_token,
                 &self.project_id,
+                &self.history_window,
+                &self.connect_timeout_secs,
+                &self.read_timeout_secs,
+                &self.max_retries,
                 &self.created_at
             ],
         )?;
@@ -51,29 +86,22 @@ impl Agent {
     
     pub fn get_all(conn: &Connection) -> Result<Vec<Agent>> {
         let mut stmt = conn.prepare(
-            "SELECT id, name, api_url, auth_token, project_id, created_at 
+            "SELECT id, name, api_url, auth_token, project_id, history_window,
+                    connect_timeout_secs, read_timeout_secs, max_retries, created_at 
              FROM agents 
              ORDER BY created_at DESC"
         )?;
         
-        let agents = stmt.query_map([], |row| {
-            Ok(Agent {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                api_url: row.get(2)?,
-                auth_token: row.get(3)?,
-                project_id: row.get(4)?,
-                created_at: row.get(5)?,
-            })
-        })?
-        .collect::<Result<Vec<_>, _>>()?;
+        let agents = stmt.query_map([], Self::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
         
         Ok(agents)
     }
     
     pub fn get_by_id(conn: &Connection, id: &str) -> Result<Option<Agent>> {
         let mut stmt = conn.prepare(
-            "SELECT id, name, api_url, auth_token, project_id, created_at 
+            "SELECT id, name, api_url, auth_token, project_id, history_window,
+                    connect_timeout_secs, read_timeout_secs, max_retries, created_at 
              FROM agents 
              WHERE id = ?1"
         )?;
@@ -81,19 +109,52 @@ impl Agent {
         let mut rows = stmt.query(params![id])?;
         
         if let Some(row) = rows.next()? {
-            Ok(Some(Agent {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                api_url: row.get(2)?,
-                auth_token: row.get(3)?,
-                project_id: row.get(4)?,
-                created_at: row.get(5)?,
-            }))
+            Ok(Some(Self::from_row(row)?))
         } else {
             Ok(None)
         }
     }
     
+    fn from_row(row: &Row) -> Result<Agent> {
+        Ok(Agent {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            api_url: row.get(2)?,
+            auth_token: row.get(3)?,
+            project_id: row.get(4)?,
+            history_window: row.get(5)?,
+            connect_timeout_secs: row.get(6)?,
+            read_timeout_secs: row.get(7)?,
+            max_retries: row.get(8)?,
+            created_at: row.get(9)?,
+        })
+    }
+    
+    /// Copy that is safe to hand to the frontend: the token is masked
+    pub fn masked(&self, cipher: &TokenCipher) -> Agent {
+        Agent {
+            auth_token: cipher.mask(&self.auth_token),
+            ..self.clone()
+        }
+    }
+    
+    /// Encrypts tokens saved before encryption at rest existed; returns how many were updated
+    pub fn encrypt_legacy_tokens(conn: &Connection, cipher: &TokenCipher) -> std::result::Result<usize, AppError> {
+        let mut updated = 0;
+        for agent in Self::get_all(conn)? {
+            if is_encrypted(&agent.auth_token) {
+                continue;
+            }
+            let encrypted = cipher.encrypt(&agent.auth_token)?;
+            conn.execute(
+                "UPDATE agents SET auth_token = ?1 WHERE id = ?2",
+                params![encrypted, &agent.id],
+            )?;
+            updated += 1;
+        }
+        Ok(updated)
+    }
+    
     pub fn delete(conn: &Connection, id: &str) -> Result<()> {
         conn.execute("DELETE FROM agents WHERE id = ?1", params![id])?;
         Ok(())
@@ -102,13 +163,18 @@ impl Agent {
     pub fn update(&self, conn: &Connection) -> Result<()> {
         conn.execute(
             "UPDATE agents 
-             SET name = ?1, api_url = ?2, auth_token = ?3, project_id = ?4
-             WHERE id = ?5",
+             SET name = ?1, api_url = ?2, auth_token = ?3, project_id = ?4, history_window = ?5,
+                 connect_timeout_secs = ?6, read_timeout_secs = ?7, max_retries = ?8
+             WHERE id = ?9",
             params![
                 &self.name,
                 &self.api_url,
                 &self.auth_token,
                 &self.project_id,
+                &self.history_window.max(0),
+                &self.connect_timeout_secs,
+                &self.read_timeout_secs,
+                &self.max_retries,
                 &self.id
             ],
         )?;
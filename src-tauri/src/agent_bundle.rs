@@ -0,0 +1,376 @@
+//! Portable bundles of agent definitions, so a team can share one setup.
+//!
+//! Bundles are JSON or TOML. Tokens are left out unless asked for, and are then
+//! written in plain text because the encryption key never leaves this machine.
+//! Importing keeps agent ids, so re-importing a teammate's bundle finds the same
+//! agents again; clashes on id or name are reported until the caller resolves them.
+
+use serde::{Deserialize, Serialize};
+use rusqlite::Connection;
+use chrono::Utc;
+use std::collections::HashMap;
+use uuid::Uuid;
+use crate::agent::Agent;
+use crate::crypto::TokenCipher;
+use crate::error::AppError;
+
+/// Bumped whenever the bundle layout changes incompatibly
+pub const AGENT_BUNDLE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BundleFormat {
+    Json,
+    Toml,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentBundle {
+    pub bundle_version: u32,
+    pub exported_at: i64,
+    pub agents: Vec<BundledAgent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundledAgent {
+    pub id: String,
+    pub name: String,
+    pub api_url: String,
+    pub project_id: String,
+    // Plain text; only present when exported with secrets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_token: Option<String>,
+    #[serde(default = "default_history_window")]
+    pub history_window: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_secs: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_timeout_secs: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<i64>,
+}
+
+fn default_history_window() -> i64 {
+    crate::agent::DEFAULT_HISTORY_WINDOW
+}
+
+/// How to import a bundled agent that clashes with an existing one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictResolution {
+    Skip,
+    // Update the existing agent; its token is kept if the bundle has none
+    Overwrite,
+    // Add the bundled agent as a new one next to the existing agent
+    KeepBoth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictKind {
+    Id,
+    Name,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportAction {
+    Created,
+    Updated,
+    Skipped,
+    // Conflicting and no resolution given; nothing was imported
+    Unresolved,
+    // What a resolved or clash-free agent would have had done to it, when an
+    // unresolved clash kept the import from being applied
+    WouldCreate,
+    WouldUpdate,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentImport {
+    // Id in the bundle, which is also the key for resolutions
+    pub id: String,
+    pub name: String,
+    pub conflict: Option<ConflictKind>,
+    pub existing_id: Option<String>,
+    pub action: ImportAction,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentImportReport {
+    // False when some conflict was left unresolved, in which case nothing changed
+    pub applied: bool,
+    pub agents: Vec<AgentImport>,
+}
+
+/// Serializes the agents with `ids`, or every agent when `ids` is empty
+pub fn export_agents(
+    conn: &Connection,
+    cipher: &TokenCipher,
+    ids: &[String],
+    include_secrets: bool,
+    format: BundleFormat,
+) -> Result<String, AppError> {
+    let agents: Vec<Agent> = Agent::get_all(conn)?
+        .into_iter()
+        .filter(|a| ids.is_empty() || ids.contains(&a.id))
+        .collect();
+    if let Some(missing) = ids.iter().find(|id| !agents.iter().any(|a| &a.id == *id)) {
+        return Err(AppError::not_found(format!("Agent not found: {}", missing)));
+    }
+
+    let agents = agents
+        .into_iter()
+        .map(|agent| {
+            let auth_token = match include_secrets {
+                true => Some(cipher.decrypt(&agent.auth_token)?),
+                false => None,
+            };
+            Ok(BundledAgent {
+                id: agent.id,
+                name: agent.name,
+                api_url: agent.api_url,
+                project_id: agent.project_id,
+                auth_token,
+                history_window: agent.history_window,
+                connect_timeout_secs: agent.connect_timeout_secs,
+                read_timeout_secs: agent.read_timeout_secs,
+                max_retries: agent.max_retries,
+            })
+        })
+        .collect::<Result<Vec<_>, AppError>>()?;
+
+    let bundle = AgentBundle {
+        bundle_version: AGENT_BUNDLE_VERSION,
+        exported_at: Utc::now().timestamp(),
+        agents,
+    };
+    match format {
+        BundleFormat::Json => serde_json::to_string_pretty(&bundle).map_err(|e| AppError::validation(e.to_string())),
+        BundleFormat::Toml => toml::to_string_pretty(&bundle).map_err(|e| AppError::validation(e.to_string())),
+    }
+}
+
+/// Reads a JSON or TOML bundle
+pub fn parse_bundle(text: &str) -> Result<AgentBundle, AppError> {
+    let bundle: AgentBundle = if text.trim_start().starts_with('{') {
+        serde_json::from_str(text).map_err(|e| AppError::validation(format!("Invalid agent bundle: {}", e)))?
+    } else {
+        toml::from_str(text).map_err(|e| AppError::validation(format!("Invalid agent bundle: {}", e)))?
+    };
+    if bundle.bundle_version > AGENT_BUNDLE_VERSION {
+        return Err(AppError::validation(format!(
+            "The bundle was made by a newer version of the app (version {})",
+            bundle.bundle_version
+        )));
+    }
+    Ok(bundle)
+}
+
+/// Adds the bundle's agents, applying `resolutions` (keyed by bundled agent id) to
+/// those that clash with an existing agent. If any clash has no resolution, the
+/// report lists the conflicts and nothing is written.
+pub fn import_agents(
+    conn: &Connection,
+    cipher: &TokenCipher,
+    bundle: AgentBundle,
+    resolutions: &HashMap<String, ConflictResolution>,
+) -> Result<AgentImportReport, AppError> {
+    let tx = conn.unchecked_transaction()?;
+    let mut existing = Agent::get_all(&tx)?;
+    let mut report = AgentImportReport { applied: true, agents: Vec::new() };
+
+    for bundled in bundle.agents {
+        let clash = existing
+            .iter()
+            .find(|a| a.id == bundled.id)
+            .map(|a| (ConflictKind::Id, a.clone()))
+            .or_else(|| existing.iter().find(|a| a.name == bundled.name).map(|a| (ConflictKind::Name, a.clone())));
+        let mut entry = AgentImport {
+            id: bundled.id.clone(),
+            name: bundled.name.clone(),
+            conflict: clash.as_ref().map(|(kind, _)| *kind),
+            existing_id: clash.as_ref().map(|(_, a)| a.id.clone()),
+            action: ImportAction::Created,
+        };
+
+        let has_token = bundled.auth_token.is_some();
+        let token = cipher.encrypt(bundled.auth_token.as_deref().unwrap_or_default())?;
+        let mut agent = Agent {
+            id: bundled.id,
+            name: bundled.name,
+            api_url: bundled.api_url,
+            auth_token: token,
+            project_id: bundled.project_id,
+            history_window: bundled.history_window.max(0),
+            connect_timeout_secs: bundled.connect_timeout_secs,
+            read_timeout_secs: bundled.read_timeout_secs,
+            max_retries: bundled.max_retries,
+            created_at: Utc::now().timestamp(),
+        };
+
+        match (clash, resolutions.get(&entry.id)) {
+            (None, _) => {
+                agent.save(&tx)?;
+                existing.push(agent);
+            }
+            (Some(_), None) => {
+                entry.action = ImportAction::Unresolved;
+                report.applied = false;
+            }
+            (Some(_), Some(ConflictResolution::Skip)) => entry.action = ImportAction::Skipped,
+            (Some((_, current)), Some(ConflictResolution::Overwrite)) => {
+                agent.id = current.id.clone();
+                if !has_token {
+                    agent.auth_token = current.auth_token;
+                }
+                agent.update(&tx)?;
+                if let Some(slot) = existing.iter_mut().find(|a| a.id == agent.id) {
+                    *slot = agent;
+                }
+                entry.action = ImportAction::Updated;
+            }
+            (Some(_), Some(ConflictResolution::KeepBoth)) => {
+                agent.id = Uuid::new_v4().to_string();
+                while existing.iter().any(|a| a.name == agent.name) {
+                    agent.name = format!("{} (imported)", agent.name);
+                }
+                agent.save(&tx)?;
+                existing.push(agent);
+            }
+        }
+        report.agents.push(entry);
+    }
+
+    if report.applied {
+        tx.commit()?;
+    } else {
+        for entry in &mut report.agents {
+            entry.action = match entry.action {
+                ImportAction::Created => ImportAction::WouldCreate,
+                ImportAction::Updated => ImportAction::WouldUpdate,
+                other => other,
+            };
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::CreateAgentInput;
+    use crate::test_support::{agent_input, database_with_cipher};
+
+    fn agent(conn: &Connection, cipher: &TokenCipher, name: &str, token: &str) -> Agent {
+        let mut agent = Agent::new(CreateAgentInput {
+            auth_token: token.to_string(),
+            history_window: Some(4),
+            connect_timeout_secs: Some(5),
+            ..agent_input(name)
+        });
+        agent.auth_token = cipher.encrypt(&agent.auth_token).unwrap();
+        agent.save(conn).unwrap();
+        agent
+    }
+
+    fn token(conn: &Connection, cipher: &TokenCipher, id: &str) -> String {
+        cipher.decrypt(&Agent::get_by_id(conn, id).unwrap().unwrap().auth_token).unwrap()
+    }
+
+    #[test]
+    fn secrets_are_left_out_unless_asked_for() {
+        let (conn, cipher, _dir) = database_with_cipher();
+        agent(&conn, &cipher, "Analyst", "pat_secret");
+
+        let without = export_agents(&conn, &cipher, &[], false, BundleFormat::Json).unwrap();
+        let with = export_agents(&conn, &cipher, &[], true, BundleFormat::Json).unwrap();
+
+        assert!(!without.contains("pat_secret") && !without.contains("auth_token"));
+        assert_eq!(parse_bundle(&with).unwrap().agents[0].auth_token.as_deref(), Some("pat_secret"));
+    }
+
+    #[test]
+    fn toml_bundle_round_trips_into_another_workspace() {
+        let (source, cipher, _dir) = database_with_cipher();
+        let original = agent(&source, &cipher, "Analyst", "pat_secret");
+        agent(&source, &cipher, "Other", "t");
+        let toml = export_agents(&source, &cipher, std::slice::from_ref(&original.id), true, BundleFormat::Toml).unwrap();
+
+        let (target, target_cipher, _target_dir) = database_with_cipher();
+        let report = import_agents(&target, &target_cipher, parse_bundle(&toml).unwrap(), &HashMap::new()).unwrap();
+
+        assert!(report.applied);
+        assert_eq!(report.agents.len(), 1);
+        assert_eq!(report.agents[0].action, ImportAction::Created);
+        let imported = Agent::get_by_id(&target, &original.id).unwrap().unwrap();
+        assert_eq!((imported.history_window, imported.connect_timeout_secs), (4, Some(5)));
+        assert_eq!(token(&target, &target_cipher, &original.id), "pat_secret");
+    }
+
+    #[test]
+    fn conflicts_block_the_import_until_resolved() {
+        let (conn, cipher, _dir) = database_with_cipher();
+        let same_id = agent(&conn, &cipher, "Analyst", "kept");
+        agent(&conn, &cipher, "Writer", "w");
+        let mut bundle = parse_bundle(&export_agents(&conn, &cipher, &[], false, BundleFormat::Json).unwrap()).unwrap();
+        for bundled in &mut bundle.agents {
+            bundled.api_url = "https://changed.example.com".to_string();
+        }
+        let writer = bundle.agents.iter_mut().find(|a| a.name == "Writer").unwrap();
+        writer.id = "new-id".to_string();
+
+        let report = import_agents(&conn, &cipher, bundle.clone(), &HashMap::new()).unwrap();
+        assert!(!report.applied);
+        assert!(report.agents.iter().all(|a| a.action == ImportAction::Unresolved));
+        assert_eq!(report.agents.iter().find(|a| a.id == "new-id").unwrap().conflict, Some(ConflictKind::Name));
+        assert_eq!(Agent::get_all(&conn).unwrap().len(), 2);
+
+        let mut with_new = bundle.clone();
+        let mut fresh = with_new.agents[0].clone();
+        (fresh.id, fresh.name) = ("fresh".to_string(), "Fresh".to_string());
+        with_new.agents.push(fresh);
+        let only_one = HashMap::from([(same_id.id.clone(), ConflictResolution::Overwrite)]);
+        let report = import_agents(&conn, &cipher, with_new, &only_one).unwrap();
+        assert!(!report.applied);
+        let action = |id: &str| report.agents.iter().find(|a| a.id == id).unwrap().action;
+        assert_eq!(action("fresh"), ImportAction::WouldCreate);
+        assert_eq!(action(&same_id.id), ImportAction::WouldUpdate);
+        assert_eq!(action("new-id"), ImportAction::Unresolved);
+        assert_eq!(Agent::get_all(&conn).unwrap().len(), 2);
+
+        let resolutions = HashMap::from([
+            (same_id.id.clone(), ConflictResolution::Overwrite),
+            ("new-id".to_string(), ConflictResolution::KeepBoth),
+        ]);
+        let report = import_agents(&conn, &cipher, bundle, &resolutions).unwrap();
+
+        assert!(report.applied);
+        let updated = Agent::get_by_id(&conn, &same_id.id).unwrap().unwrap();
+        assert_eq!(updated.api_url, "https://changed.example.com");
+        assert_eq!(token(&conn, &cipher, &same_id.id), "kept");
+        let names: Vec<String> = Agent::get_all(&conn).unwrap().into_iter().map(|a| a.name).collect();
+        assert!(names.contains(&"Writer (imported)".to_string()));
+        assert_eq!(names.len(), 3);
+    }
+
+    #[test]
+    fn skip_leaves_existing_agent_alone() {
+        let (conn, cipher, _dir) = database_with_cipher();
+        let existing = agent(&conn, &cipher, "Analyst", "t");
+        let mut bundle = parse_bundle(&export_agents(&conn, &cipher, &[], false, BundleFormat::Json).unwrap()).unwrap();
+        bundle.agents[0].project_id = "changed".to_string();
+
+        let report = import_agents(&conn, &cipher, bundle, &HashMap::from([(existing.id.clone(), ConflictResolution::Skip)])).unwrap();
+
+        assert_eq!(report.agents[0].action, ImportAction::Skipped);
+        assert_eq!(Agent::get_by_id(&conn, &existing.id).unwrap().unwrap().project_id, "p1");
+    }
+
+    #[test]
+    fn rejects_newer_bundles_and_unknown_ids() {
+        let (conn, cipher, _dir) = database_with_cipher();
+        assert!(parse_bundle("bundle_version = 99\nexported_at = 0\nagents = []").is_err());
+        assert!(export_agents(&conn, &cipher, &["missing".to_string()], false, BundleFormat::Json).is_err());
+    }
+}
@@ -0,0 +1,353 @@
+//! Workspace backup and restore through SQLite's online backup API.
+//!
+//! A backup is a consistent copy of the live database taken while the app keeps
+//! running, optionally gzip-compressed. Agent tokens are either left out or kept
+//! encrypted; the key stays in the app data directory, so tokens from a backup
+//! only decrypt on the installation that made it.
+
+use serde::{Deserialize, Serialize};
+use rusqlite::backup::Backup;
+use rusqlite::{params, Connection};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tempfile::TempPath;
+use crate::db::{self, MigrationError, MIGRATIONS};
+
+// Copy in steps so writers on the live database are not blocked for the whole copy
+const PAGES_PER_STEP: i32 = 1024;
+const PAUSE_BETWEEN_STEPS: Duration = Duration::from_millis(5);
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+const SCRATCH_PREFIX: &str = "coze-studio-scratch-";
+
+// Scratch files this old were left behind by a crash
+const STALE_SCRATCH_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BackupOptions {
+    #[serde(default)]
+    pub compress: bool,
+    // Keep the (encrypted) agent tokens instead of blanking them
+    #[serde(default)]
+    pub include_tokens: bool,
+}
+
+/// What a backup contains, or what a restore brought back
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WorkspaceSummary {
+    pub schema_version: i64,
+    pub agents: i64,
+    pub conversations: i64,
+    pub messages: i64,
+}
+
+#[derive(Debug)]
+pub enum BackupError {
+    /// The file is not a database backup of this app
+    NotABackup(String),
+    Migration(MigrationError),
+    Sqlite(rusqlite::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackupError::NotABackup(reason) => write!(f, "Not a workspace backup: {}", reason),
+            BackupError::Migration(e) => write!(f, "{}", e),
+            BackupError::Sqlite(e) => write!(f, "{}", e),
+            BackupError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for BackupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BackupError::Migration(e) => Some(e),
+            BackupError::Sqlite(e) => Some(e),
+            BackupError::Io(e) => Some(e),
+            BackupError::NotABackup(_) => None,
+        }
+    }
+}
+
+impl From<MigrationError> for BackupError {
+    fn from(e: MigrationError) -> Self {
+        BackupError::Migration(e)
+    }
+}
+
+impl From<rusqlite::Error> for BackupError {
+    fn from(e: rusqlite::Error) -> Self {
+        BackupError::Sqlite(e)
+    }
+}
+
+impl From<io::Error> for BackupError {
+    fn from(e: io::Error) -> Self {
+        BackupError::Io(e)
+    }
+}
+
+/// A scratch database file readable only by the current user, next to the live
+/// database (the system temp directory for an in-memory one); removed when dropped
+struct ScratchFile(TempPath);
+
+impl ScratchFile {
+    fn new(live: &Connection) -> io::Result<Self> {
+        let dir = live
+            .path()
+            .filter(|path| !path.is_empty())
+            .and_then(|path| Path::new(path).parent().map(Path::to_path_buf))
+            .unwrap_or_else(std::env::temp_dir);
+        remove_stale_scratch(&dir);
+        let file = tempfile::Builder::new().prefix(SCRATCH_PREFIX).suffix(".db").tempfile_in(&dir)?;
+        Ok(Self(file.into_temp_path()))
+    }
+}
+
+fn remove_stale_scratch(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    let cutoff = SystemTime::now() - STALE_SCRATCH_AGE;
+    for entry in entries.flatten() {
+        let is_scratch = entry.file_name().to_str().is_some_and(|name| name.starts_with(SCRATCH_PREFIX));
+        let is_stale = entry.metadata().and_then(|m| m.modified()).is_ok_and(|modified| modified < cutoff);
+        if is_scratch && is_stale {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Writes a consistent snapshot of `live` to `path`
+pub fn backup(live: &Connection, path: &Path, options: &BackupOptions) -> Result<WorkspaceSummary, BackupError> {
+    let scratch = ScratchFile::new(live)?;
+    let summary = {
+        let mut snapshot = Connection::open(&scratch.0)?;
+        Backup::new(live, &mut snapshot)?.run_to_completion(PAGES_PER_STEP, PAUSE_BETWEEN_STEPS, None)?;
+        if !options.include_tokens {
+            // VACUUM rewrites the file so the old tokens do not linger in free pages
+            snapshot.execute("UPDATE agents SET auth_token = ''", params![])?;
+            snapshot.execute_batch("VACUUM")?;
+        }
+        summarize(&snapshot)?
+    };
+
+    if options.compress {
+        let mut encoder = GzEncoder::new(File::create(path)?, Compression::default());
+        io::copy(&mut File::open(&scratch.0)?, &mut encoder)?;
+        encoder.finish()?;
+    } else {
+        std::fs::copy(&scratch.0, path)?;
+    }
+    Ok(summary)
+}
+
+/// Replaces the contents of `live` with the backup at `path`, compressed or not.
+/// Backups from older versions are migrated first; the live database is left
+/// untouched if the file is not a usable backup.
+pub fn restore(live: &mut Connection, path: &Path) -> Result<WorkspaceSummary, BackupError> {
+    let scratch = ScratchFile::new(live)?;
+    let mut source = File::open(path)?;
+    let mut magic = [0u8; 2];
+    let compressed = source.read(&mut magic)? == 2 && magic == GZIP_MAGIC;
+    let mut source = File::open(path)?;
+    let mut target = File::create(&scratch.0)?;
+    if compressed {
+        io::copy(&mut GzDecoder::new(source), &mut target)?;
+    } else {
+        io::copy(&mut source, &mut target)?;
+    }
+    drop(target);
+
+    let mut restored = Connection::open(&scratch.0)?;
+    validate(&restored)?;
+    db::migrate(&mut restored)?;
+
+    Backup::new(&restored, live)?.run_to_completion(PAGES_PER_STEP, PAUSE_BETWEEN_STEPS, None)?;
+    Ok(summarize(live)?)
+}
+
+fn validate(conn: &Connection) -> Result<(), BackupError> {
+    let not_a_backup = |e: rusqlite::Error| BackupError::NotABackup(e.to_string());
+    let integrity: String = conn
+        .query_row("PRAGMA quick_check", params![], |row| row.get(0))
+        .map_err(not_a_backup)?;
+    if integrity != "ok" {
+        return Err(BackupError::NotABackup(format!("the database is damaged ({})", integrity)));
+    }
+
+    let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() as i64 {
+        return Err(MigrationError::UnsupportedVersion { found: version, supported: MIGRATIONS.len() as i64 }.into());
+    }
+    for table in ["agents", "conversations", "messages"] {
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+            params![table],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Err(BackupError::NotABackup(format!("the `{}` table is missing", table)));
+        }
+    }
+    Ok(())
+}
+
+fn summarize(conn: &Connection) -> rusqlite::Result<WorkspaceSummary> {
+    let count = |table: &str| conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), params![], |row| row.get(0));
+    Ok(WorkspaceSummary {
+        schema_version: conn.pragma_query_value(None, "user_version", |row| row.get(0))?,
+        agents: count("agents")?,
+        conversations: count("conversations")?,
+        messages: count("messages")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{Agent, CreateAgentInput};
+    use crate::chat::{Conversation, Message};
+    use crate::test_support::agent_input;
+
+    fn workspace(dir: &Path, name: &str) -> Connection {
+        let mut conn = Connection::open(dir.join(name)).unwrap();
+        conn.pragma_update(None, "journal_mode", "WAL").unwrap();
+        db::migrate(&mut conn).unwrap();
+        conn
+    }
+
+    fn populate(conn: &Connection) -> Agent {
+        let agent = Agent::new(CreateAgentInput {
+            auth_token: "enc:v1:secret-token".to_string(),
+            ..agent_input("Analyst")
+        });
+        agent.save(conn).unwrap();
+        let conversation = Conversation::new(agent.id.clone(), Some("Weather".to_string()));
+        conversation.save(conn).unwrap();
+        Message::new(conversation.id.clone(), "user".to_string(), "q".to_string(), None)
+            .save(conn)
+            .unwrap();
+        agent
+    }
+
+    fn token(conn: &Connection, agent: &Agent) -> String {
+        Agent::get_by_id(conn, &agent.id).unwrap().unwrap().auth_token
+    }
+
+    #[test]
+    fn compressed_backup_restores_without_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let live = workspace(dir.path(), "live.db");
+        let agent = populate(&live);
+        let path = dir.path().join("backup.db.gz");
+
+        let summary = backup(&live, &path, &BackupOptions { compress: true, include_tokens: false }).unwrap();
+        assert_eq!((summary.agents, summary.conversations, summary.messages), (1, 1, 1));
+        assert_eq!(&std::fs::read(&path).unwrap()[..2], &GZIP_MAGIC);
+
+        let mut other = workspace(dir.path(), "other.db");
+        let restored = restore(&mut other, &path).unwrap();
+        assert_eq!(restored, summary);
+        assert_eq!(token(&other, &agent), "");
+        assert_eq!(Message::get_by_conversation(&other, &Conversation::get_by_agent(&other, &agent.id).unwrap()[0].id).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn tokens_kept_when_asked_and_uncompressed_restore_replaces_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let live = workspace(dir.path(), "live.db");
+        let agent = populate(&live);
+        let path = dir.path().join("backup.db");
+        backup(&live, &path, &BackupOptions { compress: false, include_tokens: true }).unwrap();
+
+        let mut other = workspace(dir.path(), "other.db");
+        populate(&other);
+        populate(&other);
+        let restored = restore(&mut other, &path).unwrap();
+
+        assert_eq!(restored.agents, 1);
+        assert_eq!(token(&other, &agent), "enc:v1:secret-token");
+    }
+
+    #[test]
+    fn scratch_files_are_private_and_swept() {
+        let dir = tempfile::tempdir().unwrap();
+        let live = workspace(dir.path(), "live.db");
+        let stale = dir.path().join(format!("{}crashed.db", SCRATCH_PREFIX));
+        File::create(&stale).unwrap().set_modified(SystemTime::now() - 2 * STALE_SCRATCH_AGE).unwrap();
+
+        let scratch = ScratchFile::new(&live).unwrap();
+        assert_eq!(scratch.0.parent(), Some(dir.path()));
+        assert!(!stale.exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&scratch.0).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        let path = scratch.0.to_path_buf();
+        drop(scratch);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn refuses_newer_schema_and_leaves_live_database_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let newer = workspace(dir.path(), "newer.db");
+        newer.pragma_update(None, "user_version", MIGRATIONS.len() as i64 + 1).unwrap();
+        drop(newer);
+
+        let mut live = workspace(dir.path(), "live.db");
+        populate(&live);
+        let err = restore(&mut live, &dir.path().join("newer.db")).unwrap_err();
+
+        assert!(matches!(err, BackupError::Migration(MigrationError::UnsupportedVersion { .. })));
+        assert_eq!(summarize(&live).unwrap().agents, 1);
+    }
+
+    #[test]
+    fn refuses_files_that_are_not_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = dir.path().join("notes.txt");
+        std::fs::write(&text, "not a database, just some text that is long enough").unwrap();
+        let empty = dir.path().join("empty.db");
+        Connection::open(&empty).unwrap().execute_batch("CREATE TABLE t (x)").unwrap();
+
+        let mut live = workspace(dir.path(), "live.db");
+        assert!(matches!(restore(&mut live, &text), Err(BackupError::NotABackup(_))));
+        assert!(matches!(restore(&mut live, &empty), Err(BackupError::NotABackup(_))));
+    }
+
+    #[test]
+    fn older_backup_is_migrated_on_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let old_path = dir.path().join("old.db");
+        let old = Connection::open(&old_path).unwrap();
+        old.execute_batch(
+            "CREATE TABLE agents (id TEXT PRIMARY KEY, name TEXT NOT NULL, api_url TEXT NOT NULL,
+                 auth_token TEXT NOT NULL, project_id TEXT NOT NULL, created_at INTEGER NOT NULL);
+             CREATE TABLE conversations (id TEXT PRIMARY KEY, agent_id TEXT NOT NULL, title TEXT,
+                 created_at INTEGER NOT NULL);
+             CREATE TABLE messages (id TEXT PRIMARY KEY, conversation_id TEXT NOT NULL, role TEXT NOT NULL,
+                 content TEXT NOT NULL, created_at INTEGER NOT NULL);
+             INSERT INTO agents VALUES ('a1', 'Old', 'https://example.com', '', 'p', 0);",
+        )
+        .unwrap();
+        drop(old);
+
+        let mut live = workspace(dir.path(), "live.db");
+        let restored = restore(&mut live, &old_path).unwrap();
+
+        assert_eq!(restored.schema_version, MIGRATIONS.len() as i64);
+        assert_eq!(Agent::get_by_id(&live, "a1").unwrap().unwrap().history_window, 10);
+    }
+}
@@ -1,20 +1,209 @@
 use serde::{Deserialize, Serialize};
-use rusqlite::{params, Connection, Result as SqlResult};
+use rusqlite::{params, Connection, OptionalExtension, Result as SqlResult, Row};
 use uuid::Uuid;
 use chrono::Utc;
-use reqwest::Client;
+use reqwest::{Client, RequestBuilder};
 use futures::StreamExt;
-use std::error::Error;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use tauri::{AppHandle, Emitter};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use crate::db::DbPool;
+use crate::error::AppError;
+use crate::http::{send_with_retry, RequestPolicy, RetryNotice, SendOutcome};
+use crate::coze::{self, ReplyAccumulator, ReplyUpdate, TokenUsage};
+use crate::sse::SseDecoder;
 
 // Event payload for streaming responses
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StreamEvent {
-    pub event_type: String, // "content", "tool_call", "tool_result", "done", "error"
+    pub event_type: String, // "content", "tool_call", "tool_result", "retrying", "done", "error", "cancelled"
+    pub conversation_id: String,
+    pub message_id: String, // id the assistant message is saved under
     pub content: Option<String>,
     pub tool_call: Option<ToolCall>,
     pub full_content: Option<String>,
     pub tool_calls: Option<Vec<ToolCall>>,
+    // Set on "retrying": which retry is coming up, out of how many, and after what delay
+    pub attempt: Option<u32>,
+    pub max_retries: Option<u32>,
+    pub retry_in_ms: Option<u64>,
+}
+
+/// Destination of stream events; the app window in production, a recorder in tests
+pub trait StreamSink: Send + Sync {
+    fn emit(&self, event: StreamEvent);
+}
+
+impl StreamSink for AppHandle {
+    fn emit(&self, event: StreamEvent) {
+        let _ = Emitter::emit(self, &StreamEmitter::event_name(&event.conversation_id), event);
+    }
+}
+
+/// How often a streaming reply is written to its draft row
+pub const DRAFT_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Passes stream events on to `inner` while keeping the reply's draft row up to
+/// date, so a crash or a dropped connection loses at most one flush interval
+pub struct DraftSink<'a> {
+    inner: &'a dyn StreamSink,
+    db: DbPool,
+    message_id: String,
+    interval: Duration,
+    draft: Mutex<Draft>,
+    // Draft writes run on the blocking pool, since a busy database can hold them
+    // for the whole busy timeout; one at a time, so an older draft never lands last
+    pending: Mutex<Option<JoinHandle<()>>>,
+}
+
+// The reply as received so far
+struct Draft {
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    flushed_at: Instant,
+    dirty: bool,
+}
+
+impl<'a> DraftSink<'a> {
+    pub fn new(inner: &'a dyn StreamSink, db: &'a DbPool, message_id: &str, interval: Duration) -> Self {
+        Self {
+            inner,
+            db: db.clone(),
+            message_id: message_id.to_string(),
+            interval,
+            draft: Mutex::new(Draft {
+                content: String::new(),
+                tool_calls: Vec::new(),
+                flushed_at: Instant::now(),
+                dirty: false,
+            }),
+            pending: Mutex::new(None),
+        }
+    }
+    
+    /// Waits for the draft write in flight, then saves whatever it did not include
+    pub async fn flush(&self) {
+        let pending = self.pending.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(write) = pending {
+            let _ = write.await;
+        }
+        let write = {
+            let mut draft = self.draft.lock().unwrap_or_else(|e| e.into_inner());
+            if !draft.dirty {
+                return;
+            }
+            draft.dirty = false;
+            self.spawn_write(&draft)
+        };
+        let _ = write.await;
+    }
+    
+    /// The content and tool calls received so far
+    pub fn snapshot(&self) -> (String, Option<Vec<ToolCall>>) {
+        let draft = self.draft.lock().unwrap_or_else(|e| e.into_inner());
+        let tool_calls = (!draft.tool_calls.is_empty()).then(|| draft.tool_calls.clone());
+        (draft.content.clone(), tool_calls)
+    }
+    
+    fn record(&self, event: &StreamEvent) {
+        let mut draft = self.draft.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(content) = &event.full_content {
+            if *content != draft.content {
+                draft.content = content.clone();
+                draft.dirty = true;
+            }
+        }
+        if let Some(tool_calls) = &event.tool_calls {
+            draft.tool_calls = tool_calls.clone();
+            draft.dirty = true;
+        } else if let Some(tool_call) = &event.tool_call {
+            match draft.tool_calls.iter_mut().find(|tc| tc.id == tool_call.id) {
+                Some(existing) => *existing = tool_call.clone(),
+                None => draft.tool_calls.push(tool_call.clone()),
+            }
+            draft.dirty = true;
+        }
+        
+        if draft.dirty && draft.flushed_at.elapsed() >= self.interval {
+            // Still writing the last one; this draft goes out with a later event or `flush`
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            if pending.as_ref().is_none_or(JoinHandle::is_finished) {
+                *pending = Some(self.spawn_write(&draft));
+                draft.dirty = false;
+                draft.flushed_at = Instant::now();
+            }
+        }
+    }
+    
+    fn spawn_write(&self, draft: &Draft) -> JoinHandle<()> {
+        let db = self.db.clone();
+        let message_id = self.message_id.clone();
+        let content = draft.content.clone();
+        let tool_calls = (!draft.tool_calls.is_empty()).then(|| draft.tool_calls.clone());
+        tokio::task::spawn_blocking(move || {
+            let written = db
+                .get()
+                .map_err(|e| e.to_string())
+                .and_then(|conn| {
+                    Message::update_draft(&conn, &message_id, &content, tool_calls.as_deref()).map_err(|e| e.to_string())
+                });
+            if let Err(e) = written {
+                tracing::warn!(message_id = %message_id, error = %e, "could not save draft");
+            }
+        })
+    }
+}
+
+impl StreamSink for DraftSink<'_> {
+    fn emit(&self, event: StreamEvent) {
+        self.record(&event);
+        self.inner.emit(event);
+    }
+}
+
+/// Emits the stream events of one assistant reply on its conversation's channel
+pub struct StreamEmitter<'a> {
+    sink: &'a dyn StreamSink,
+    conversation_id: String,
+    message_id: String,
+}
+
+impl<'a> StreamEmitter<'a> {
+    pub fn new(sink: &'a dyn StreamSink, conversation_id: &str, message_id: &str) -> Self {
+        Self {
+            sink,
+            conversation_id: conversation_id.to_string(),
+            message_id: message_id.to_string(),
+        }
+    }
+    
+    /// Event name the frontend listens on for a conversation, e.g. `chat-stream:<id>`
+    pub fn event_name(conversation_id: &str) -> String {
+        format!("chat-stream:{}", conversation_id)
+    }
+    
+    /// An event of `event_type` addressed to this reply, with no payload yet
+    pub fn event(&self, event_type: &str) -> StreamEvent {
+        StreamEvent {
+            event_type: event_type.to_string(),
+            conversation_id: self.conversation_id.clone(),
+            message_id: self.message_id.clone(),
+            content: None,
+            tool_call: None,
+            full_content: None,
+            tool_calls: None,
+            attempt: None,
+            max_retries: None,
+            retry_in_ms: None,
+        }
+    }
+    
+    pub fn emit(&self, event: StreamEvent) {
+        self.sink.emit(event);
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,10 +213,29 @@ pub struct Message {
     pub role: String, // "user" or "assistant"
     pub content: String,
     pub tool_calls: Option<Vec<ToolCall>>,
-    pub created_at: i64,
+    pub status: String, // "complete", "interrupted", "error", or "streaming" while a draft
+    pub created_at: i64, // milliseconds since the epoch
+    // Position in the conversation, counting from 1 in the order messages were saved; read-only
+    #[serde(default)]
+    pub seq: i64,
+    // The message this one follows; replies to the same message are versions of one turn
+    pub parent_id: Option<String>,
+    pub version: i64, // 1-based among siblings
+    pub version_count: i64, // number of siblings, this one included; read-only
 }
 
+/// One path from the first message of a conversation to a leaf
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Branch {
+    pub leaf_id: String,
+    pub message_count: i64,
+    // Start of the latest user message on the path, to tell branches apart
+    pub last_user_message: Option<String>,
+    pub updated_at: i64,
+    pub active: bool, // the path currently shown
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ToolCall {
     pub id: String,
     pub tool_name: String,
@@ -41,7 +249,10 @@ pub struct Conversation {
     pub id: String,
     pub agent_id: String,
     pub title: Option<String>,
-    pub created_at: i64,
+    pub created_at: i64, // milliseconds since the epoch
+    // Set on forks: the conversation and last message they were copied from
+    pub forked_from_conversation_id: Option<String>,
+    pub forked_from_message_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -60,6 +271,15 @@ pub struct CozeContent {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CozeQuery {
     pub prompt: Vec<CozePrompt>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<CozeHistoryMessage>,
+}
+
+/// An earlier turn of the conversation sent along with the prompt
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CozeHistoryMessage {
+    pub role: String,
+    pub content: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -74,6 +294,79 @@ pub struct CozePromptContent {
     pub text: String,
 }
 
+impl CozeRequest {
+    /// Builds a query for `message`, carrying `history` (oldest first) as prior turns.
+    /// No session id is sent: the history already is the context, and a server-side
+    /// session would mix in turns from other branches. Unfinished replies are skipped.
+    pub fn new(project_id: &str, history: &[Message], message: &str) -> Self {
+        Self {
+            content: CozeContent {
+                query: CozeQuery {
+                    prompt: vec![CozePrompt {
+                        prompt_type: "text".to_string(),
+                        content: CozePromptContent {
+                            text: message.to_string(),
+                        },
+                    }],
+                    history: history
+                        .iter()
+                        .filter(|m| m.status == "complete" && !m.content.is_empty())
+                        .map(|m| CozeHistoryMessage {
+                            role: m.role.clone(),
+                            content: m.content.clone(),
+                        })
+                        .collect(),
+                },
+            },
+            request_type: "query".to_string(),
+            project_id: project_id.to_string(),
+        }
+    }
+}
+
+/// What a streamed Coze request produced before it finished or was stopped
+#[derive(Debug, Clone)]
+pub struct StreamOutcome {
+    pub content: String,
+    pub title: Option<String>,
+    pub tool_calls: Option<Vec<ToolCall>>,
+    pub cancelled: bool,
+    pub stats: ReplyStats,
+}
+
+/// How long a streamed reply took and how much it carried
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplyStats {
+    pub started_at: i64, // milliseconds since epoch, before the first attempt was sent
+    pub first_token_ms: Option<i64>, // until the first answer text arrived
+    pub duration_ms: i64,
+    pub response_bytes: i64,
+    pub usage: Option<TokenUsage>,
+}
+
+impl ReplyStats {
+    fn start() -> (Self, Instant) {
+        (ReplyStats { started_at: Utc::now().timestamp_millis(), ..Default::default() }, Instant::now())
+    }
+}
+
+fn elapsed_ms(since: Instant) -> i64 {
+    since.elapsed().as_millis() as i64
+}
+
+
+fn tool_calls_json(tool_calls: Option<&[ToolCall]>) -> Option<String> {
+    tool_calls.map(|tc| serde_json::to_string(tc).unwrap_or_default())
+}
+
+// Characters of the latest user message shown for a branch
+const BRANCH_PREVIEW_CHARS: usize = 80;
+
+// Columns read by `Message::from_row`, for queries aliasing messages as `m`
+const MESSAGE_COLUMNS: &str = "m.id, m.conversation_id, m.role, m.content, m.tool_calls, m.status, m.created_at,
+    m.parent_id, m.version,
+    (SELECT COUNT(*) FROM messages s WHERE s.conversation_id = m.conversation_id AND s.parent_id IS m.parent_id),
+    m.seq";
 
 impl Message {
     pub fn new(conversation_id: String, role: String, content: String, tool_calls: Option<Vec<ToolCall>>) -> Self {
@@ -83,94 +376,385 @@ impl Message {
             role,
             content,
             tool_calls,
-            created_at: Utc::now().timestamp(),
+            status: "complete".to_string(),
+            created_at: Utc::now().timestamp_millis(),
+            seq: 0,
+            parent_id: None,
+            version: 1,
+            version_count: 1,
         }
     }
     
-    pub fn save(&self, conn: &Connection) -> SqlResult<()> {
-        let tool_calls_json = self.tool_calls.as_ref().map(|tc| serde_json::to_string(tc).unwrap_or_default());
-        conn.execute(
-            "INSERT INTO messages (id, conversation_id, role, content, tool_calls, created_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    /// Appends the message to the conversation's current path
+    pub fn save(&mut self, conn: &Connection) -> SqlResult<()> {
+        let leaf = Conversation::active_message_id(conn, &self.conversation_id)?;
+        self.save_under(conn, leaf)
+    }
+    
+    /// Stores the message as a child of `parent_id` (a root when `None`) and makes it
+    /// the end of the conversation's current path
+    pub fn save_under(&mut self, conn: &Connection, parent_id: Option<String>) -> SqlResult<()> {
+        let tool_calls_json = tool_calls_json(self.tool_calls.as_deref());
+        let version: i64 = conn.query_row(
+            "SELECT COALESCE(MAX(version), 0) + 1 FROM messages WHERE conversation_id = ?1 AND parent_id IS ?2",
+            params![&self.conversation_id, &parent_id],
+            |row| row.get(0),
+        )?;
+        // The sequence number is picked inside the insert, so concurrent writers cannot share one
+        let seq: i64 = conn.query_row(
+            "INSERT INTO messages (id, conversation_id, role, content, tool_calls, status, created_at, parent_id, version, seq)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9,
+                     (SELECT COALESCE(MAX(seq), 0) + 1 FROM messages WHERE conversation_id = ?2))
+             RETURNING seq",
             params![
                 &self.id,
                 &self.conversation_id,
                 &self.role,
                 &self.content,
                 &tool_calls_json,
-                &self.created_at
+                &self.status,
+                &self.created_at,
+                &parent_id,
+                version
             ],
+            |row| row.get(0),
         )?;
+        Conversation::set_active_message(conn, &self.conversation_id, &self.id)?;
+        
+        self.parent_id = parent_id;
+        self.seq = seq;
+        self.version = version;
+        self.version_count = version;
         Ok(())
     }
     
+    /// Rewrites a streaming draft with what has arrived so far; finished messages are left alone
+    pub fn update_draft(conn: &Connection, id: &str, content: &str, tool_calls: Option<&[ToolCall]>) -> SqlResult<()> {
+        conn.execute(
+            "UPDATE messages SET content = ?2, tool_calls = ?3 WHERE id = ?1 AND status = 'streaming'",
+            params![id, content, tool_calls_json(tool_calls)],
+        )?;
+        Ok(())
+    }
+    
+    /// Stores a reply's final content and its status ("complete", "interrupted" or "error")
+    pub fn finish(conn: &Connection, id: &str, content: &str, tool_calls: Option<&[ToolCall]>, status: &str) -> SqlResult<()> {
+        conn.execute(
+            "UPDATE messages SET content = ?2, tool_calls = ?3, status = ?4 WHERE id = ?1",
+            params![id, content, tool_calls_json(tool_calls), status],
+        )?;
+        Ok(())
+    }
+    
+    /// Deletes a draft that never received anything; its parent becomes the end of
+    /// the current path again if the draft was
+    pub fn discard_draft(conn: &Connection, id: &str) -> SqlResult<()> {
+        let tx = conn.unchecked_transaction()?;
+        let parent_id: Option<Option<String>> = tx
+            .query_row(
+                "SELECT parent_id FROM messages WHERE id = ?1 AND status = 'streaming'",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(parent_id) = parent_id else {
+            return Ok(());
+        };
+        tx.execute(
+            "UPDATE conversations SET active_message_id = ?2 WHERE active_message_id = ?1",
+            params![id, parent_id],
+        )?;
+        tx.execute("DELETE FROM messages WHERE id = ?1", params![id])?;
+        tx.commit()
+    }
+    
+    /// Marks drafts left behind by a run that ended mid-stream as interrupted.
+    /// Only safe while nothing is streaming, i.e. at startup. Returns how many there were.
+    pub fn recover_drafts(conn: &Connection) -> SqlResult<usize> {
+        conn.execute("UPDATE messages SET status = 'interrupted' WHERE status = 'streaming'", [])
+    }
+    
+    /// The messages on the conversation's current path, oldest first
     pub fn get_by_conversation(conn: &Connection, conversation_id: &str) -> SqlResult<Vec<Message>> {
+        Self::active_path(conn, conversation_id, -1)
+    }
+    
+    /// The last `limit` messages of the conversation's current path, oldest first
+    pub fn get_recent(conn: &Connection, conversation_id: &str, limit: i64) -> SqlResult<Vec<Message>> {
+        Self::active_path(conn, conversation_id, limit.max(0))
+    }
+    
+    fn active_path(conn: &Connection, conversation_id: &str, limit: i64) -> SqlResult<Vec<Message>> {
+        match Conversation::active_message_id(conn, conversation_id)? {
+            Some(leaf_id) => Self::get_path(conn, &leaf_id, limit),
+            None => Ok(Vec::new()),
+        }
+    }
+    
+    /// `leaf_id` and the messages leading up to it, oldest first; at most `limit`
+    /// of them, or all when `limit` is negative
+    pub fn get_path(conn: &Connection, leaf_id: &str, limit: i64) -> SqlResult<Vec<Message>> {
+        let mut stmt = conn.prepare(&format!(
+            "WITH RECURSIVE path(id, depth) AS (
+                 SELECT id, 0 FROM messages WHERE id = ?1 AND ?2 != 0
+                 UNION ALL
+                 SELECT m.parent_id, path.depth + 1 FROM messages m
+                 JOIN path ON m.id = path.id
+                 WHERE m.parent_id IS NOT NULL AND (?2 < 0 OR path.depth + 1 < ?2)
+             )
+             SELECT {} FROM path JOIN messages m ON m.id = path.id
+             ORDER BY path.depth DESC",
+            MESSAGE_COLUMNS
+        ))?;
+        
+        let messages = stmt.query_map(params![leaf_id, limit], Self::from_row)?
+            .collect::<SqlResult<Vec<_>, _>>()?;
+        
+        Ok(messages)
+    }
+    
+    /// Every message of the conversation, all branches included, oldest first
+    pub fn get_all(conn: &Connection, conversation_id: &str) -> SqlResult<Vec<Message>> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM messages m WHERE m.conversation_id = ?1 ORDER BY m.seq",
+            MESSAGE_COLUMNS
+        ))?;
+        
+        let messages = stmt.query_map(params![conversation_id], Self::from_row)?
+            .collect::<SqlResult<Vec<_>, _>>()?;
+        
+        Ok(messages)
+    }
+    
+    pub fn get_by_id(conn: &Connection, id: &str) -> SqlResult<Option<Message>> {
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM messages m WHERE m.id = ?1", MESSAGE_COLUMNS))?;
+        let mut rows = stmt.query_map(params![id], Self::from_row)?;
+        rows.next().transpose()
+    }
+    
+    /// Shows `version` of the turn `message_id` belongs to, following that version's
+    /// most recent replies. Returns the conversation id, or `None` if there is no such version.
+    pub fn select_version(conn: &Connection, message_id: &str, version: i64) -> SqlResult<Option<String>> {
+        let target: Option<(String, String)> = conn
+            .query_row(
+                "SELECT s.id, s.conversation_id FROM messages m
+                 JOIN messages s ON s.conversation_id = m.conversation_id AND s.parent_id IS m.parent_id
+                 WHERE m.id = ?1 AND s.version = ?2",
+                params![message_id, version],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let Some((mut leaf, conversation_id)) = target else {
+            return Ok(None);
+        };
+        
+        while let Some(child) = conn
+            .query_row(
+                "SELECT id FROM messages WHERE parent_id = ?1 ORDER BY seq DESC LIMIT 1",
+                params![&leaf],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?
+        {
+            leaf = child;
+        }
+        
+        Conversation::set_active_message(conn, &conversation_id, &leaf)?;
+        Ok(Some(conversation_id))
+    }
+    
+    /// Every path through the conversation, one per leaf, most recently extended first
+    pub fn get_branches(conn: &Connection, conversation_id: &str) -> SqlResult<Vec<Branch>> {
+        let active = Conversation::active_message_id(conn, conversation_id)?;
         let mut stmt = conn.prepare(
-            "SELECT id, conversation_id, role, content, tool_calls, created_at 
-             FROM messages 
-             WHERE conversation_id = ?1 
-             ORDER BY created_at ASC"
+            "SELECT id, parent_id, role, content, created_at FROM messages
+             WHERE conversation_id = ?1
+             ORDER BY seq"
         )?;
-        
-        let messages = stmt.query_map(params![conversation_id], |row| {
-            let tool_calls_json: Option<String> = row.get(4)?;
-            let tool_calls = tool_calls_json.and_then(|json| serde_json::from_str(&json).ok());
-            Ok(Message {
-                id: row.get(0)?,
-                conversation_id: row.get(1)?,
-                role: row.get(2)?,
-                content: row.get(3)?,
-                tool_calls,
-                created_at: row.get(5)?,
-            })
+        let nodes = stmt.query_map(params![conversation_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, i64>(4)?,
+            ))
         })?
-        .collect::<SqlResult<Vec<_>, _>>()?;
+        .collect::<SqlResult<Vec<_>>>()?;
         
-        Ok(messages)
+        let by_id: HashMap<&str, usize> = nodes.iter().enumerate().map(|(i, n)| (n.0.as_str(), i)).collect();
+        let parents: HashSet<&str> = nodes.iter().filter_map(|n| n.1.as_deref()).collect();
+        
+        let mut branches: Vec<Branch> = nodes
+            .iter()
+            .filter(|leaf| !parents.contains(leaf.0.as_str()))
+            .map(|leaf| {
+                let mut message_count = 0;
+                let mut last_user_message = None;
+                let mut node = Some(leaf);
+                while let Some((_, parent_id, role, content, _)) = node {
+                    message_count += 1;
+                    if last_user_message.is_none() && role == "user" {
+                        last_user_message = Some(content.chars().take(BRANCH_PREVIEW_CHARS).collect());
+                    }
+                    node = parent_id.as_deref().and_then(|p| by_id.get(p)).map(|&i| &nodes[i]);
+                }
+                Branch {
+                    leaf_id: leaf.0.clone(),
+                    message_count,
+                    last_user_message,
+                    updated_at: leaf.4,
+                    active: active.as_deref() == Some(leaf.0.as_str()),
+                }
+            })
+            .collect();
+        // Leaves were read in sequence order, so the most recently extended path is last
+        branches.reverse();
+        Ok(branches)
+    }
+    
+    /// Makes the path ending at `message_id` the one shown; `false` if the message is
+    /// not part of the conversation
+    pub fn select_branch(conn: &Connection, conversation_id: &str, message_id: &str) -> SqlResult<bool> {
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM messages WHERE id = ?1 AND conversation_id = ?2)",
+            params![message_id, conversation_id],
+            |row| row.get(0),
+        )?;
+        if exists {
+            Conversation::set_active_message(conn, conversation_id, message_id)?;
+        }
+        Ok(exists)
+    }
+    
+    fn from_row(row: &Row) -> SqlResult<Message> {
+        let tool_calls_json: Option<String> = row.get(4)?;
+        let tool_calls = tool_calls_json.and_then(|json| serde_json::from_str(&json).ok());
+        Ok(Message {
+            id: row.get(0)?,
+            conversation_id: row.get(1)?,
+            role: row.get(2)?,
+            content: row.get(3)?,
+            tool_calls,
+            status: row.get(5)?,
+            created_at: row.get(6)?,
+            parent_id: row.get(7)?,
+            version: row.get(8)?,
+            version_count: row.get(9)?,
+            seq: row.get(10)?,
+        })
     }
 }
 
+// Columns read by `Conversation::from_row`
+const CONVERSATION_COLUMNS: &str = "id, agent_id, title, created_at, forked_from_conversation_id, forked_from_message_id";
+
 impl Conversation {
     pub fn new(agent_id: String, title: Option<String>) -> Self {
         Self {
             id: Uuid::new_v4().to_string(),
             agent_id,
             title,
-            created_at: Utc::now().timestamp(),
+            created_at: Utc::now().timestamp_millis(),
+            forked_from_conversation_id: None,
+            forked_from_message_id: None,
         }
     }
     
     pub fn save(&self, conn: &Connection) -> SqlResult<()> {
         conn.execute(
-            "INSERT INTO conversations (id, agent_id, title, created_at)
-             VALUES (?1, ?2, ?3, ?4)",
+            "INSERT INTO conversations (id, agent_id, title, created_at, forked_from_conversation_id, forked_from_message_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
             params![
                 &self.id,
                 &self.agent_id,
                 &self.title,
-                &self.created_at
+                &self.created_at,
+                &self.forked_from_conversation_id,
+                &self.forked_from_message_id
             ],
         )?;
         Ok(())
     }
     
+    pub fn get_by_id(conn: &Connection, id: &str) -> SqlResult<Option<Conversation>> {
+        conn.query_row(
+            &format!("SELECT {} FROM conversations WHERE id = ?1", CONVERSATION_COLUMNS),
+            params![id],
+            Self::from_row,
+        )
+        .optional()
+    }
+    
+    /// Copies `up_to_message_id` and the messages leading up to it, tool calls included,
+    /// into a new conversation, under `agent_id` or else the original agent. Other
+    /// branches are left behind. Returns `None` if the message is not in the conversation.
+    pub fn fork(
+        conn: &Connection,
+        conversation_id: &str,
+        up_to_message_id: &str,
+        agent_id: Option<&str>,
+    ) -> SqlResult<Option<Conversation>> {
+        let Some(source) = Self::get_by_id(conn, conversation_id)? else {
+            return Ok(None);
+        };
+        let path = Message::get_path(conn, up_to_message_id, -1)?;
+        if path.last().map(|m| m.conversation_id.as_str()) != Some(conversation_id) {
+            return Ok(None);
+        }
+        
+        let tx = conn.unchecked_transaction()?;
+        let mut fork = Conversation::new(agent_id.unwrap_or(&source.agent_id).to_string(), source.title);
+        fork.forked_from_conversation_id = Some(source.id);
+        fork.forked_from_message_id = Some(up_to_message_id.to_string());
+        fork.save(&tx)?;
+        
+        let mut parent_id = None;
+        for original in path {
+            let mut copy = Message {
+                id: Uuid::new_v4().to_string(),
+                conversation_id: fork.id.clone(),
+                ..original
+            };
+            // A reply still streaming in the source stops growing in the fork
+            if copy.status == "streaming" {
+                copy.status = "interrupted".to_string();
+            }
+            copy.save_under(&tx, parent_id)?;
+            parent_id = Some(copy.id);
+        }
+        tx.commit()?;
+        
+        Ok(Some(fork))
+    }
+    
+    /// Last message of the path currently shown, if the conversation has any
+    pub fn active_message_id(conn: &Connection, id: &str) -> SqlResult<Option<String>> {
+        let active: Option<Option<String>> = conn
+            .query_row("SELECT active_message_id FROM conversations WHERE id = ?1", params![id], |row| row.get(0))
+            .optional()?;
+        Ok(active.flatten())
+    }
+    
+    pub fn set_active_message(conn: &Connection, id: &str, message_id: &str) -> SqlResult<()> {
+        conn.execute(
+            "UPDATE conversations SET active_message_id = ?1 WHERE id = ?2",
+            params![message_id, id],
+        )?;
+        Ok(())
+    }
+    
     pub fn get_by_agent(conn: &Connection, agent_id: &str) -> SqlResult<Vec<Conversation>> {
-        let mut stmt = conn.prepare(
-            "SELECT id, agent_id, title, created_at 
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} 
              FROM conversations 
              WHERE agent_id = ?1 
-             ORDER BY created_at DESC"
-        )?;
+             ORDER BY created_at DESC",
+            CONVERSATION_COLUMNS
+        ))?;
         
-        let conversations = stmt.query_map(params![agent_id], |row| {
-            Ok(Conversation {
-                id: row.get(0)?,
-                agent_id: row.get(1)?,
-                title: row.get(2)?,
-                created_at: row.get(3)?,
-            })
-        })?
-        .collect::<SqlResult<Vec<_>, _>>()?;
+        let conversations = stmt.query_map(params![agent_id], Self::from_row)?
+            .collect::<SqlResult<Vec<_>, _>>()?;
         
         Ok(conversations)
     }
@@ -192,380 +776,493 @@ impl Conversation {
         )?;
         Ok(())
     }
+    
+    fn from_row(row: &Row) -> SqlResult<Conversation> {
+        Ok(Conversation {
+            id: row.get(0)?,
+            agent_id: row.get(1)?,
+            title: row.get(2)?,
+            created_at: row.get(3)?,
+            forked_from_conversation_id: row.get(4)?,
+            forked_from_message_id: row.get(5)?,
+        })
+    }
 }
 
-#[allow(dead_code)]
+fn stream_interrupted(e: reqwest::Error) -> AppError {
+    AppError::Network(format!("Stream interrupted: {}", e))
+}
+
+fn coze_post(client: &Client, api_url: &str, auth_token: &str, request: &CozeRequest) -> RequestBuilder {
+    client
+        .post(api_url)
+        .header("Authorization", format!("Bearer {}", auth_token))
+        .header("Content-Type", "application/json")
+        .json(request)
+}
+
+fn log_retry(notice: &RetryNotice) {
+    tracing::warn!(
+        attempt = notice.attempt,
+        max_retries = notice.max_retries,
+        delay_ms = notice.delay.as_millis() as u64,
+        reason = %notice.reason,
+        "retrying request"
+    );
+}
+
+/// Blocking version: reads the whole reply before returning it. Retries and
+/// decodes like the streaming version, but reports no progress and cannot be stopped.
 pub async fn send_message_to_coze(
+    client: &Client,
+    policy: &RequestPolicy,
     api_url: &str,
     auth_token: &str,
-    project_id: &str,
-    message: &str,
-) -> Result<(String, Option<String>, Option<Vec<ToolCall>>), Box<dyn Error>> {
-    let client = Client::new();
-    
-    let request = CozeRequest {
-        content: CozeContent {
-            query: CozeQuery {
-                prompt: vec![CozePrompt {
-                    prompt_type: "text".to_string(),
-                    content: CozePromptContent {
-                        text: message.to_string(),
-                    },
-                }],
-            },
-        },
-        request_type: "query".to_string(),
-        project_id: project_id.to_string(),
+    request: &CozeRequest,
+) -> Result<StreamOutcome, AppError> {
+    let (mut stats, started) = ReplyStats::start();
+    let build_request = || coze_post(client, api_url, auth_token, request);
+    let response = match send_with_retry(build_request, policy, &CancellationToken::new(), log_retry).await? {
+        SendOutcome::Response(response) => response,
+        SendOutcome::Cancelled => return Err(AppError::Cancelled("Stopped before the agent answered".to_string())),
     };
-    
-    let response = client
-        .post(api_url)
-        .header("Authorization", format!("Bearer {}", auth_token))
-        .header("Content-Type", "application/json")
-        .json(&request)
-        .send()
-        .await?;
-    
     if !response.status().is_success() {
-        return Err(format!("API request failed: {}", response.status()).into());
+        return Err(AppError::from_response(response).await);
     }
     
-    let mut stream = response.bytes_stream();
-    let mut full_response = String::new();
-    let mut title = None;
-    let mut tool_calls: Vec<ToolCall> = Vec::new();
-    
-    let mut buffer = String::new();
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk?;
-        let text = String::from_utf8_lossy(&chunk);
-        buffer.push_str(&text);
-        
-        while let Some(pos) = buffer.find('\n') {
-            let line = buffer[..pos].to_string();
-            let remaining = buffer[pos + 1..].to_string();
-            buffer = remaining;
-            
-            let line = line.trim();
-            if line.starts_with("data: ") {
-                let json_str = &line[6..]; // Remove "data: " prefix
-                if let Ok(v) = serde_json::from_str::<serde_json::Value>(json_str) {
-                    // Try to extract answer from various places
-                    if let Some(answer) = v.get("answer").and_then(|a| a.as_str()) {
-                        full_response.push_str(answer);
-                    } else if let Some(content) = v.get("content") {
-                        if let Some(answer) = content.get("answer").and_then(|a| a.as_str()) {
-                            full_response.push_str(answer);
-                        } else if let Some(answer) = content.as_str() {
-                            // If the whole content is a string and no other specific fields match, 
-                            // check message type to decide if it's answer text
-                            if v.get("type").and_then(|t| t.as_str()) == Some("answer") || 
-                               v.get("role").and_then(|r| r.as_str()) == Some("assistant") {
-                                full_response.push_str(answer);
-                            }
-                        }
-                    }
-                    
-                    // Extract title
-                    if let Some(t) = v.get("title").and_then(|t| t.as_str()) {
-                        println!("Found title in root: {}", t);
-                        title = Some(t.to_string());
-                    } else if v.get("type").and_then(|t| t.as_str()) == Some("title") {
-                        if let Some(t) = v.get("content").and_then(|c| c.as_str()) {
-                            println!("Found title in content as string (type=title): {}", t);
-                            title = Some(t.to_string());
-                        }
-                    } else if let Some(content) = v.get("content") {
-                        if let Some(t) = content.get("title").and_then(|t| t.as_str()) {
-                            println!("Found title in content object: {}", t);
-                            title = Some(t.to_string());
-                        }
-                    }
-                    
-                    // Check message type for tool_request and tool_response
-                    let msg_type = v.get("type").and_then(|t| t.as_str()).unwrap_or("");
-                    
-                    // Handle tool_request type (Coze API format)
-                    if msg_type == "tool_request" {
-                        if let Some(content) = v.get("content") {
-                            if let Some(tool_req) = content.get("tool_request") {
-                                if let Some(tool_id) = tool_req.get("tool_call_id").and_then(|v| v.as_str()) {
-                                    let tool_name = tool_req.get("tool_name").and_then(|v| v.as_str()).unwrap_or("Unknown").to_string();
-                                    let tool_input = tool_req.get("parameters")
-                                        .map(|p| serde_json::to_string_pretty(p).unwrap_or_default())
-                                        .unwrap_or_default();
-                                    
-                                    if !tool_calls.iter().any(|tc| tc.id == tool_id) {
-                                        tool_calls.push(ToolCall {
-                                            id: tool_id.to_string(),
-                                            tool_name,
-                                            tool_input,
-                                            tool_output: None,
-                                            status: "running".to_string(),
-                                        });
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    
-                    // Handle tool_response type (Coze API format)
-                    if msg_type == "tool_response" {
-                        if let Some(content) = v.get("content") {
-                            if let Some(tool_res) = content.get("tool_response") {
-                                if let Some(tool_id) = tool_res.get("tool_call_id").and_then(|v| v.as_str()) {
-                                    let code = tool_res.get("code").and_then(|c| c.as_str()).unwrap_or("0");
-                                    let status = if code == "0" { "success" } else { "error" };
-                                    
-                                    // Get result, truncate if extremely long
-                                    let result = tool_res.get("result")
-                                        .and_then(|r| r.as_str())
-                                        .map(|s| {
-                                            if s.len() > 1_000_000 {
-                                                format!("{}...(truncated)", &s[..1_000_000])
-                                            } else {
-                                                s.to_string()
-                                            }
-                                        });
-                                    
-                                    if let Some(tc) = tool_calls.iter_mut().find(|tc| tc.id == tool_id) {
-                                        tc.status = status.to_string();
-                                        tc.tool_output = result;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    
-                    // Also handle legacy tool_calls array format
-                    let search_targets = vec![&v, if v.get("content").map_or(false, |c| c.is_object()) { v.get("content").unwrap() } else { &serde_json::Value::Null }];
-                    
-                    for target in search_targets {
-                        if target.is_null() { continue; }
-                        
-                        // Handle tool calls array
-                        if let Some(calls) = target.get("tool_calls").and_then(|c| c.as_array()) {
-                            for tool_call_val in calls {
-                                if let Some(tool_id) = tool_call_val.get("id").and_then(|v| v.as_str()) {
-                                    let tool_name = tool_call_val.get("name").and_then(|v| v.as_str()).unwrap_or("Unknown").to_string();
-                                    let tool_input = tool_call_val.get("args").map(|v| v.to_string()).unwrap_or_default();
-                                    
-                                    if !tool_calls.iter().any(|tc| tc.id == tool_id) {
-                                        tool_calls.push(ToolCall {
-                                            id: tool_id.to_string(),
-                                            tool_name,
-                                            tool_input,
-                                            tool_output: None,
-                                            status: "running".to_string(),
-                                        });
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+    let body = response.bytes().await.map_err(stream_interrupted)?;
+    stats.response_bytes = body.len() as i64;
+    let mut decoder = SseDecoder::new();
+    let mut events = decoder.push(&body);
+    events.extend(decoder.finish());
+    
+    let mut reply = ReplyAccumulator::new();
+    for event in events.iter().flat_map(|e| coze::decode(&e.data)) {
+        reply.apply(event);
+        if let Some(error) = reply.error.take() {
+            return Err(AppError::Agent(error));
         }
     }
     
-    let tool_calls_opt = if tool_calls.is_empty() { None } else { Some(tool_calls) };
-    Ok((full_response, title, tool_calls_opt))
+    stats.duration_ms = elapsed_ms(started);
+    stats.usage = reply.usage;
+    let tool_calls = reply.tool_calls();
+    Ok(StreamOutcome {
+        content: reply.content,
+        title: reply.title,
+        tool_calls,
+        cancelled: false,
+        stats,
+    })
 }
 
-/// Streaming version that emits events as content arrives
+/// Streaming version that emits events as content arrives.
+///
+/// Stops reading as soon as `cancel` fires and returns whatever had been
+/// received so far with `cancelled` set. Stopped before the agent answered,
+/// there is nothing to keep and it fails with [`AppError::Cancelled`].
 pub async fn send_message_to_coze_streaming(
-    app: &AppHandle,
+    emitter: &StreamEmitter<'_>,
+    client: &Client,
+    policy: &RequestPolicy,
     api_url: &str,
     auth_token: &str,
-    project_id: &str,
-    message: &str,
-) -> Result<(String, Option<String>, Option<Vec<ToolCall>>), Box<dyn Error + Send + Sync>> {
-    let client = Client::new();
-    
-    let request = CozeRequest {
-        content: CozeContent {
-            query: CozeQuery {
-                prompt: vec![CozePrompt {
-                    prompt_type: "text".to_string(),
-                    content: CozePromptContent {
-                        text: message.to_string(),
-                    },
-                }],
-            },
-        },
-        request_type: "query".to_string(),
-        project_id: project_id.to_string(),
+    request: &CozeRequest,
+    cancel: &CancellationToken,
+) -> Result<StreamOutcome, AppError> {
+    let (mut stats, started) = ReplyStats::start();
+    let build_request = || coze_post(client, api_url, auth_token, request);
+    
+    let on_retry = |notice: &RetryNotice| {
+        log_retry(notice);
+        emitter.emit(StreamEvent {
+            content: Some(notice.reason.clone()),
+            attempt: Some(notice.attempt),
+            max_retries: Some(notice.max_retries),
+            retry_in_ms: Some(notice.delay.as_millis() as u64),
+            ..emitter.event("retrying")
+        });
     };
     
-    let response = client
-        .post(api_url)
-        .header("Authorization", format!("Bearer {}", auth_token))
-        .header("Content-Type", "application/json")
-        .json(&request)
-        .send()
-        .await?;
+    let response = match send_with_retry(build_request, policy, cancel, on_retry).await {
+        Ok(SendOutcome::Response(response)) => response,
+        Ok(SendOutcome::Cancelled) => {
+            emitter.emit(StreamEvent {
+                full_content: Some(String::new()),
+                ..emitter.event("cancelled")
+            });
+            return Err(AppError::Cancelled("Stopped before the agent answered".to_string()));
+        }
+        Err(e) => {
+            let error = AppError::from(e);
+            emitter.emit(StreamEvent {
+                content: Some(error.to_string()),
+                ..emitter.event("error")
+            });
+            return Err(error);
+        }
+    };
     
     if !response.status().is_success() {
-        let _ = app.emit("chat-stream", StreamEvent {
-            event_type: "error".to_string(),
-            content: Some(format!("API request failed: {}", response.status())),
-            tool_call: None,
-            full_content: None,
-            tool_calls: None,
+        let error = AppError::from_response(response).await;
+        emitter.emit(StreamEvent {
+            content: Some(error.to_string()),
+            ..emitter.event("error")
         });
-        return Err(format!("API request failed: {}", response.status()).into());
+        return Err(error);
     }
     
     let mut stream = response.bytes_stream();
-    let mut full_response = String::new();
-    let mut title = None;
-    let mut tool_calls: Vec<ToolCall> = Vec::new();
-    
-    let mut buffer = String::new();
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk?;
-        let text = String::from_utf8_lossy(&chunk);
-        buffer.push_str(&text);
+    let mut reply = ReplyAccumulator::new();
+    
+    let mut cancelled = false;
+    
+    let mut decoder = SseDecoder::new();
+    let mut finished = false;
+    while !finished {
+        let next = tokio::select! {
+            _ = cancel.cancelled() => {
+                cancelled = true;
+                break;
+            }
+            next = stream.next() => next,
+        };
+        let events = match next {
+            Some(Ok(chunk)) => {
+                stats.response_bytes += chunk.len() as i64;
+                decoder.push(&chunk)
+            }
+            Some(Err(e)) => {
+                let error = stream_interrupted(e);
+                emitter.emit(StreamEvent {
+                    content: Some(error.to_string()),
+                    full_content: Some(reply.content.clone()),
+                    ..emitter.event("error")
+                });
+                return Err(error);
+            }
+            None => {
+                finished = true;
+                decoder.finish()
+            }
+        };
         
-        while let Some(pos) = buffer.find('\n') {
-            let line = buffer[..pos].to_string();
-            let remaining = buffer[pos + 1..].to_string();
-            buffer = remaining;
-            
-            let line = line.trim();
-            if line.starts_with("data: ") {
-                let json_str = &line[6..]; // Remove "data: " prefix
-                if let Ok(v) = serde_json::from_str::<serde_json::Value>(json_str) {
-                    let msg_type = v.get("type").and_then(|t| t.as_str()).unwrap_or("");
-                    
-                    // Extract answer content and emit streaming event
-                    let mut chunk_content: Option<String> = None;
-                    
-                    if let Some(answer) = v.get("answer").and_then(|a| a.as_str()) {
-                        chunk_content = Some(answer.to_string());
-                        full_response.push_str(answer);
-                    } else if let Some(content) = v.get("content") {
-                        if let Some(answer) = content.get("answer").and_then(|a| a.as_str()) {
-                            chunk_content = Some(answer.to_string());
-                            full_response.push_str(answer);
-                        } else if let Some(answer) = content.as_str() {
-                            if msg_type == "answer" || v.get("role").and_then(|r| r.as_str()) == Some("assistant") {
-                                chunk_content = Some(answer.to_string());
-                                full_response.push_str(answer);
-                            }
-                        }
-                    }
-                    
-                    // Emit content event if we have new content
-                    if let Some(content) = chunk_content {
-                        if !content.is_empty() {
-                            let _ = app.emit("chat-stream", StreamEvent {
-                                event_type: "content".to_string(),
-                                content: Some(content),
-                                tool_call: None,
-                                full_content: Some(full_response.clone()),
-                                tool_calls: None,
-                            });
-                        }
-                    }
-                    
-                    // Extract title
-                    if let Some(t) = v.get("title").and_then(|t| t.as_str()) {
-                        title = Some(t.to_string());
-                    } else if msg_type == "title" {
-                        if let Some(t) = v.get("content").and_then(|c| c.as_str()) {
-                            title = Some(t.to_string());
-                        }
-                    } else if let Some(content) = v.get("content") {
-                        if let Some(t) = content.get("title").and_then(|t| t.as_str()) {
-                            title = Some(t.to_string());
-                        }
-                    }
-                    
-                    // Handle tool_request type
-                    if msg_type == "tool_request" {
-                        if let Some(content) = v.get("content") {
-                            if let Some(tool_req) = content.get("tool_request") {
-                                if let Some(tool_id) = tool_req.get("tool_call_id").and_then(|v| v.as_str()) {
-                                    let tool_name = tool_req.get("tool_name").and_then(|v| v.as_str()).unwrap_or("Unknown").to_string();
-                                    let tool_input = tool_req.get("parameters")
-                                        .map(|p| serde_json::to_string_pretty(p).unwrap_or_default())
-                                        .unwrap_or_default();
-                                    
-                                    if !tool_calls.iter().any(|tc| tc.id == tool_id) {
-                                        let new_tool_call = ToolCall {
-                                            id: tool_id.to_string(),
-                                            tool_name,
-                                            tool_input,
-                                            tool_output: None,
-                                            status: "running".to_string(),
-                                        };
-                                        
-                                        // Emit tool call event
-                                        let _ = app.emit("chat-stream", StreamEvent {
-                                            event_type: "tool_call".to_string(),
-                                            content: None,
-                                            tool_call: Some(new_tool_call.clone()),
-                                            full_content: None,
-                                            tool_calls: None,
-                                        });
-                                        
-                                        tool_calls.push(new_tool_call);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    
-                    // Handle tool_response type
-                    if msg_type == "tool_response" {
-                        if let Some(content) = v.get("content") {
-                            if let Some(tool_res) = content.get("tool_response") {
-                                if let Some(tool_id) = tool_res.get("tool_call_id").and_then(|v| v.as_str()) {
-                                    let code = tool_res.get("code").and_then(|c| c.as_str()).unwrap_or("0");
-                                    let status = if code == "0" { "success" } else { "error" };
-                                    
-                                    let result = tool_res.get("result")
-                                        .and_then(|r| r.as_str())
-                                        .map(|s| {
-                                            if s.len() > 1_000_000 {
-                                                format!("{}...(truncated)", &s[..1_000_000])
-                                            } else {
-                                                s.to_string()
-                                            }
-                                        });
-                                    
-                                    if let Some(tc) = tool_calls.iter_mut().find(|tc| tc.id == tool_id) {
-                                        tc.status = status.to_string();
-                                        tc.tool_output = result;
-                                        
-                                        // Emit tool result event
-                                        let _ = app.emit("chat-stream", StreamEvent {
-                                            event_type: "tool_result".to_string(),
-                                            content: None,
-                                            tool_call: Some(tc.clone()),
-                                            full_content: None,
-                                            tool_calls: Some(tool_calls.clone()),
-                                        });
-                                    }
-                                }
-                            }
-                        }
-                    }
+        for event in events.iter().flat_map(|e| coze::decode(&e.data)) {
+            match reply.apply(event) {
+                Some(ReplyUpdate::Content(content)) => {
+                    stats.first_token_ms.get_or_insert_with(|| elapsed_ms(started));
+                    emitter.emit(StreamEvent {
+                        content: Some(content),
+                        full_content: Some(reply.content.clone()),
+                        ..emitter.event("content")
+                    })
                 }
+                Some(ReplyUpdate::ToolCall(tool_call)) => emitter.emit(StreamEvent {
+                    tool_call: Some(tool_call),
+                    ..emitter.event("tool_call")
+                }),
+                Some(ReplyUpdate::ToolResult(tool_call)) => emitter.emit(StreamEvent {
+                    tool_call: Some(tool_call),
+                    tool_calls: Some(reply.tool_calls.clone()),
+                    ..emitter.event("tool_result")
+                }),
+                None => {}
+            }
+            
+            if let Some(error) = reply.error.take() {
+                let error = AppError::Agent(error);
+                emitter.emit(StreamEvent {
+                    content: Some(error.to_string()),
+                    full_content: Some(reply.content.clone()),
+                    ..emitter.event("error")
+                });
+                return Err(error);
             }
         }
     }
     
-    // Emit done event
-    let tool_calls_opt = if tool_calls.is_empty() { None } else { Some(tool_calls.clone()) };
-    let _ = app.emit("chat-stream", StreamEvent {
-        event_type: "done".to_string(),
-        content: None,
-        tool_call: None,
-        full_content: Some(full_response.clone()),
-        tool_calls: tool_calls_opt.clone(),
+    // Tool calls still running when the stream was stopped never got a result
+    if cancelled {
+        reply.tool_calls.retain(|tc| tc.status != "running");
+    }
+    
+    // Emit done (or cancelled) event
+    let tool_calls = reply.tool_calls();
+    emitter.emit(StreamEvent {
+        full_content: Some(reply.content.clone()),
+        tool_calls: tool_calls.clone(),
+        ..emitter.event(if cancelled { "cancelled" } else { "done" })
     });
     
-    Ok((full_response, title, tool_calls_opt))
+    stats.duration_ms = elapsed_ms(started);
+    stats.usage = reply.usage;
+    Ok(StreamOutcome {
+        content: reply.content,
+        title: reply.title,
+        tool_calls,
+        cancelled,
+        stats,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{Agent, CreateAgentInput};
+    use crate::test_support::{agent_input, setup_conversation};
+
+    fn message(conversation: &Conversation, role: &str, content: &str) -> Message {
+        Message::new(conversation.id.clone(), role.to_string(), content.to_string(), None)
+    }
+
+    fn contents(messages: &[Message]) -> Vec<&str> {
+        messages.iter().map(|m| m.content.as_str()).collect()
+    }
+
+    #[test]
+    fn saved_messages_form_a_chain() {
+        let (conn, conversation) = setup_conversation(None);
+        let mut question = message(&conversation, "user", "q");
+        question.save(&conn).unwrap();
+        let mut answer = message(&conversation, "assistant", "a");
+        answer.save(&conn).unwrap();
+
+        assert_eq!(question.parent_id, None);
+        assert_eq!(answer.parent_id.as_deref(), Some(question.id.as_str()));
+        assert_eq!(contents(&Message::get_by_conversation(&conn, &conversation.id).unwrap()), vec!["q", "a"]);
+    }
+
+    #[test]
+    fn drafts_are_updated_until_finished() {
+        let (conn, conversation) = setup_conversation(None);
+        let mut question = message(&conversation, "user", "q");
+        question.save(&conn).unwrap();
+        let mut draft = message(&conversation, "assistant", "");
+        draft.status = "streaming".to_string();
+        draft.save(&conn).unwrap();
+
+        Message::update_draft(&conn, &draft.id, "partial", None).unwrap();
+        let stored = Message::get_by_id(&conn, &draft.id).unwrap().unwrap();
+        assert_eq!((stored.content.as_str(), stored.status.as_str()), ("partial", "streaming"));
+
+        Message::finish(&conn, &draft.id, "partial answer", None, "error").unwrap();
+        Message::update_draft(&conn, &draft.id, "late flush", None).unwrap();
+        Message::discard_draft(&conn, &draft.id).unwrap();
+        let stored = Message::get_by_id(&conn, &draft.id).unwrap().unwrap();
+        assert_eq!((stored.content.as_str(), stored.status.as_str()), ("partial answer", "error"));
+    }
+
+    #[test]
+    fn empty_draft_is_discarded_and_stale_ones_recovered() {
+        let (conn, conversation) = setup_conversation(None);
+        let mut question = message(&conversation, "user", "q");
+        question.save(&conn).unwrap();
+        let mut empty = message(&conversation, "assistant", "");
+        empty.status = "streaming".to_string();
+        empty.save(&conn).unwrap();
+
+        Message::discard_draft(&conn, &empty.id).unwrap();
+        assert!(Message::get_by_id(&conn, &empty.id).unwrap().is_none());
+        assert_eq!(Conversation::active_message_id(&conn, &conversation.id).unwrap(), Some(question.id.clone()));
+
+        let mut stale = message(&conversation, "assistant", "cut off");
+        stale.status = "streaming".to_string();
+        stale.save(&conn).unwrap();
+        assert_eq!(Message::recover_drafts(&conn).unwrap(), 1);
+        let path = Message::get_by_conversation(&conn, &conversation.id).unwrap();
+        assert_eq!(path[1].status, "interrupted");
+        assert_eq!(Message::recover_drafts(&conn).unwrap(), 0);
+    }
+
+    #[test]
+    fn save_order_wins_over_timestamps() {
+        let (conn, conversation) = setup_conversation(None);
+        let mut question = message(&conversation, "user", "q");
+        let mut answer = message(&conversation, "assistant", "a");
+        let mut retry = message(&conversation, "assistant", "b");
+        answer.created_at = question.created_at;
+        retry.created_at = question.created_at - 5;
+        question.save(&conn).unwrap();
+        answer.save(&conn).unwrap();
+        retry.save_under(&conn, Some(question.id.clone())).unwrap();
+
+        assert_eq!((question.seq, answer.seq, retry.seq), (1, 2, 3));
+        assert_eq!(contents(&Message::get_all(&conn, &conversation.id).unwrap()), vec!["q", "a", "b"]);
+        let branches = Message::get_branches(&conn, &conversation.id).unwrap();
+        assert_eq!(branches[0].leaf_id, retry.id);
+
+        // Numbering is per conversation
+        let other = Conversation::new(conversation.agent_id.clone(), None);
+        other.save(&conn).unwrap();
+        let mut first = message(&other, "user", "x");
+        first.save(&conn).unwrap();
+        assert_eq!(first.seq, 1);
+    }
+
+    #[test]
+    fn replies_to_the_same_message_are_versions() {
+        let (conn, conversation) = setup_conversation(None);
+        let mut question = message(&conversation, "user", "q");
+        question.save(&conn).unwrap();
+        let mut first = message(&conversation, "assistant", "first");
+        first.save_under(&conn, Some(question.id.clone())).unwrap();
+        let mut second = message(&conversation, "assistant", "second");
+        second.save_under(&conn, Some(question.id.clone())).unwrap();
+
+        assert_eq!((first.version, second.version), (1, 2));
+        let path = Message::get_by_conversation(&conn, &conversation.id).unwrap();
+        assert_eq!(contents(&path), vec!["q", "second"]);
+        assert_eq!(path[1].version, 2);
+        assert_eq!(path[1].version_count, 2);
+        assert_eq!(path[0].version_count, 1);
+    }
+
+    #[test]
+    fn selecting_a_version_follows_its_latest_replies() {
+        let (conn, conversation) = setup_conversation(None);
+        let mut question = message(&conversation, "user", "q");
+        question.save(&conn).unwrap();
+        let mut first = message(&conversation, "assistant", "first");
+        first.save(&conn).unwrap();
+        message(&conversation, "user", "follow-up").save(&conn).unwrap();
+        message(&conversation, "assistant", "follow-up answer").save(&conn).unwrap();
+        message(&conversation, "assistant", "second").save_under(&conn, Some(question.id.clone())).unwrap();
+
+        assert_eq!(contents(&Message::get_by_conversation(&conn, &conversation.id).unwrap()), vec!["q", "second"]);
+
+        let selected = Message::select_version(&conn, &first.id, 1).unwrap();
+
+        assert_eq!(selected.as_deref(), Some(conversation.id.as_str()));
+        assert_eq!(
+            contents(&Message::get_by_conversation(&conn, &conversation.id).unwrap()),
+            vec!["q", "first", "follow-up", "follow-up answer"]
+        );
+        assert_eq!(Message::select_version(&conn, &first.id, 3).unwrap(), None);
+    }
+
+    #[test]
+    fn recent_messages_come_from_the_current_path() {
+        let (conn, conversation) = setup_conversation(None);
+        let mut question = message(&conversation, "user", "q");
+        question.save(&conn).unwrap();
+        message(&conversation, "assistant", "old").save(&conn).unwrap();
+        message(&conversation, "assistant", "new").save_under(&conn, Some(question.id.clone())).unwrap();
+        message(&conversation, "user", "next").save(&conn).unwrap();
+
+        assert_eq!(contents(&Message::get_recent(&conn, &conversation.id, 2).unwrap()), vec!["new", "next"]);
+        assert_eq!(contents(&Message::get_recent(&conn, &conversation.id, 10).unwrap()), vec!["q", "new", "next"]);
+        assert!(Message::get_recent(&conn, &conversation.id, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn edited_message_starts_a_branch_and_both_are_listed() {
+        let (conn, conversation) = setup_conversation(None);
+        let mut first = message(&conversation, "user", "What is Rust?");
+        first.save(&conn).unwrap();
+        message(&conversation, "assistant", "A language").save(&conn).unwrap();
+        let mut question = message(&conversation, "user", "Is it fast?");
+        question.save(&conn).unwrap();
+        let mut original_answer = message(&conversation, "assistant", "Yes");
+        original_answer.save(&conn).unwrap();
+
+        let mut edited = message(&conversation, "user", "Is it safe?");
+        edited.save_under(&conn, question.parent_id.clone()).unwrap();
+        let mut edited_answer = message(&conversation, "assistant", "Memory safe");
+        edited_answer.save(&conn).unwrap();
+
+        let path = Message::get_by_conversation(&conn, &conversation.id).unwrap();
+        assert_eq!(contents(&path), vec!["What is Rust?", "A language", "Is it safe?", "Memory safe"]);
+        assert_eq!((path[2].version, path[2].version_count), (2, 2));
+
+        let branches = Message::get_branches(&conn, &conversation.id).unwrap();
+        assert_eq!(branches.len(), 2);
+        let active: Vec<_> = branches.iter().filter(|b| b.active).collect();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].leaf_id, edited_answer.id);
+        let original = branches.iter().find(|b| b.leaf_id == original_answer.id).unwrap();
+        assert_eq!(original.message_count, 4);
+        assert_eq!(original.last_user_message.as_deref(), Some("Is it fast?"));
+
+        assert!(Message::select_branch(&conn, &conversation.id, &original_answer.id).unwrap());
+        assert_eq!(
+            contents(&Message::get_by_conversation(&conn, &conversation.id).unwrap()),
+            vec!["What is Rust?", "A language", "Is it fast?", "Yes"]
+        );
+        assert!(!Message::select_branch(&conn, &conversation.id, "missing").unwrap());
+    }
+
+    #[test]
+    fn path_to_a_message_ignores_other_branches() {
+        let (conn, conversation) = setup_conversation(None);
+        let mut root = message(&conversation, "user", "root");
+        root.save(&conn).unwrap();
+        let mut left = message(&conversation, "assistant", "left");
+        left.save(&conn).unwrap();
+        message(&conversation, "assistant", "right").save_under(&conn, Some(root.id.clone())).unwrap();
+
+        assert_eq!(contents(&Message::get_path(&conn, &left.id, -1).unwrap()), vec!["root", "left"]);
+        assert_eq!(contents(&Message::get_path(&conn, &left.id, 1).unwrap()), vec!["left"]);
+    }
+
+    #[test]
+    fn fork_copies_the_path_with_tool_calls_and_records_its_origin() {
+        let (conn, conversation) = setup_conversation(None);
+        let mut question = message(&conversation, "user", "q");
+        question.save(&conn).unwrap();
+        let tool_call = ToolCall {
+            id: "t1".to_string(),
+            tool_name: "search".to_string(),
+            tool_input: "{}".to_string(),
+            tool_output: Some("found".to_string()),
+            status: "success".to_string(),
+        };
+        let mut answer = Message::new(conversation.id.clone(), "assistant".to_string(), "a".to_string(), Some(vec![tool_call.clone()]));
+        answer.save(&conn).unwrap();
+        message(&conversation, "assistant", "other version").save_under(&conn, Some(question.id.clone())).unwrap();
+        message(&conversation, "user", "later").save(&conn).unwrap();
+
+        let fork = Conversation::fork(&conn, &conversation.id, &answer.id, None).unwrap().unwrap();
+
+        assert_eq!(fork.agent_id, conversation.agent_id);
+        assert_eq!(fork.forked_from_conversation_id.as_deref(), Some(conversation.id.as_str()));
+        assert_eq!(fork.forked_from_message_id.as_deref(), Some(answer.id.as_str()));
+        let copied = Message::get_by_conversation(&conn, &fork.id).unwrap();
+        assert_eq!(contents(&copied), vec!["q", "a"]);
+        assert_ne!(copied[1].id, answer.id);
+        assert_eq!(copied[1].tool_calls, Some(vec![tool_call]));
+        assert_eq!(copied[1].version_count, 1);
+        assert_eq!(Conversation::get_by_id(&conn, &fork.id).unwrap().unwrap().forked_from_message_id, fork.forked_from_message_id);
+        assert_eq!(Message::get_by_conversation(&conn, &conversation.id).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn fork_settles_a_reply_that_is_still_streaming() {
+        let (conn, conversation) = setup_conversation(None);
+        message(&conversation, "user", "q").save(&conn).unwrap();
+        let mut draft = message(&conversation, "assistant", "partial");
+        draft.status = "streaming".to_string();
+        draft.save(&conn).unwrap();
+
+        let fork = Conversation::fork(&conn, &conversation.id, &draft.id, None).unwrap().unwrap();
+
+        let copied = Message::get_by_conversation(&conn, &fork.id).unwrap();
+        assert_eq!((copied[1].content.as_str(), copied[1].status.as_str()), ("partial", "interrupted"));
+        assert_eq!(Message::get_by_id(&conn, &draft.id).unwrap().unwrap().status, "streaming");
+    }
+
+    #[test]
+    fn fork_can_move_to_another_agent_and_rejects_foreign_messages() {
+        let (conn, conversation) = setup_conversation(None);
+        let mut question = message(&conversation, "user", "q");
+        question.save(&conn).unwrap();
+        let other = Conversation::new(conversation.agent_id.clone(), None);
+        other.save(&conn).unwrap();
+        let agent = Agent::new(CreateAgentInput { project_id: "p2".to_string(), ..agent_input("Second") });
+        agent.save(&conn).unwrap();
+
+        let fork = Conversation::fork(&conn, &conversation.id, &question.id, Some(&agent.id)).unwrap().unwrap();
+        assert_eq!(fork.agent_id, agent.id);
+        assert!(Conversation::fork(&conn, &other.id, &question.id, None).unwrap().is_none());
+        assert!(Conversation::fork(&conn, &conversation.id, "missing", None).unwrap().is_none());
+    }
 }
@@ -0,0 +1,672 @@
+//! Turns Coze `stream_run` payloads into domain events, and folds those events
+//! into the reply being built. Every front-end that talks to Coze goes through
+//! here, so a change in the payload format only needs handling once.
+//!
+//! Payloads are read into typed models keyed on their `type` field first. Older
+//! and untyped shapes (a root `answer`, a `tool_calls` array, ...) go through a
+//! lenient model, and anything neither understands comes out as
+//! [`CozeEvent::Unknown`]. Both of those paths are logged at debug level under
+//! [`UNCLASSIFIED_LOG_TARGET`].
+
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::chat::ToolCall;
+
+/// Log target for payloads the typed models rejected; enable it with
+/// `COZE_STUDIO_LOG=info,tauri_app_lib::coze::unclassified=debug`
+pub const UNCLASSIFIED_LOG_TARGET: &str = "tauri_app_lib::coze::unclassified";
+
+// Tool outputs larger than this are cut before they reach the UI or the database
+const MAX_TOOL_OUTPUT_BYTES: usize = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CozeEvent {
+    /// A piece of the answer text, to be appended
+    AnswerDelta(String),
+    Title(String),
+    ToolRequest {
+        id: String,
+        name: String,
+        input: String,
+    },
+    ToolResponse {
+        id: String,
+        output: Option<String>,
+        success: bool,
+    },
+    /// The server reported a failure inside the stream
+    Error(String),
+    /// Token counts reported when the reply ended
+    Usage(TokenUsage),
+    Done,
+    /// A payload none of the models could classify
+    Unknown(Value),
+}
+
+/// Token counts for one reply; counts Coze leaves out stay `None`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "ReportedUsage")]
+pub struct TokenUsage {
+    pub input_tokens: Option<i64>,
+    pub output_tokens: Option<i64>,
+    pub total_tokens: Option<i64>,
+}
+
+/// Token counts under every name deployments use. Serde aliases would reject a
+/// payload carrying two names for one count, so each is read on its own.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ReportedUsage {
+    input_tokens: Option<i64>,
+    input: Option<i64>,
+    input_count: Option<i64>,
+    prompt_tokens: Option<i64>,
+    output_tokens: Option<i64>,
+    output: Option<i64>,
+    output_count: Option<i64>,
+    completion_tokens: Option<i64>,
+    total_tokens: Option<i64>,
+    total: Option<i64>,
+    token_count: Option<i64>,
+}
+
+impl From<ReportedUsage> for TokenUsage {
+    fn from(reported: ReportedUsage) -> Self {
+        TokenUsage {
+            input_tokens: reported.input_tokens.or(reported.input).or(reported.input_count).or(reported.prompt_tokens),
+            output_tokens: reported
+                .output_tokens
+                .or(reported.output)
+                .or(reported.output_count)
+                .or(reported.completion_tokens),
+            total_tokens: reported.total_tokens.or(reported.total).or(reported.token_count),
+        }
+    }
+}
+
+impl TokenUsage {
+    fn is_empty(&self) -> bool {
+        self.input_tokens.is_none() && self.output_tokens.is_none() && self.total_tokens.is_none()
+    }
+}
+
+/// Stream messages by their `type` field
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamMessage {
+    Answer {
+        content: AnswerContent,
+    },
+    Title {
+        content: String,
+    },
+    ToolRequest {
+        content: ToolRequestContent,
+    },
+    ToolResponse {
+        content: ToolResponseContent,
+    },
+    MessageStart {},
+    MessageEnd {
+        #[serde(default)]
+        content: Option<MessageEndContent>,
+    },
+    Error {
+        #[serde(default)]
+        content: Option<ErrorContent>,
+        #[serde(default)]
+        msg: Option<String>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum AnswerContent {
+    Text(String),
+    Object {
+        answer: String,
+        #[serde(default)]
+        title: Option<String>,
+        #[serde(default)]
+        tool_calls: Vec<LegacyToolCall>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolRequestContent {
+    tool_request: ToolRequest,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolRequest {
+    tool_call_id: String,
+    #[serde(default)]
+    tool_name: Option<String>,
+    #[serde(default)]
+    parameters: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolResponseContent {
+    tool_response: ToolResponse,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolResponse {
+    tool_call_id: String,
+    #[serde(default)]
+    code: Option<ResultCode>,
+    #[serde(default)]
+    result: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageEndContent {
+    message_end: MessageEnd,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageEnd {
+    #[serde(default)]
+    code: Option<ResultCode>,
+    #[serde(default)]
+    message: Option<String>,
+    // Deployments report token counts under either key, merged like the counts themselves
+    #[serde(default)]
+    usage: Option<TokenUsage>,
+    #[serde(default)]
+    token_cost: Option<TokenUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ErrorContent {
+    Detail { error: ErrorDetail },
+    Flat { error_msg: String },
+    Text(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorDetail {
+    #[serde(default)]
+    error_msg: Option<String>,
+}
+
+/// Result codes arrive as "0" or 0
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ResultCode {
+    Text(String),
+    Number(i64),
+}
+
+impl ResultCode {
+    fn is_success(&self) -> bool {
+        match self {
+            ResultCode::Text(code) => code == "0",
+            ResultCode::Number(code) => *code == 0,
+        }
+    }
+}
+
+/// Untyped and older payload shapes
+#[derive(Debug, Default, Deserialize)]
+struct LegacyMessage {
+    #[serde(default, rename = "type")]
+    msg_type: Option<String>,
+    #[serde(default)]
+    role: Option<String>,
+    #[serde(default)]
+    answer: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    content: Option<LegacyContent>,
+    #[serde(default)]
+    tool_calls: Vec<LegacyToolCall>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum LegacyContent {
+    Text(String),
+    Object {
+        #[serde(default)]
+        answer: Option<String>,
+        #[serde(default)]
+        title: Option<String>,
+        #[serde(default)]
+        tool_calls: Vec<LegacyToolCall>,
+    },
+    // Any other content still lets the root fields through
+    Other(IgnoredAny),
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacyToolCall {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    args: Option<Value>,
+}
+
+/// Decodes the data of one SSE event; a payload can carry several events at once
+pub fn decode(data: &str) -> Vec<CozeEvent> {
+    let events = match serde_json::from_str::<Value>(data) {
+        Ok(payload) => decode_payload(payload),
+        Err(_) => vec![CozeEvent::Unknown(Value::String(data.to_string()))],
+    };
+    if matches!(events[..], [CozeEvent::Unknown(_)]) {
+        tracing::debug!(target: UNCLASSIFIED_LOG_TARGET, payload = data, "unclassified stream payload");
+    }
+    events
+}
+
+pub fn decode_payload(payload: Value) -> Vec<CozeEvent> {
+    let events = match StreamMessage::deserialize(&payload) {
+        Ok(message) => typed_events(message),
+        Err(error) => match LegacyMessage::deserialize(&payload) {
+            Ok(message) => {
+                // A typed message that changed shape would otherwise pass silently as a legacy one
+                tracing::debug!(target: UNCLASSIFIED_LOG_TARGET, %error, %payload, "stream payload read with the legacy model");
+                legacy_events(message)
+            }
+            Err(_) => Vec::new(),
+        },
+    };
+
+    if events.is_empty() && !is_known_empty(&payload) {
+        return vec![CozeEvent::Unknown(payload)];
+    }
+    events
+}
+
+fn typed_events(message: StreamMessage) -> Vec<CozeEvent> {
+    match message {
+        StreamMessage::Answer { content: AnswerContent::Text(answer) } => vec![CozeEvent::AnswerDelta(answer)],
+        StreamMessage::Answer { content: AnswerContent::Object { answer, title, tool_calls } } => {
+            let mut events = vec![CozeEvent::AnswerDelta(answer)];
+            events.extend(title.map(CozeEvent::Title));
+            events.extend(legacy_tool_calls(tool_calls));
+            events
+        }
+        StreamMessage::Title { content } => vec![CozeEvent::Title(content)],
+        StreamMessage::ToolRequest { content: ToolRequestContent { tool_request } } => vec![CozeEvent::ToolRequest {
+            id: tool_request.tool_call_id,
+            name: tool_request.tool_name.unwrap_or_else(|| "Unknown".to_string()),
+            input: tool_request
+                .parameters
+                .map(|p| serde_json::to_string_pretty(&p).unwrap_or_default())
+                .unwrap_or_default(),
+        }],
+        StreamMessage::ToolResponse { content: ToolResponseContent { tool_response } } => vec![CozeEvent::ToolResponse {
+            id: tool_response.tool_call_id,
+            output: tool_response.result.as_deref().map(truncate_output),
+            success: tool_response.code.as_ref().is_none_or(ResultCode::is_success),
+        }],
+        StreamMessage::MessageStart {} => Vec::new(),
+        StreamMessage::MessageEnd { content } => {
+            let Some(end) = content.map(|c| c.message_end) else {
+                return vec![CozeEvent::Done];
+            };
+            let mut events: Vec<CozeEvent> = end
+                .usage
+                .into_iter()
+                .chain(end.token_cost)
+                .find(|usage| !usage.is_empty())
+                .map(CozeEvent::Usage)
+                .into_iter()
+                .collect();
+            if let Some(code) = end.code.filter(|code| !code.is_success()) {
+                events.push(CozeEvent::Error(end.message.filter(|m| !m.is_empty()).unwrap_or_else(|| match code {
+                    ResultCode::Text(code) => format!("Coze ended the reply with code {}", code),
+                    ResultCode::Number(code) => format!("Coze ended the reply with code {}", code),
+                })));
+            }
+            events.push(CozeEvent::Done);
+            events
+        }
+        StreamMessage::Error { content, msg } => {
+            let message = match content {
+                Some(ErrorContent::Detail { error: ErrorDetail { error_msg: Some(m) } }) => Some(m),
+                Some(ErrorContent::Flat { error_msg }) => Some(error_msg),
+                Some(ErrorContent::Text(m)) => Some(m),
+                _ => None,
+            };
+            let message = message.or(msg).filter(|m| !m.is_empty());
+            vec![CozeEvent::Error(message.unwrap_or_else(|| "Coze reported an error".to_string()))]
+        }
+    }
+}
+
+fn legacy_events(message: LegacyMessage) -> Vec<CozeEvent> {
+    let mut events = Vec::new();
+    // A bare string content is answer text only when the payload says so
+    let is_answer = message.msg_type.as_deref() == Some("answer") || message.role.as_deref() == Some("assistant");
+
+    let (content_answer, content_title, content_tool_calls) = match message.content {
+        Some(LegacyContent::Text(text)) if is_answer => (Some(text), None, Vec::new()),
+        Some(LegacyContent::Object { answer, title, tool_calls }) => (answer, title, tool_calls),
+        _ => (None, None, Vec::new()),
+    };
+
+    events.extend(message.answer.or(content_answer).map(CozeEvent::AnswerDelta));
+    events.extend(message.title.or(content_title).map(CozeEvent::Title));
+    events.extend(legacy_tool_calls(message.tool_calls));
+    events.extend(legacy_tool_calls(content_tool_calls));
+    events
+}
+
+fn legacy_tool_calls(calls: Vec<LegacyToolCall>) -> impl Iterator<Item = CozeEvent> {
+    calls.into_iter().filter_map(|call| {
+        Some(CozeEvent::ToolRequest {
+            id: call.id?,
+            name: call.name.unwrap_or_else(|| "Unknown".to_string()),
+            input: call.args.map(|a| a.to_string()).unwrap_or_default(),
+        })
+    })
+}
+
+// Types that legitimately carry nothing for the reply
+fn is_known_empty(payload: &Value) -> bool {
+    matches!(payload.get("type").and_then(|t| t.as_str()), Some("message_start"))
+}
+
+fn truncate_output(s: &str) -> String {
+    if s.len() <= MAX_TOOL_OUTPUT_BYTES {
+        return s.to_string();
+    }
+    let mut end = MAX_TOOL_OUTPUT_BYTES;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}...(truncated)", &s[..end])
+}
+
+/// What applying an event changed, for front-ends that report progress
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplyUpdate {
+    Content(String),
+    ToolCall(ToolCall),
+    ToolResult(ToolCall),
+}
+
+/// The reply as assembled so far from decoded events
+#[derive(Debug, Default)]
+pub struct ReplyAccumulator {
+    pub content: String,
+    pub title: Option<String>,
+    pub tool_calls: Vec<ToolCall>,
+    pub error: Option<String>,
+    pub usage: Option<TokenUsage>,
+    pub done: bool,
+}
+
+impl ReplyAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn apply(&mut self, event: CozeEvent) -> Option<ReplyUpdate> {
+        match event {
+            CozeEvent::AnswerDelta(text) => {
+                if text.is_empty() {
+                    return None;
+                }
+                self.content.push_str(&text);
+                Some(ReplyUpdate::Content(text))
+            }
+            CozeEvent::Title(title) => {
+                self.title = Some(title);
+                None
+            }
+            CozeEvent::ToolRequest { id, name, input } => {
+                if self.tool_calls.iter().any(|tc| tc.id == id) {
+                    return None;
+                }
+                let tool_call = ToolCall {
+                    id,
+                    tool_name: name,
+                    tool_input: input,
+                    tool_output: None,
+                    status: "running".to_string(),
+                };
+                self.tool_calls.push(tool_call.clone());
+                Some(ReplyUpdate::ToolCall(tool_call))
+            }
+            CozeEvent::ToolResponse { id, output, success } => {
+                let tool_call = self.tool_calls.iter_mut().find(|tc| tc.id == id)?;
+                tool_call.status = if success { "success" } else { "error" }.to_string();
+                tool_call.tool_output = output;
+                Some(ReplyUpdate::ToolResult(tool_call.clone()))
+            }
+            CozeEvent::Error(message) => {
+                self.error = Some(message);
+                None
+            }
+            CozeEvent::Usage(usage) => {
+                self.usage = Some(usage);
+                None
+            }
+            CozeEvent::Done => {
+                self.done = true;
+                None
+            }
+            CozeEvent::Unknown(_) => None,
+        }
+    }
+
+    /// Tool calls for storage: `None` when there were none
+    pub fn tool_calls(&self) -> Option<Vec<ToolCall>> {
+        (!self.tool_calls.is_empty()).then(|| self.tool_calls.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Captured;
+    use serde_json::json;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::{fmt, EnvFilter};
+
+    fn events(payload: Value) -> Vec<CozeEvent> {
+        decode_payload(payload)
+    }
+
+    #[test]
+    fn reads_answer_text_from_every_known_shape() {
+        let expected = vec![CozeEvent::AnswerDelta("hi".to_string())];
+
+        assert_eq!(events(json!({"answer": "hi"})), expected);
+        assert_eq!(events(json!({"type": "answer", "content": {"answer": "hi"}})), expected);
+        assert_eq!(events(json!({"type": "answer", "content": "hi"})), expected);
+        assert_eq!(events(json!({"role": "assistant", "content": "hi"})), expected);
+    }
+
+    #[test]
+    fn string_content_of_other_types_is_not_answer_text() {
+        assert_eq!(
+            events(json!({"type": "title", "content": "Trip plan"})),
+            vec![CozeEvent::Title("Trip plan".to_string())]
+        );
+        assert!(matches!(events(json!({"type": "verbose", "content": "x"}))[..], [CozeEvent::Unknown(_)]));
+    }
+
+    #[test]
+    fn reads_titles_next_to_answers() {
+        assert_eq!(
+            events(json!({"type": "answer", "content": {"answer": "a", "title": "T"}})),
+            vec![CozeEvent::AnswerDelta("a".to_string()), CozeEvent::Title("T".to_string())]
+        );
+        assert_eq!(events(json!({"title": "Root"})), vec![CozeEvent::Title("Root".to_string())]);
+    }
+
+    #[test]
+    fn reads_tool_requests_and_responses() {
+        let request = events(json!({"type": "tool_request", "content": {"tool_request": {
+            "tool_call_id": "c1", "tool_name": "search", "parameters": {"q": "rust"}}}}));
+        assert_eq!(
+            request,
+            vec![CozeEvent::ToolRequest {
+                id: "c1".to_string(),
+                name: "search".to_string(),
+                input: "{\n  \"q\": \"rust\"\n}".to_string(),
+            }]
+        );
+
+        let failed = events(json!({"type": "tool_response", "content": {"tool_response": {
+            "tool_call_id": "c1", "code": 500, "result": "boom"}}}));
+        assert_eq!(
+            failed,
+            vec![CozeEvent::ToolResponse { id: "c1".to_string(), output: Some("boom".to_string()), success: false }]
+        );
+    }
+
+    #[test]
+    fn reads_legacy_tool_call_arrays() {
+        let decoded = events(json!({"content": {"tool_calls": [
+            {"id": "t1", "name": "lookup", "args": {"k": 1}},
+            {"name": "no id"}
+        ]}}));
+
+        assert_eq!(
+            decoded,
+            vec![CozeEvent::ToolRequest { id: "t1".to_string(), name: "lookup".to_string(), input: "{\"k\":1}".to_string() }]
+        );
+    }
+
+    #[test]
+    fn reports_errors_and_end_of_stream() {
+        assert_eq!(
+            events(json!({"type": "error", "content": {"error": {"code": 4000, "error_msg": "quota exceeded"}}})),
+            vec![CozeEvent::Error("quota exceeded".to_string())]
+        );
+        assert_eq!(
+            events(json!({"type": "message_end", "content": {"message_end": {"code": "0", "message": ""}}})),
+            vec![CozeEvent::Done]
+        );
+        assert_eq!(
+            events(json!({"type": "message_end", "content": {"message_end": {"code": "702", "message": "workflow failed"}}})),
+            vec![CozeEvent::Error("workflow failed".to_string()), CozeEvent::Done]
+        );
+    }
+
+    #[test]
+    fn reads_token_usage_under_either_key() {
+        let usage = TokenUsage { input_tokens: Some(12), output_tokens: Some(30), total_tokens: Some(42) };
+        assert_eq!(
+            events(json!({"type": "message_end", "content": {"message_end": {"code": "0",
+                "usage": {"input_count": 12, "output_count": 30, "token_count": 42}}}})),
+            vec![CozeEvent::Usage(usage), CozeEvent::Done]
+        );
+        assert_eq!(
+            events(json!({"type": "message_end", "content": {"message_end": {"code": "0",
+                "token_cost": {"prompt_tokens": 12, "completion_tokens": 30, "total_tokens": 42}}}})),
+            vec![CozeEvent::Usage(usage), CozeEvent::Done]
+        );
+        assert_eq!(
+            events(json!({"type": "message_end", "content": {"message_end": {"code": "0",
+                "usage": {"input_tokens": 12, "prompt_tokens": 99, "output_count": 30, "completion_tokens": 31, "total": 42},
+                "token_cost": {"token_count": 1}}}})),
+            vec![CozeEvent::Usage(usage), CozeEvent::Done],
+            "the first of several names for one count wins"
+        );
+        assert_eq!(
+            events(json!({"type": "message_end", "content": {"message_end": {"code": "0",
+                "usage": {}, "token_cost": {"input": 12, "output": 30, "total": 42}}}})),
+            vec![CozeEvent::Usage(usage), CozeEvent::Done]
+        );
+        assert_eq!(
+            events(json!({"type": "message_end", "content": {"message_end": {"code": "0", "usage": {}}}})),
+            vec![CozeEvent::Done],
+            "empty usage is not reported"
+        );
+    }
+
+    #[test]
+    fn lenient_model_keeps_content_the_typed_one_rejects() {
+        // `type: answer` without a `content` field does not fit the typed model
+        assert_eq!(
+            events(json!({"type": "answer", "answer": "root"})),
+            vec![CozeEvent::AnswerDelta("root".to_string())]
+        );
+        assert_eq!(
+            events(json!({"answer": "still read", "content": [1, 2]})),
+            vec![CozeEvent::AnswerDelta("still read".to_string())]
+        );
+    }
+
+    #[test]
+    fn legacy_fallbacks_are_logged_under_their_own_target() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(EnvFilter::new(format!("warn,{}=debug", UNCLASSIFIED_LOG_TARGET)))
+            .with(fmt::layer().with_ansi(false).with_writer(move || writer.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            decode(r#"{"type": "answer", "content": {"text": "moved"}, "answer": "root"}"#);
+            decode(r#"{"type": "title", "content": "typed"}"#);
+        });
+
+        let output = captured.output();
+        assert!(output.contains("stream payload read with the legacy model"), "{}", output);
+        assert!(output.contains("root"));
+        assert!(!output.contains("typed"), "{}", output);
+    }
+
+    #[test]
+    fn unclassified_payloads_are_kept_whole() {
+        let payload = json!({"type": "verbose", "content": {"data": "{}"}});
+        assert_eq!(events(payload.clone()), vec![CozeEvent::Unknown(payload)]);
+
+        assert!(events(json!({"type": "message_start", "content": {"message_start": {}}})).is_empty());
+    }
+
+    #[test]
+    fn invalid_json_is_unknown() {
+        assert_eq!(decode("not json"), vec![CozeEvent::Unknown(Value::String("not json".to_string()))]);
+    }
+
+    #[test]
+    fn truncates_huge_tool_output_on_a_char_boundary() {
+        let output = "é".repeat(MAX_TOOL_OUTPUT_BYTES);
+        let truncated = truncate_output(&output);
+
+        assert!(truncated.ends_with("...(truncated)"));
+        assert!(truncated.len() <= MAX_TOOL_OUTPUT_BYTES + "...(truncated)".len());
+    }
+
+    #[test]
+    fn accumulator_builds_the_reply() {
+        let mut reply = ReplyAccumulator::new();
+        let request = CozeEvent::ToolRequest { id: "c1".to_string(), name: "search".to_string(), input: "{}".to_string() };
+
+        assert!(matches!(reply.apply(request.clone()), Some(ReplyUpdate::ToolCall(_))));
+        assert_eq!(reply.apply(request), None, "duplicate requests are ignored");
+        assert_eq!(
+            reply.apply(CozeEvent::ToolResponse { id: "unknown".to_string(), output: None, success: true }),
+            None
+        );
+        let Some(ReplyUpdate::ToolResult(result)) =
+            reply.apply(CozeEvent::ToolResponse { id: "c1".to_string(), output: Some("ok".to_string()), success: true })
+        else {
+            panic!("expected a tool result");
+        };
+        assert_eq!(result.status, "success");
+
+        reply.apply(CozeEvent::AnswerDelta("Hello ".to_string()));
+        reply.apply(CozeEvent::AnswerDelta("world".to_string()));
+        reply.apply(CozeEvent::Title("Greeting".to_string()));
+        reply.apply(CozeEvent::Done);
+
+        assert_eq!(reply.content, "Hello world");
+        assert_eq!(reply.title.as_deref(), Some("Greeting"));
+        assert_eq!(reply.tool_calls().unwrap()[0].tool_output.as_deref(), Some("ok"));
+        assert!(reply.done);
+    }
+}
@@ -0,0 +1,202 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+// Marks a stored value as AES-256-GCM ciphertext: "enc:v1:" + base64(nonce || ciphertext)
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+// Shown in place of a token; update commands treat a value starting with it as "unchanged"
+pub const MASK: &str = "••••••••";
+
+#[derive(Debug)]
+pub enum CryptoError {
+    Io(std::io::Error),
+    /// The key file exists but is not a 256-bit key
+    InvalidKey,
+    /// The stored value could not be decrypted with this key
+    Corrupt,
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptoError::Io(e) => write!(f, "Failed to access token key file: {}", e),
+            CryptoError::InvalidKey => write!(f, "Token key file is invalid"),
+            CryptoError::Corrupt => write!(f, "Stored auth token could not be decrypted; re-enter it for this agent"),
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+impl From<std::io::Error> for CryptoError {
+    fn from(e: std::io::Error) -> Self {
+        CryptoError::Io(e)
+    }
+}
+
+/// Encrypts agent auth tokens at rest with a key kept outside the database
+pub struct TokenCipher {
+    cipher: Aes256Gcm,
+}
+
+impl TokenCipher {
+    /// Reads the key at `key_path`, creating it (readable by the current user only) on first run
+    pub fn load_or_create(key_path: &Path) -> Result<Self, CryptoError> {
+        let key_bytes = if key_path.exists() {
+            fs::read(key_path)?
+        } else {
+            let key = Aes256Gcm::generate_key(OsRng);
+            write_private_file(key_path, &key)?;
+            key.to_vec()
+        };
+
+        if key_bytes.len() != KEY_LEN {
+            return Err(CryptoError::InvalidKey);
+        }
+        Ok(Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_bytes)),
+        })
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, CryptoError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| CryptoError::Corrupt)?;
+
+        let mut payload = nonce.to_vec();
+        payload.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}", ENCRYPTED_PREFIX, BASE64.encode(payload)))
+    }
+
+    /// Decrypts a stored value; values written before encryption are returned as-is
+    pub fn decrypt(&self, stored: &str) -> Result<String, CryptoError> {
+        let Some(encoded) = stored.strip_prefix(ENCRYPTED_PREFIX) else {
+            return Ok(stored.to_string());
+        };
+
+        let payload = BASE64.decode(encoded).map_err(|_| CryptoError::Corrupt)?;
+        if payload.len() < NONCE_LEN {
+            return Err(CryptoError::Corrupt);
+        }
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| CryptoError::Corrupt)?;
+        String::from_utf8(plaintext).map_err(|_| CryptoError::Corrupt)
+    }
+
+    /// Display form of a stored token: the mask plus its last four characters
+    pub fn mask(&self, stored: &str) -> String {
+        match self.decrypt(stored) {
+            Ok(token) if token.chars().count() > 8 => {
+                let tail: String = token.chars().skip(token.chars().count() - 4).collect();
+                format!("{}{}", MASK, tail)
+            }
+            _ => MASK.to_string(),
+        }
+    }
+}
+
+pub fn is_encrypted(stored: &str) -> bool {
+    stored.starts_with(ENCRYPTED_PREFIX)
+}
+
+/// Whether a token coming back from the frontend is the masked value it was given
+pub fn is_masked(token: &str) -> bool {
+    token.starts_with(MASK)
+}
+
+#[cfg(unix)]
+fn write_private_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(contents)
+}
+
+// The app data directory is already private to the user on other platforms
+#[cfg(not(unix))]
+fn write_private_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut file = fs::OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_never_stores_plaintext() {
+        let dir = tempfile::tempdir().unwrap();
+        let cipher = TokenCipher::load_or_create(&dir.path().join("test.key")).unwrap();
+
+        let stored = cipher.encrypt("pat_secret_token_1234").unwrap();
+
+        assert!(is_encrypted(&stored));
+        assert!(!stored.contains("secret"));
+        assert_eq!(cipher.decrypt(&stored).unwrap(), "pat_secret_token_1234");
+        assert_ne!(stored, cipher.encrypt("pat_secret_token_1234").unwrap());
+    }
+
+    #[test]
+    fn reuses_existing_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let key_path = dir.path().join("test.key");
+        let stored = TokenCipher::load_or_create(&key_path).unwrap().encrypt("token").unwrap();
+
+        let reopened = TokenCipher::load_or_create(&key_path).unwrap();
+
+        assert_eq!(reopened.decrypt(&stored).unwrap(), "token");
+    }
+
+    #[test]
+    fn other_key_cannot_decrypt() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = TokenCipher::load_or_create(&dir.path().join("a.key")).unwrap();
+        let second = TokenCipher::load_or_create(&dir.path().join("b.key")).unwrap();
+
+        let stored = first.encrypt("token").unwrap();
+
+        assert!(matches!(second.decrypt(&stored), Err(CryptoError::Corrupt)));
+    }
+
+    #[test]
+    fn masks_all_but_the_last_four_characters() {
+        let dir = tempfile::tempdir().unwrap();
+        let cipher = TokenCipher::load_or_create(&dir.path().join("test.key")).unwrap();
+
+        let masked = cipher.mask(&cipher.encrypt("pat_secret_token_1234").unwrap());
+
+        assert_eq!(masked, format!("{}1234", MASK));
+        assert!(is_masked(&masked));
+        assert_eq!(cipher.mask(&cipher.encrypt("short").unwrap()), MASK);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn key_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let key_path = dir.path().join("test.key");
+        TokenCipher::load_or_create(&key_path).unwrap();
+
+        let mode = fs::metadata(&key_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
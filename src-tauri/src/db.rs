@@ -1,26 +1,161 @@
-use rusqlite::{Connection, Result};
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, Connection, Result, Transaction};
+use std::fmt;
+use std::path::Path;
 use tauri::{AppHandle, Manager};
 
-pub fn init_db(app: &AppHandle) -> Result<Connection> {
+/// One schema change. Migrations run in order and the database's
+/// `PRAGMA user_version` records how many have been applied.
+pub struct Migration {
+    pub description: &'static str,
+    pub apply: fn(&Transaction) -> Result<()>,
+}
+
+/// Every schema change ever shipped, oldest first. Only ever append to this list.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { description: "create agents, conversations and messages", apply: create_initial_tables },
+    Migration { description: "add messages.tool_calls", apply: add_message_tool_calls },
+    Migration { description: "add messages.status", apply: add_message_status },
+    Migration { description: "add agents.history_window", apply: add_agent_history_window },
+    Migration { description: "add full-text search index over messages", apply: create_message_search_index },
+    Migration { description: "add agent request timeout and retry overrides", apply: add_agent_request_overrides },
+    Migration { description: "link messages into a tree of reply versions", apply: add_message_tree },
+    Migration { description: "record which conversation a fork was copied from", apply: add_conversation_fork_origin },
+    Migration { description: "millisecond message timestamps and per-conversation sequence numbers", apply: add_message_sequence },
+    Migration { description: "add per-reply timing and token usage", apply: create_message_usage },
+    Migration { description: "millisecond conversation timestamps", apply: convert_conversation_timestamps },
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    /// The database was written by a newer version of the app
+    UnsupportedVersion { found: i64, supported: i64 },
+    /// A migration step failed and was rolled back
+    StepFailed { version: i64, description: &'static str, source: rusqlite::Error },
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "Database schema version {} is newer than this app supports ({}); please update the app",
+                found, supported
+            ),
+            MigrationError::StepFailed { version, description, source } => write!(
+                f,
+                "Database migration {} ({}) failed: {}",
+                version, description, source
+            ),
+            MigrationError::Sqlite(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MigrationError::StepFailed { source, .. } => Some(source),
+            MigrationError::Sqlite(e) => Some(e),
+            MigrationError::UnsupportedVersion { .. } => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for MigrationError {
+    fn from(e: rusqlite::Error) -> Self {
+        MigrationError::Sqlite(e)
+    }
+}
+
+pub type DbPool = Pool<SqliteConnectionManager>;
+pub type DbConnection = PooledConnection<SqliteConnectionManager>;
+
+// How long a connection waits on another writer before giving up with SQLITE_BUSY
+const BUSY_TIMEOUT_MS: u32 = 5_000;
+
+/// Opens the app database, brings its schema up to date and returns the shared pool
+pub fn init_db(app: &AppHandle) -> std::result::Result<DbPool, Box<dyn std::error::Error>> {
     let app_dir = app
         .path()
         .app_data_dir()
         .expect("Failed to get app data directory");
-    
+
     std::fs::create_dir_all(&app_dir).expect("Failed to create app data directory");
-    
-    let db_path = app_dir.join("coze_studio.db");
-    let conn = Connection::open(db_path)?;
-    
-    // Enable foreign keys
-    conn.execute("PRAGMA foreign_keys = ON", [])?;
-    
-    create_tables(&conn)?;
-    
-    Ok(conn)
-}
-
-fn create_tables(conn: &Connection) -> Result<()> {
+
+    let pool = open_pool(&app_dir.join("coze_studio.db"))?;
+    let mut conn = pool.get()?;
+    migrate(&mut conn)?;
+
+    Ok(pool)
+}
+
+/// Pool over a database file in WAL mode, so readers don't block the writer
+/// while a reply is being streamed
+pub fn open_pool(db_path: &Path) -> std::result::Result<DbPool, r2d2::Error> {
+    let manager = SqliteConnectionManager::file(db_path).with_init(|conn| {
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "busy_timeout", BUSY_TIMEOUT_MS)?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        Ok(())
+    });
+    Pool::builder().build(manager)
+}
+
+/// Brings the schema up to the latest version in `MIGRATIONS`
+pub fn migrate(conn: &mut Connection) -> std::result::Result<(), MigrationError> {
+    run_migrations(conn, MIGRATIONS)
+}
+
+/// Applies every migration past the database's current version, each in its own
+/// transaction so a failing step leaves the database at the last good version.
+fn run_migrations(conn: &mut Connection, migrations: &[Migration]) -> std::result::Result<(), MigrationError> {
+    let current: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    let supported = migrations.len() as i64;
+
+    if current > supported {
+        return Err(MigrationError::UnsupportedVersion { found: current, supported });
+    }
+
+    for (index, migration) in migrations.iter().enumerate().skip(current as usize) {
+        let version = index as i64 + 1;
+        let step_failed = |source| MigrationError::StepFailed {
+            version,
+            description: migration.description,
+            source,
+        };
+
+        let tx = conn.transaction().map_err(step_failed)?;
+        (migration.apply)(&tx).map_err(step_failed)?;
+        tx.pragma_update(None, "user_version", version).map_err(step_failed)?;
+        tx.commit().map_err(step_failed)?;
+        tracing::info!(version, description = migration.description, "applied migration");
+    }
+
+    Ok(())
+}
+
+fn column_exists(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2",
+        params![table, column],
+        |row| row.get::<_, i64>(0),
+    )
+    .map(|count| count > 0)
+}
+
+/// Adds a column unless an earlier ad-hoc `ALTER TABLE` already did
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    if !column_exists(conn, table, column)? {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+    }
+    Ok(())
+}
+
+// Databases created before versioning already have these tables, hence IF NOT EXISTS
+fn create_initial_tables(conn: &Transaction) -> Result<()> {
     // Agents table
     conn.execute(
         "CREATE TABLE IF NOT EXISTS agents (
@@ -33,7 +168,7 @@ fn create_tables(conn: &Connection) -> Result<()> {
         )",
         [],
     )?;
-    
+
     // Conversations table
     conn.execute(
         "CREATE TABLE IF NOT EXISTS conversations (
@@ -45,7 +180,7 @@ fn create_tables(conn: &Connection) -> Result<()> {
         )",
         [],
     )?;
-    
+
     // Messages table
     conn.execute(
         "CREATE TABLE IF NOT EXISTS messages (
@@ -53,27 +188,373 @@ fn create_tables(conn: &Connection) -> Result<()> {
             conversation_id TEXT NOT NULL,
             role TEXT NOT NULL,
             content TEXT NOT NULL,
-            tool_calls TEXT,
             created_at INTEGER NOT NULL,
             FOREIGN KEY (conversation_id) REFERENCES conversations(id) ON DELETE CASCADE
         )",
         [],
     )?;
 
-    // Migration: ensure tool_calls column exists
-    let _ = conn.execute("ALTER TABLE messages ADD COLUMN tool_calls TEXT", []);
-    
     Ok(())
 }
 
-pub fn get_db_connection(app: &AppHandle) -> Result<Connection> {
-    let app_dir = app
-        .path()
-        .app_data_dir()
-        .expect("Failed to get app data directory");
-    
-    let db_path = app_dir.join("coze_studio.db");
-    let conn = Connection::open(db_path)?;
-    conn.execute("PRAGMA foreign_keys = ON", [])?;
-    Ok(conn)
+fn add_message_tool_calls(conn: &Transaction) -> Result<()> {
+    add_column_if_missing(conn, "messages", "tool_calls", "TEXT")
+}
+
+fn add_message_status(conn: &Transaction) -> Result<()> {
+    add_column_if_missing(conn, "messages", "status", "TEXT NOT NULL DEFAULT 'complete'")
+}
+
+fn add_agent_history_window(conn: &Transaction) -> Result<()> {
+    add_column_if_missing(conn, "agents", "history_window", "INTEGER NOT NULL DEFAULT 10")
+}
+
+fn add_agent_request_overrides(conn: &Transaction) -> Result<()> {
+    add_column_if_missing(conn, "agents", "connect_timeout_secs", "INTEGER")?;
+    add_column_if_missing(conn, "agents", "read_timeout_secs", "INTEGER")?;
+    add_column_if_missing(conn, "agents", "max_retries", "INTEGER")
+}
+
+// Each message points at the one it answers or follows, so a turn can have several
+// versions (siblings). The conversation remembers the leaf of the path on screen.
+// Existing conversations become a single chain in creation order.
+fn add_message_tree(conn: &Transaction) -> Result<()> {
+    add_column_if_missing(conn, "messages", "parent_id", "TEXT")?;
+    add_column_if_missing(conn, "messages", "version", "INTEGER NOT NULL DEFAULT 1")?;
+    add_column_if_missing(conn, "conversations", "active_message_id", "TEXT")?;
+    // Each message follows the one created before it; one pass over the table
+    // rather than a lookup per message
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_messages_parent ON messages(conversation_id, parent_id);
+
+        UPDATE messages SET parent_id = ordered.previous_id
+        FROM (
+            SELECT rowid AS message_rowid,
+                   LAG(id) OVER (PARTITION BY conversation_id ORDER BY created_at, rowid) AS previous_id
+            FROM messages
+        ) AS ordered
+        WHERE messages.rowid = ordered.message_rowid AND messages.parent_id IS NULL;
+
+        UPDATE conversations SET active_message_id = latest.id
+        FROM (
+            SELECT conversation_id, id,
+                   ROW_NUMBER() OVER (PARTITION BY conversation_id ORDER BY created_at DESC, rowid DESC) AS position
+            FROM messages
+        ) AS latest
+        WHERE latest.conversation_id = conversations.id
+          AND latest.position = 1
+          AND conversations.active_message_id IS NULL;",
+    )
+}
+
+// A forked conversation keeps the ids of the conversation and message it was copied
+// from. They are not foreign keys: the fork outlives a deleted original.
+fn add_conversation_fork_origin(conn: &Transaction) -> Result<()> {
+    add_column_if_missing(conn, "conversations", "forked_from_conversation_id", "TEXT")?;
+    add_column_if_missing(conn, "conversations", "forked_from_message_id", "TEXT")
+}
+
+// Timestamps before this were whole seconds; anything below 10^11 cannot be a
+// millisecond value from after 1973. Existing messages are numbered in the order
+// they were created, ties broken by insertion order.
+fn add_message_sequence(conn: &Transaction) -> Result<()> {
+    add_column_if_missing(conn, "messages", "seq", "INTEGER NOT NULL DEFAULT 0")?;
+    conn.execute_batch(
+        "UPDATE messages SET created_at = created_at * 1000 WHERE created_at < 100000000000;
+
+        UPDATE messages SET seq = numbered.seq
+        FROM (
+            SELECT rowid AS message_rowid,
+                   ROW_NUMBER() OVER (PARTITION BY conversation_id ORDER BY created_at, rowid) AS seq
+            FROM messages
+        ) AS numbered
+        WHERE messages.rowid = numbered.message_rowid;
+
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_messages_seq ON messages(conversation_id, seq);",
+    )
+}
+
+// Searchable text of a message's tool calls: name, input and output of each
+const TOOL_CALL_TEXT_NEW: &str = "CASE WHEN json_valid(new.tool_calls) THEN (
+    SELECT group_concat(
+        ifnull(json_extract(value, '$.tool_name'), '') || ' ' ||
+        ifnull(json_extract(value, '$.tool_input'), '') || ' ' ||
+        ifnull(json_extract(value, '$.tool_output'), ''),
+        char(10))
+    FROM json_each(new.tool_calls)
+) END";
+
+// The index keeps its own copy of the text, keyed by the message's rowid.
+// Trigram tokenizing matches substrings, which also covers CJK text without spaces.
+fn create_message_search_index(conn: &Transaction) -> Result<()> {
+    conn.execute_batch(&format!(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+            content,
+            tool_calls,
+            tokenize = 'trigram'
+        );
+
+        CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages BEGIN
+            INSERT INTO messages_fts (rowid, content, tool_calls)
+            VALUES (new.rowid, new.content, {tool_text});
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS messages_fts_delete AFTER DELETE ON messages BEGIN
+            DELETE FROM messages_fts WHERE rowid = old.rowid;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS messages_fts_update AFTER UPDATE OF content, tool_calls ON messages BEGIN
+            DELETE FROM messages_fts WHERE rowid = old.rowid;
+            INSERT INTO messages_fts (rowid, content, tool_calls)
+            VALUES (new.rowid, new.content, {tool_text});
+        END;
+
+        DELETE FROM messages_fts;
+        INSERT INTO messages_fts (rowid, content, tool_calls)
+        SELECT rowid, content, {backfill_text} FROM messages;",
+        tool_text = TOOL_CALL_TEXT_NEW,
+        backfill_text = TOOL_CALL_TEXT_NEW.replace("new.", "messages."),
+    ))
+}
+
+// Rows are keyed by message but carry their own agent and conversation ids, so
+// usage stays counted after either is deleted
+fn create_message_usage(conn: &Transaction) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS message_usage (
+            message_id TEXT PRIMARY KEY,
+            agent_id TEXT NOT NULL,
+            conversation_id TEXT NOT NULL,
+            started_at INTEGER NOT NULL,
+            first_token_ms INTEGER,
+            duration_ms INTEGER NOT NULL,
+            response_bytes INTEGER NOT NULL,
+            response_chars INTEGER NOT NULL,
+            input_tokens INTEGER,
+            output_tokens INTEGER,
+            total_tokens INTEGER
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_message_usage_started ON message_usage(started_at);
+        CREATE INDEX IF NOT EXISTS idx_message_usage_agent ON message_usage(agent_id, started_at);",
+    )
+}
+
+// Same cut-off as for messages in `add_message_sequence`
+fn convert_conversation_timestamps(conn: &Transaction) -> Result<()> {
+    conn.execute("UPDATE conversations SET created_at = created_at * 1000 WHERE created_at < 100000000000", [])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+    // Schema written by `create_tables` before migrations were versioned
+    const UNVERSIONED_SCHEMA: &str = "
+        CREATE TABLE agents (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            api_url TEXT NOT NULL,
+            auth_token TEXT NOT NULL,
+            project_id TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+        CREATE TABLE conversations (
+            id TEXT PRIMARY KEY,
+            agent_id TEXT NOT NULL,
+            title TEXT,
+            created_at INTEGER NOT NULL,
+            FOREIGN KEY (agent_id) REFERENCES agents(id) ON DELETE CASCADE
+        );
+        CREATE TABLE messages (
+            id TEXT PRIMARY KEY,
+            conversation_id TEXT NOT NULL,
+            role TEXT NOT NULL,
+            content TEXT NOT NULL,
+            tool_calls TEXT,
+            created_at INTEGER NOT NULL,
+            FOREIGN KEY (conversation_id) REFERENCES conversations(id) ON DELETE CASCADE
+        );
+        INSERT INTO agents VALUES ('a1', 'Analyst', 'https://example.com/stream_run', 'token', 'p1', 1700000000);
+        INSERT INTO conversations VALUES ('c1', 'a1', 'Old chat', 1700000001);
+        INSERT INTO messages VALUES ('m1', 'c1', 'user', 'hello', NULL, 1700000002);
+        INSERT INTO messages VALUES ('m2', 'c1', 'assistant', 'hi there', '[]', 1700000003);
+    ";
+
+    fn user_version(conn: &Connection) -> i64 {
+        conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap()
+    }
+
+    fn failing_step(conn: &Transaction) -> Result<()> {
+        conn.execute("CREATE TABLE half_done (id TEXT)", [])?;
+        conn.execute("ALTER TABLE no_such_table ADD COLUMN x TEXT", [])?;
+        Ok(())
+    }
+
+    #[test]
+    fn fresh_database_reaches_latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+
+        assert_eq!(user_version(&conn), SCHEMA_VERSION);
+        assert!(column_exists(&conn, "messages", "tool_calls").unwrap());
+        assert!(column_exists(&conn, "messages", "status").unwrap());
+        assert!(column_exists(&conn, "agents", "history_window").unwrap());
+    }
+
+    #[test]
+    fn upgrades_unversioned_database_and_keeps_data() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(UNVERSIONED_SCHEMA).unwrap();
+
+        migrate(&mut conn).unwrap();
+
+        assert_eq!(user_version(&conn), SCHEMA_VERSION);
+        let (title, created_at, window): (String, i64, i64) = conn
+            .query_row(
+                "SELECT c.title, c.created_at, a.history_window FROM conversations c JOIN agents a ON a.id = c.agent_id",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(title, "Old chat");
+        assert_eq!(created_at, 1_700_000_001_000);
+        assert_eq!(window, 10);
+
+        let statuses: Vec<String> = conn
+            .prepare("SELECT status FROM messages ORDER BY created_at")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(statuses, vec!["complete", "complete"]);
+
+        let indexed: i64 = conn
+            .query_row("SELECT COUNT(*) FROM messages_fts WHERE messages_fts MATCH 'there'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(indexed, 1);
+
+        let (parent, active): (Option<String>, Option<String>) = conn
+            .query_row(
+                "SELECT m.parent_id, c.active_message_id FROM messages m JOIN conversations c ON c.id = m.conversation_id
+                 WHERE m.id = 'm2'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(parent.as_deref(), Some("m1"));
+        assert_eq!(active.as_deref(), Some("m2"));
+
+        let numbered: Vec<(String, i64, i64)> = conn
+            .prepare("SELECT id, seq, created_at FROM messages ORDER BY seq")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            numbered,
+            vec![("m1".to_string(), 1, 1_700_000_002_000), ("m2".to_string(), 2, 1_700_000_003_000)]
+        );
+    }
+
+    #[test]
+    fn sequence_backfill_keeps_insertion_order_within_a_second() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(&format!(
+            "{}
+            INSERT INTO conversations VALUES ('c2', 'a1', 'Fast chat', 1700000010);
+            INSERT INTO messages VALUES ('q', 'c2', 'user', 'quick?', NULL, 1700000020);
+            INSERT INTO messages VALUES ('r', 'c2', 'assistant', 'yes', NULL, 1700000020);
+            INSERT INTO messages VALUES ('q2', 'c2', 'user', 'again?', NULL, 1700000020);",
+            UNVERSIONED_SCHEMA
+        ))
+        .unwrap();
+
+        migrate(&mut conn).unwrap();
+
+        let order: Vec<String> = conn
+            .prepare("SELECT id FROM messages WHERE conversation_id = 'c2' ORDER BY seq")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(order, vec!["q", "r", "q2"]);
+        let duplicate = conn.execute(
+            "INSERT INTO messages (id, conversation_id, role, content, created_at, seq) VALUES ('x', 'c2', 'user', '', 0, 3)",
+            [],
+        );
+        assert!(duplicate.is_err());
+    }
+
+    #[test]
+    fn upgrades_database_missing_tool_calls() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(&UNVERSIONED_SCHEMA.replace("tool_calls TEXT,", "").replace(", '[]',", ",").replace(", NULL,", ","))
+            .unwrap();
+        assert!(!column_exists(&conn, "messages", "tool_calls").unwrap());
+
+        migrate(&mut conn).unwrap();
+
+        assert!(column_exists(&conn, "messages", "tool_calls").unwrap());
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn migrating_twice_is_a_no_op() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn refuses_database_from_newer_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(UNVERSIONED_SCHEMA).unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+
+        let err = migrate(&mut conn).unwrap_err();
+
+        assert!(matches!(err, MigrationError::UnsupportedVersion { found, .. } if found == SCHEMA_VERSION + 1));
+        assert!(!column_exists(&conn, "messages", "status").unwrap());
+    }
+
+    #[test]
+    fn pooled_connections_use_wal_and_busy_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = open_pool(&dir.path().join("test.db")).unwrap();
+        let conn = pool.get().unwrap();
+
+        let journal_mode: String = conn.pragma_query_value(None, "journal_mode", |row| row.get(0)).unwrap();
+        let busy_timeout: i64 = conn.pragma_query_value(None, "busy_timeout", |row| row.get(0)).unwrap();
+        let foreign_keys: i64 = conn.pragma_query_value(None, "foreign_keys", |row| row.get(0)).unwrap();
+        assert_eq!(journal_mode, "wal");
+        assert_eq!(busy_timeout, BUSY_TIMEOUT_MS as i64);
+        assert_eq!(foreign_keys, 1);
+    }
+
+    #[test]
+    fn failed_step_rolls_back_and_keeps_previous_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let migrations = [
+            Migration { description: "create tables", apply: create_initial_tables },
+            Migration { description: "broken", apply: failing_step },
+        ];
+
+        let err = run_migrations(&mut conn, &migrations).unwrap_err();
+
+        assert!(matches!(err, MigrationError::StepFailed { version: 2, .. }));
+        assert_eq!(user_version(&conn), 1);
+        let leftovers: i64 = conn
+            .query_row("SELECT COUNT(*) FROM sqlite_master WHERE name = 'half_done'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(leftovers, 0);
+    }
 }
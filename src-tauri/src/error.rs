@@ -0,0 +1,299 @@
+//! The error every command returns.
+//!
+//! It reaches the frontend as an object the UI can branch on rather than a bare
+//! string: `{ "code": "upstream", "message": "...", "retryable": true, "status": 502, "body": "..." }`.
+
+use serde::ser::{Serialize, Serializer};
+use reqwest::{Response, StatusCode};
+use rusqlite::ErrorCode;
+use std::fmt;
+use std::io;
+use chrono::Utc;
+use crate::backup::BackupError;
+use crate::crypto::CryptoError;
+use crate::db::MigrationError;
+use crate::http::retry_after;
+
+// Characters of an error response body kept for the UI
+const UPSTREAM_BODY_CHARS: usize = 2000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppError {
+    /// A record the request refers to does not exist
+    NotFound(String),
+    /// The request, or a file it names, is not acceptable
+    Validation(String),
+    /// The agent's token was rejected, or the stored one cannot be read
+    Auth(String),
+    /// The agent's endpoint is throttling requests
+    RateLimited { message: String, retry_after_secs: Option<u64> },
+    /// The endpoint could not be reached, timed out or broke off the stream
+    Network(String),
+    /// The endpoint answered with an error status
+    Upstream { status: u16, body: String },
+    /// The agent reported an error inside an otherwise successful stream
+    Agent(String),
+    /// The local database failed; `busy` when another writer held it for too long
+    Database { message: String, busy: bool },
+    /// Reading or writing a local file failed for a reason other than its path
+    Io(String),
+    /// The user stopped the request, or it was refused while a reply is streaming
+    Cancelled(String),
+}
+
+impl AppError {
+    pub fn not_found(message: impl Into<String>) -> Self {
+        AppError::NotFound(message.into())
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        AppError::Validation(message.into())
+    }
+
+    /// Reading or writing a file the user picked failed
+    pub fn file(action: &str, path: &str, e: io::Error) -> Self {
+        Self::from_io(format!("Failed to {} {}: {}", action, path, e), e.kind())
+    }
+
+    // Only a path that cannot name a usable file is the user's input being wrong;
+    // permissions, a full disk and the like are not
+    fn from_io(message: String, kind: io::ErrorKind) -> Self {
+        match kind {
+            io::ErrorKind::NotFound => AppError::NotFound(message),
+            io::ErrorKind::InvalidInput
+            | io::ErrorKind::InvalidFilename
+            | io::ErrorKind::IsADirectory
+            | io::ErrorKind::NotADirectory => AppError::Validation(message),
+            _ => AppError::Io(message),
+        }
+    }
+
+    /// Classifies a response with an error status, keeping the start of its body
+    pub async fn from_response(response: Response) -> Self {
+        let status = response.status();
+        let retry_after_secs = retry_after(response.headers(), Utc::now()).map(|d| d.as_secs());
+        let text = response.text().await.unwrap_or_default();
+        let body: String = text.trim().chars().take(UPSTREAM_BODY_CHARS).collect();
+
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                AppError::Auth(format!("The agent rejected the token ({}): {}", status, or_empty(&body)))
+            }
+            StatusCode::TOO_MANY_REQUESTS => AppError::RateLimited {
+                message: format!("The agent is rate limiting requests: {}", or_empty(&body)),
+                retry_after_secs,
+            },
+            _ => AppError::Upstream { status: status.as_u16(), body },
+        }
+    }
+
+    /// Stable identifier of the kind of error
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::Validation(_) => "validation",
+            AppError::Auth(_) => "auth",
+            AppError::RateLimited { .. } => "rate_limited",
+            AppError::Network(_) => "network",
+            AppError::Upstream { .. } => "upstream",
+            AppError::Agent(_) => "agent",
+            AppError::Database { .. } => "database",
+            AppError::Io(_) => "io",
+            AppError::Cancelled(_) => "cancelled",
+        }
+    }
+
+    /// Whether the same request may succeed if simply tried again later
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            AppError::RateLimited { .. } | AppError::Network(_) => true,
+            AppError::Upstream { status, .. } => *status >= 500,
+            AppError::Database { busy, .. } => *busy,
+            AppError::NotFound(_)
+            | AppError::Validation(_)
+            | AppError::Auth(_)
+            | AppError::Agent(_)
+            | AppError::Io(_)
+            | AppError::Cancelled(_) => false,
+        }
+    }
+}
+
+fn or_empty(body: &str) -> &str {
+    if body.is_empty() { "empty body" } else { body }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NotFound(message)
+            | AppError::Validation(message)
+            | AppError::Auth(message)
+            | AppError::Network(message)
+            | AppError::RateLimited { message, .. }
+            | AppError::Database { message, .. }
+            | AppError::Io(message)
+            | AppError::Cancelled(message) => write!(f, "{}", message),
+            AppError::Agent(message) => write!(f, "The agent reported an error: {}", message),
+            AppError::Upstream { status, body } if body.is_empty() => write!(f, "API request failed: {}", status),
+            AppError::Upstream { status, body } => write!(f, "API request failed: {}: {}", status, body),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+// What the frontend receives
+#[derive(serde::Serialize)]
+struct ErrorPayload<'a> {
+    code: &'static str,
+    message: String,
+    retryable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after_secs: Option<u64>,
+}
+
+impl Serialize for AppError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (status, body, retry_after_secs) = match self {
+            AppError::Upstream { status, body } => (Some(*status), Some(body.as_str()), None),
+            AppError::RateLimited { retry_after_secs, .. } => (Some(429), None, *retry_after_secs),
+            _ => (None, None, None),
+        };
+        ErrorPayload {
+            code: self.code(),
+            message: self.to_string(),
+            retryable: self.is_retryable(),
+            status,
+            body,
+            retry_after_secs,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl From<rusqlite::Error> for AppError {
+    fn from(e: rusqlite::Error) -> Self {
+        let busy = matches!(e.sqlite_error_code(), Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked));
+        AppError::Database { message: e.to_string(), busy }
+    }
+}
+
+// The pool only fails when no connection came free in time
+impl From<r2d2::Error> for AppError {
+    fn from(e: r2d2::Error) -> Self {
+        AppError::Database { message: e.to_string(), busy: true }
+    }
+}
+
+impl From<MigrationError> for AppError {
+    fn from(e: MigrationError) -> Self {
+        match e {
+            MigrationError::Sqlite(e) => e.into(),
+            other => AppError::Database { message: other.to_string(), busy: false },
+        }
+    }
+}
+
+impl From<BackupError> for AppError {
+    fn from(e: BackupError) -> Self {
+        match e {
+            BackupError::Sqlite(e) => e.into(),
+            // Migrating a restored file only fails when the file is the problem
+            BackupError::Migration(_) | BackupError::NotABackup(_) => AppError::Validation(e.to_string()),
+            BackupError::Io(io) => AppError::from_io(io.to_string(), io.kind()),
+        }
+    }
+}
+
+impl From<CryptoError> for AppError {
+    fn from(e: CryptoError) -> Self {
+        AppError::Auth(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for AppError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_builder() {
+            AppError::Validation(format!("Invalid request: {}", e))
+        } else if e.is_timeout() {
+            AppError::Network(format!("Request timed out: {}", e))
+        } else if e.is_connect() {
+            AppError::Network(format!("Could not connect: {}", e))
+        } else if let Some(status) = e.status() {
+            AppError::Upstream { status: status.as_u16(), body: String::new() }
+        } else {
+            AppError::Network(format!("Request failed: {}", e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn serializes_code_message_and_retryability() {
+        let upstream = AppError::Upstream { status: 502, body: "bad gateway".to_string() };
+        assert_eq!(
+            serde_json::to_value(&upstream).unwrap(),
+            json!({
+                "code": "upstream",
+                "message": "API request failed: 502: bad gateway",
+                "retryable": true,
+                "status": 502,
+                "body": "bad gateway",
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(AppError::not_found("Agent not found")).unwrap(),
+            json!({"code": "not_found", "message": "Agent not found", "retryable": false})
+        );
+        let limited = AppError::RateLimited { message: "slow down".to_string(), retry_after_secs: Some(7) };
+        assert_eq!(serde_json::to_value(&limited).unwrap()["retry_after_secs"], 7);
+    }
+
+    #[test]
+    fn only_transient_failures_are_retryable() {
+        assert!(AppError::Network("reset".to_string()).is_retryable());
+        assert!(!AppError::Upstream { status: 400, body: String::new() }.is_retryable());
+        assert!(!AppError::Auth("rejected".to_string()).is_retryable());
+        assert!(!AppError::Cancelled("stopped".to_string()).is_retryable());
+        assert!(!AppError::Agent("quota exceeded".to_string()).is_retryable());
+    }
+
+    #[test]
+    fn locked_database_is_retryable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("locked.db");
+        let holder = rusqlite::Connection::open(&path).unwrap();
+        holder.execute_batch("CREATE TABLE t (x); BEGIN EXCLUSIVE; INSERT INTO t VALUES (1);").unwrap();
+        let other = rusqlite::Connection::open(&path).unwrap();
+        other.busy_timeout(std::time::Duration::ZERO).unwrap();
+
+        let err = AppError::from(other.execute("INSERT INTO t VALUES (2)", []).unwrap_err());
+
+        assert!(matches!(err, AppError::Database { busy: true, .. }));
+        assert!(err.is_retryable());
+        let missing = AppError::from(holder.execute("INSERT INTO missing VALUES (1)", []).unwrap_err());
+        assert!(!missing.is_retryable());
+    }
+
+    #[test]
+    fn file_errors_are_classified_by_kind() {
+        let err = AppError::file("read", "/nowhere.json", io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(err.code(), "not_found");
+        assert!(err.to_string().starts_with("Failed to read /nowhere.json"));
+        let err = AppError::file("write", "/root.json", io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(err.code(), "io");
+        let err = AppError::file("write", "/full.json", io::Error::from(io::ErrorKind::StorageFull));
+        assert_eq!(err.code(), "io");
+        let err = AppError::file("read", "/tmp", io::Error::from(io::ErrorKind::IsADirectory));
+        assert_eq!(err.code(), "validation");
+    }
+}
@@ -0,0 +1,307 @@
+//! Conversation export as Markdown, lossless JSON or a self-contained HTML page.
+//!
+//! Markdown and HTML show the path on screen when exported; JSON keeps the whole
+//! message tree so it can be imported again.
+
+use serde::{Deserialize, Serialize};
+use rusqlite::{Connection, Result as SqlResult};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use crate::agent::Agent;
+use crate::chat::{Conversation, Message, ToolCall};
+
+/// Bumped whenever the JSON layout changes incompatibly.
+/// Version 2: message timestamps are milliseconds rather than seconds.
+/// Version 3: so are the conversation's and the export's.
+pub const EXPORT_FORMAT_VERSION: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    Html,
+}
+
+/// The agent a conversation belongs to, without its endpoint or token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedAgent {
+    pub id: String,
+    pub name: String,
+    pub project_id: String,
+}
+
+/// Every timestamp in it, `exported_at` and each `created_at`, is milliseconds
+/// since the epoch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationExport {
+    pub format_version: u32,
+    pub exported_at: i64,
+    pub agent: ExportedAgent,
+    pub conversation: Conversation,
+    // Last message of the path shown when exported
+    pub active_message_id: Option<String>,
+    // Every message, all branches included, oldest first
+    pub messages: Vec<Message>,
+}
+
+impl ConversationExport {
+    /// Everything needed to export the conversation; `None` if it does not exist
+    pub fn load(conn: &Connection, conversation_id: &str) -> SqlResult<Option<Self>> {
+        let Some(conversation) = Conversation::get_by_id(conn, conversation_id)? else {
+            return Ok(None);
+        };
+        let agent = match Agent::get_by_id(conn, &conversation.agent_id)? {
+            Some(agent) => ExportedAgent { id: agent.id, name: agent.name, project_id: agent.project_id },
+            None => ExportedAgent {
+                id: conversation.agent_id.clone(),
+                name: "Unknown agent".to_string(),
+                project_id: String::new(),
+            },
+        };
+
+        Ok(Some(Self {
+            format_version: EXPORT_FORMAT_VERSION,
+            exported_at: Utc::now().timestamp_millis(),
+            agent,
+            active_message_id: Conversation::active_message_id(conn, conversation_id)?,
+            messages: Message::get_all(conn, conversation_id)?,
+            conversation,
+        }))
+    }
+
+    /// The messages from the first one to the active one, oldest first
+    pub fn active_path(&self) -> Vec<&Message> {
+        let by_id: HashMap<&str, &Message> = self.messages.iter().map(|m| (m.id.as_str(), m)).collect();
+        let mut path = Vec::new();
+        let mut next = self.active_message_id.as_deref();
+        while let Some(message) = next.and_then(|id| by_id.get(id)) {
+            path.push(*message);
+            next = message.parent_id.as_deref();
+        }
+        path.reverse();
+        path
+    }
+
+    fn title(&self) -> &str {
+        self.conversation.title.as_deref().unwrap_or("Untitled Conversation")
+    }
+
+    fn speaker<'a>(&'a self, message: &Message) -> &'a str {
+        if message.role == "user" { "You" } else { &self.agent.name }
+    }
+}
+
+impl ExportFormat {
+    pub fn render(self, export: &ConversationExport) -> String {
+        match self {
+            ExportFormat::Markdown => to_markdown(export),
+            ExportFormat::Json => serde_json::to_string_pretty(export).unwrap_or_default(),
+            ExportFormat::Html => to_html(export),
+        }
+    }
+}
+
+pub fn to_markdown(export: &ConversationExport) -> String {
+    let mut md = String::new();
+    md.push_str(&format!("# {}\n\n", export.title()));
+    md.push_str(&format!("- Agent: {}\n", export.agent.name));
+    md.push_str(&format!("- Created: {}\n", format_timestamp(export.conversation.created_at)));
+    md.push_str(&format!("- Exported: {}\n\n", format_timestamp(export.exported_at)));
+    md.push_str("---\n\n");
+
+    for message in export.active_path() {
+        md.push_str(&format!("### {} · {}", export.speaker(message), format_timestamp(message.created_at)));
+        if message.status == "interrupted" {
+            md.push_str(" (interrupted)");
+        }
+        md.push_str("\n\n");
+
+        // Tool calls, as <details> blocks most Markdown renderers collapse
+        for tool_call in message.tool_calls.iter().flatten() {
+            md.push_str(&format!("<details>\n<summary>Tool call: {}</summary>\n\n", tool_call_label(tool_call)));
+            md.push_str(&format!("**Input**\n\n{}\n\n", fenced(&tool_call.tool_input)));
+            if let Some(output) = &tool_call.tool_output {
+                md.push_str(&format!("**Output**\n\n{}\n\n", fenced(output)));
+            }
+            md.push_str("</details>\n\n");
+        }
+
+        md.push_str(&format!("{}\n\n---\n\n", message.content));
+    }
+
+    md
+}
+
+const HTML_STYLE: &str = "
+body { font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', sans-serif; max-width: 860px; margin: 2rem auto; padding: 0 1rem; color: #1e293b; line-height: 1.6; }
+header { border-bottom: 1px solid #e2e8f0; margin-bottom: 1.5rem; }
+header p { color: #64748b; margin: 0.25rem 0; }
+.message { margin: 1rem 0; padding: 0.75rem 1rem; border-radius: 10px; background: #f8fafc; }
+.message.user { background: #eef2ff; }
+.meta { font-size: 0.85rem; color: #64748b; margin-bottom: 0.5rem; }
+.meta strong { color: #1e293b; }
+.content { white-space: pre-wrap; word-wrap: break-word; }
+details { margin: 0.5rem 0; border: 1px solid #e2e8f0; border-radius: 6px; padding: 0.25rem 0.75rem; background: #fff; }
+summary { cursor: pointer; font-size: 0.9rem; }
+pre { white-space: pre-wrap; word-wrap: break-word; background: #f1f5f9; padding: 0.5rem; border-radius: 4px; font-size: 0.85rem; }
+";
+
+pub fn to_html(export: &ConversationExport) -> String {
+    let title = escape_html(export.title());
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n",
+        title, HTML_STYLE
+    );
+    html.push_str(&format!(
+        "<header>\n<h1>{}</h1>\n<p>Agent: {}</p>\n<p>Created: {}</p>\n<p>Exported: {}</p>\n</header>\n",
+        title,
+        escape_html(&export.agent.name),
+        format_timestamp(export.conversation.created_at),
+        format_timestamp(export.exported_at)
+    ));
+
+    for message in export.active_path() {
+        html.push_str(&format!(
+            "<section class=\"message {}\">\n<div class=\"meta\"><strong>{}</strong> · {}{}</div>\n",
+            if message.role == "user" { "user" } else { "assistant" },
+            escape_html(export.speaker(message)),
+            format_timestamp(message.created_at),
+            if message.status == "interrupted" { " (interrupted)" } else { "" }
+        ));
+        for tool_call in message.tool_calls.iter().flatten() {
+            html.push_str(&format!(
+                "<details>\n<summary>Tool call: {}</summary>\n<p>Input</p>\n<pre>{}</pre>\n",
+                escape_html(&tool_call_label(tool_call)),
+                escape_html(&tool_call.tool_input)
+            ));
+            if let Some(output) = &tool_call.tool_output {
+                html.push_str(&format!("<p>Output</p>\n<pre>{}</pre>\n", escape_html(output)));
+            }
+            html.push_str("</details>\n");
+        }
+        html.push_str(&format!("<div class=\"content\">{}</div>\n</section>\n", escape_html(&message.content)));
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn tool_call_label(tool_call: &ToolCall) -> String {
+    format!("{} ({})", tool_call.tool_name, tool_call.status)
+}
+
+fn format_timestamp(millis: i64) -> String {
+    DateTime::from_timestamp_millis(millis)
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| millis.to_string())
+}
+
+/// Wraps text in a code fence longer than any backtick run inside it
+fn fenced(text: &str) -> String {
+    let longest_run = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    format!("{}\n{}\n{}", fence, text, fence)
+}
+
+fn escape_html(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            _ => html.push(c),
+        }
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::setup_conversation;
+
+    fn setup() -> (Connection, Conversation) {
+        let (conn, conversation) = setup_conversation(Some("Weather <today>"));
+
+        let mut question = Message::new(conversation.id.clone(), "user".to_string(), "Weather in Hangzhou?".to_string(), None);
+        question.created_at = 1_700_000_000_123;
+        question.save(&conn).unwrap();
+        let mut discarded = Message::new(conversation.id.clone(), "assistant".to_string(), "Discarded version".to_string(), None);
+        discarded.save(&conn).unwrap();
+        let mut answer = Message::new(
+            conversation.id.clone(),
+            "assistant".to_string(),
+            "Sunny, 22°C & dry".to_string(),
+            Some(vec![ToolCall {
+                id: "t1".to_string(),
+                tool_name: "weather".to_string(),
+                tool_input: "{\"city\":\"Hangzhou\"}".to_string(),
+                tool_output: Some("```sunny```".to_string()),
+                status: "success".to_string(),
+            }]),
+        );
+        answer.save_under(&conn, Some(question.id.clone())).unwrap();
+        (conn, conversation)
+    }
+
+    fn load(conn: &Connection, conversation: &Conversation) -> ConversationExport {
+        ConversationExport::load(conn, &conversation.id).unwrap().unwrap()
+    }
+
+    #[test]
+    fn markdown_has_agent_timestamps_and_collapsible_tool_calls() {
+        let (conn, conversation) = setup();
+        let md = to_markdown(&load(&conn, &conversation));
+
+        assert!(md.starts_with("# Weather <today>\n"));
+        assert!(md.contains("- Agent: Analyst\n"));
+        assert!(md.contains("### You · 2023-11-14 22:13:20 UTC"));
+        assert!(md.contains("### Analyst · "));
+        assert!(md.contains("<summary>Tool call: weather (success)</summary>"));
+        assert!(md.contains("````\n```sunny```\n````"));
+        assert!(md.contains("Sunny, 22°C & dry"));
+        assert!(!md.contains("Discarded version"));
+    }
+
+    #[test]
+    fn json_keeps_every_branch_and_round_trips() {
+        let (conn, conversation) = setup();
+        let export = load(&conn, &conversation);
+        let json = ExportFormat::Json.render(&export);
+        let parsed: ConversationExport = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(parsed.format_version, EXPORT_FORMAT_VERSION);
+        assert_eq!(parsed.messages.len(), 3);
+        assert_eq!(parsed.active_message_id, export.active_message_id);
+        assert_eq!(parsed.messages[2].tool_calls, export.messages[2].tool_calls);
+        assert_eq!(parsed.messages[2].parent_id.as_deref(), Some(parsed.messages[0].id.as_str()));
+        assert!(!json.contains("token"));
+    }
+
+    #[test]
+    fn html_is_escaped_and_self_contained() {
+        let (conn, conversation) = setup();
+        let html = to_html(&load(&conn, &conversation));
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>Weather &lt;today&gt;</title>"));
+        assert!(html.contains("Sunny, 22°C &amp; dry"));
+        assert!(html.contains("<pre>{&quot;city&quot;:&quot;Hangzhou&quot;}</pre>"));
+        assert!(!html.contains("<script") && !html.contains("<link"));
+        assert!(!html.contains("Discarded version"));
+    }
+
+    #[test]
+    fn missing_conversation_is_none() {
+        let (conn, _) = setup();
+        assert!(ConversationExport::load(&conn, "missing").unwrap().is_none());
+    }
+}
@@ -0,0 +1,312 @@
+//! Connectivity check for an agent's endpoint, run step by step so a
+//! misconfiguration shows up as the first step that failed rather than as a
+//! failed chat message.
+//!
+//! The probe is a real one-word query to `stream_run`, so it costs the agent a
+//! few tokens and may leave a conversation on the Coze side. The reply is
+//! dropped at its first recognised event, which is enough to tell whether the
+//! endpoint speaks the Coze stream format.
+//!
+//! TLS is not checked on its own: the handshake happens inside the HTTP client,
+//! so that step is inferred from whether the probe got through.
+
+use serde::Serialize;
+use reqwest::{Client, StatusCode, Url};
+use futures::StreamExt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use crate::chat::CozeRequest;
+use crate::coze::{self, CozeEvent};
+use crate::http::RequestPolicy;
+use crate::sse::SseDecoder;
+
+const PROBE_MESSAGE: &str = "ping";
+
+// Upper bound for waiting on the probe's first events, whatever the agent's read timeout
+const MAX_PROBE_WAIT: Duration = Duration::from_secs(30);
+
+// Characters of an error response body kept in the report
+const BODY_PREVIEW_CHARS: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Passed,
+    Failed,
+    // Not run because an earlier step failed, or not applicable (TLS over http)
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub status: CheckStatus,
+    pub detail: String,
+    pub duration_ms: Option<u64>,
+    // Judged from a later step rather than checked directly
+    pub inferred: bool,
+}
+
+impl Check {
+    fn passed(detail: impl Into<String>, took: Option<Duration>) -> Self {
+        Self { status: CheckStatus::Passed, detail: detail.into(), duration_ms: took.map(millis), inferred: false }
+    }
+
+    fn failed(detail: impl Into<String>, took: Option<Duration>) -> Self {
+        Self { status: CheckStatus::Failed, detail: detail.into(), duration_ms: took.map(millis), inferred: false }
+    }
+
+    fn skipped(detail: impl Into<String>) -> Self {
+        Self { status: CheckStatus::Skipped, detail: detail.into(), duration_ms: None, inferred: false }
+    }
+
+    fn inferred(self) -> Self {
+        Self { inferred: true, ..self }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionReport {
+    // Every step passed (or did not apply)
+    pub ok: bool,
+    pub url: Check,
+    pub dns: Check,
+    pub connect: Check,
+    pub tls: Check,
+    pub http: Check,
+    pub auth: Check,
+    pub stream: Check,
+    pub http_status: Option<u16>,
+    // From sending the probe to the first byte of the response body
+    pub first_byte_ms: Option<u64>,
+}
+
+impl ConnectionReport {
+    fn new() -> Self {
+        let pending = || Check::skipped("Not run");
+        Self {
+            ok: false,
+            url: pending(),
+            dns: pending(),
+            connect: pending(),
+            tls: pending(),
+            http: pending(),
+            auth: pending(),
+            stream: pending(),
+            http_status: None,
+            first_byte_ms: None,
+        }
+    }
+
+    fn finish(mut self) -> Self {
+        self.ok = [&self.url, &self.dns, &self.connect, &self.tls, &self.http, &self.auth, &self.stream]
+            .iter()
+            .all(|c| c.status != CheckStatus::Failed)
+            && self.stream.status == CheckStatus::Passed;
+        self
+    }
+}
+
+/// Checks the URL, resolves and connects to the host, then sends a probe query
+/// and reads the start of the reply
+pub async fn check_connection(
+    client: &Client,
+    policy: &RequestPolicy,
+    api_url: &str,
+    auth_token: &str,
+    project_id: &str,
+) -> ConnectionReport {
+    let mut report = ConnectionReport::new();
+
+    let url = match parse_url(api_url) {
+        Ok(url) => url,
+        Err(reason) => {
+            report.url = Check::failed(reason, None);
+            return report.finish();
+        }
+    };
+    report.url = Check::passed(url.as_str(), None);
+    let https = url.scheme() == "https";
+    let host = url.host_str().unwrap_or_default().to_string();
+    let port = url.port_or_known_default().unwrap_or(if https { 443 } else { 80 });
+
+    let started = Instant::now();
+    let addrs: Vec<SocketAddr> = match tokio::time::timeout(
+        policy.connect_timeout,
+        tokio::net::lookup_host((host.as_str(), port)),
+    )
+    .await
+    {
+        Ok(Ok(addrs)) => addrs.collect(),
+        Ok(Err(e)) => {
+            report.dns = Check::failed(format!("Could not resolve {}: {}", host, e), Some(started.elapsed()));
+            return report.finish();
+        }
+        Err(_) => {
+            report.dns = Check::failed(format!("Resolving {} timed out", host), Some(started.elapsed()));
+            return report.finish();
+        }
+    };
+    let Some(&addr) = addrs.first() else {
+        report.dns = Check::failed(format!("{} has no addresses", host), Some(started.elapsed()));
+        return report.finish();
+    };
+    report.dns = Check::passed(
+        addrs.iter().map(|a| a.ip().to_string()).collect::<Vec<_>>().join(", "),
+        Some(started.elapsed()),
+    );
+
+    let started = Instant::now();
+    match tokio::time::timeout(policy.connect_timeout, TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => report.connect = Check::passed(format!("Connected to {}", addr), Some(started.elapsed())),
+        Ok(Err(e)) => {
+            report.connect = Check::failed(format!("Could not connect to {}: {}", addr, e), Some(started.elapsed()));
+            return report.finish();
+        }
+        Err(_) => {
+            report.connect = Check::failed(format!("Connecting to {} timed out", addr), Some(started.elapsed()));
+            return report.finish();
+        }
+    }
+
+    // The handshake itself happens inside the HTTP client, so TLS is inferred from the probe
+    let request = CozeRequest::new(project_id, &[], PROBE_MESSAGE);
+    let started = Instant::now();
+    let response = client
+        .post(url)
+        .header("Authorization", format!("Bearer {}", auth_token))
+        .header("Content-Type", "application/json")
+        .json(&request)
+        .send()
+        .await;
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => {
+            let detail = format!("{}", e);
+            if https && e.is_connect() {
+                report.tls = Check::failed(format!("Could not connect over https, likely the TLS handshake: {}", detail), Some(started.elapsed()))
+                    .inferred();
+            } else {
+                report.tls = tls_not_applicable(https);
+                report.http = Check::failed(format!("Request failed: {}", detail), Some(started.elapsed()));
+            }
+            return report.finish();
+        }
+    };
+    report.tls = if https {
+        Check::passed("The probe got through over https", None).inferred()
+    } else {
+        tls_not_applicable(false)
+    };
+
+    let status = response.status();
+    report.http_status = Some(status.as_u16());
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        report.http = Check::passed(format!("Server responded {}", status), Some(started.elapsed()));
+        report.auth = Check::failed(
+            format!("The token was rejected ({}): {}", status, body_preview(response).await),
+            None,
+        );
+        return report.finish();
+    }
+    if !status.is_success() {
+        report.http = Check::failed(
+            format!("Server responded {}: {}", status, body_preview(response).await),
+            Some(started.elapsed()),
+        );
+        return report.finish();
+    }
+    report.http = Check::passed(format!("Server responded {}", status), Some(started.elapsed()));
+    report.auth = Check::passed("Token accepted", None);
+
+    report.stream = read_probe(response, started, policy, &mut report.first_byte_ms).await;
+    report.finish()
+}
+
+/// Reads until the first event the Coze decoder recognises; the response is
+/// dropped on return, closing the connection instead of waiting for the rest of the reply
+async fn read_probe(
+    response: reqwest::Response,
+    sent_at: Instant,
+    policy: &RequestPolicy,
+    first_byte_ms: &mut Option<u64>,
+) -> Check {
+    let deadline = tokio::time::Instant::now() + policy.read_timeout.min(MAX_PROBE_WAIT);
+    let mut stream = response.bytes_stream();
+    let mut decoder = SseDecoder::new();
+    let mut unknown = 0;
+
+    loop {
+        // The client's own read timeout usually fires first when the server goes quiet
+        let chunk = match tokio::time::timeout_at(deadline, stream.next()).await {
+            Err(_) => return Check::failed("No Coze event arrived before the timeout", Some(sent_at.elapsed())),
+            Ok(Some(Err(e))) if e.is_timeout() => {
+                return Check::failed("No Coze event arrived before the timeout", Some(sent_at.elapsed()));
+            }
+            Ok(None) => {
+                let detail = if first_byte_ms.is_none() {
+                    "The response body was empty".to_string()
+                } else {
+                    format!("The stream ended without a Coze event ({} unrecognised)", unknown)
+                };
+                return Check::failed(detail, Some(sent_at.elapsed()));
+            }
+            Ok(Some(Err(e))) => return Check::failed(format!("Stream interrupted: {}", e), Some(sent_at.elapsed())),
+            Ok(Some(Ok(chunk))) => chunk,
+        };
+        if first_byte_ms.is_none() {
+            *first_byte_ms = Some(millis(sent_at.elapsed()));
+        }
+
+        for event in decoder.push(&chunk) {
+            for decoded in coze::decode(&event.data) {
+                match decoded {
+                    CozeEvent::Unknown(_) => unknown += 1,
+                    CozeEvent::Error(message) => {
+                        return Check::failed(format!("The agent reported an error: {}", message), Some(sent_at.elapsed()));
+                    }
+                    _ => return Check::passed("Received a Coze stream event", Some(sent_at.elapsed())),
+                }
+            }
+        }
+    }
+}
+
+fn parse_url(api_url: &str) -> Result<Url, String> {
+    let url = Url::parse(api_url.trim()).map_err(|e| format!("Not a valid URL: {}", e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("Unsupported scheme `{}`; use http or https", url.scheme()));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err("The URL has no host".to_string());
+    }
+    Ok(url)
+}
+
+fn tls_not_applicable(https: bool) -> Check {
+    if https { Check::skipped("Not reached") } else { Check::skipped("Plain http, no TLS") }
+}
+
+async fn body_preview(response: reqwest::Response) -> String {
+    let body = response.text().await.unwrap_or_default();
+    let preview: String = body.trim().chars().take(BODY_PREVIEW_CHARS).collect();
+    if preview.is_empty() { "empty body".to_string() } else { preview }
+}
+
+fn millis(d: Duration) -> u64 {
+    d.as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_needs_http_scheme_and_host() {
+        assert!(parse_url("https://api.coze.cn/v1/stream_run").is_ok());
+        assert!(parse_url(" http://localhost:8080/stream_run ").is_ok());
+        assert!(parse_url("api.coze.cn/stream_run").unwrap_err().starts_with("Not a valid URL"));
+        assert!(parse_url("ftp://example.com/x").unwrap_err().contains("ftp"));
+        assert!(parse_url("file:///tmp/x").is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tokio_util::sync::CancellationToken;
+use crate::agent::Agent;
+
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+// Longest silence tolerated between two chunks of a stream; tool calls can be slow
+pub const DEFAULT_READ_TIMEOUT_SECS: u64 = 120;
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Timeouts and retry budget for requests to one agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RequestPolicy {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub max_retries: u32,
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            read_timeout: Duration::from_secs(DEFAULT_READ_TIMEOUT_SECS),
+            max_retries: DEFAULT_MAX_RETRIES,
+        }
+    }
+}
+
+impl RequestPolicy {
+    /// Defaults with the agent's own overrides applied
+    pub fn for_agent(agent: &Agent) -> Self {
+        let defaults = Self::default();
+        Self {
+            connect_timeout: agent
+                .connect_timeout_secs
+                .filter(|s| *s > 0)
+                .map_or(defaults.connect_timeout, |s| Duration::from_secs(s as u64)),
+            read_timeout: agent
+                .read_timeout_secs
+                .filter(|s| *s > 0)
+                .map_or(defaults.read_timeout, |s| Duration::from_secs(s as u64)),
+            max_retries: agent
+                .max_retries
+                .map_or(defaults.max_retries, |n| n.clamp(0, 10) as u32),
+        }
+    }
+}
+
+/// Shared clients, one per distinct set of timeouts, so connections are pooled across requests
+#[derive(Default)]
+pub struct HttpClients {
+    clients: Mutex<HashMap<(Duration, Duration), Client>>,
+}
+
+impl HttpClients {
+    pub fn client(&self, policy: &RequestPolicy) -> Result<Client, reqwest::Error> {
+        let key = (policy.connect_timeout, policy.read_timeout);
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+
+        let client = Client::builder()
+            .connect_timeout(policy.connect_timeout)
+            .read_timeout(policy.read_timeout)
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+            .build()?;
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+}
+
+/// Announced before each retry so the user can see why the reply is delayed
+#[derive(Debug, Clone)]
+pub struct RetryNotice {
+    pub attempt: u32, // 1 for the first retry
+    pub max_retries: u32,
+    pub delay: Duration,
+    pub reason: String,
+}
+
+pub enum SendOutcome {
+    Response(Response),
+    Cancelled,
+}
+
+/// Sends the request built by `build`, retrying connection errors, timeouts, 429 and 5xx
+/// responses with exponential backoff (or the server's `Retry-After`). Only the request
+/// itself is retried; once a successful response starts streaming it is handed back.
+///
+/// When retries run out on an error status, that last response is returned for the
+/// caller to report.
+pub async fn send_with_retry(
+    build: impl Fn() -> RequestBuilder,
+    policy: &RequestPolicy,
+    cancel: &CancellationToken,
+    mut on_retry: impl FnMut(&RetryNotice),
+) -> Result<SendOutcome, reqwest::Error> {
+    let mut attempt = 0;
+    loop {
+        let result = tokio::select! {
+            _ = cancel.cancelled() => return Ok(SendOutcome::Cancelled),
+            result = build().send() => result,
+        };
+
+        let (delay, reason) = match result {
+            Ok(response) if attempt < policy.max_retries && is_retryable_status(response.status()) => (
+                retry_after(response.headers(), Utc::now()).unwrap_or_else(|| backoff(attempt)),
+                format!("Server responded {}", response.status()),
+            ),
+            Ok(response) => return Ok(SendOutcome::Response(response)),
+            Err(e) if attempt < policy.max_retries && is_retryable_error(&e) => {
+                (backoff(attempt), describe_error(&e))
+            }
+            Err(e) => return Err(e),
+        };
+
+        attempt += 1;
+        on_retry(&RetryNotice {
+            attempt,
+            max_retries: policy.max_retries,
+            delay,
+            reason,
+        });
+
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(SendOutcome::Cancelled),
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
+}
+
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+fn is_retryable_error(e: &reqwest::Error) -> bool {
+    e.is_connect() || e.is_timeout() || e.is_request()
+}
+
+fn describe_error(e: &reqwest::Error) -> String {
+    if e.is_timeout() {
+        "Request timed out".to_string()
+    } else if e.is_connect() {
+        "Could not connect".to_string()
+    } else {
+        format!("Request failed: {}", e)
+    }
+}
+
+/// 0.5s, 1s, 2s, ... capped at 30s
+fn backoff(attempt: u32) -> Duration {
+    BASE_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_BACKOFF)
+}
+
+/// Delay asked for by a `Retry-After` header, in seconds or as an HTTP date
+pub fn retry_after(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(secs) => Duration::from_secs(secs),
+        Err(_) => {
+            let at = DateTime::parse_from_rfc2822(value).ok()?.with_timezone(&Utc);
+            (at - now).to_std().unwrap_or(Duration::ZERO)
+        }
+    };
+    Some(delay.min(MAX_BACKOFF))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(retry_after: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(retry_after).unwrap());
+        headers
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        assert_eq!(backoff(0), Duration::from_millis(500));
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(3), Duration::from_secs(4));
+        assert_eq!(backoff(20), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn retry_after_accepts_seconds_and_dates() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2026 07:28:00 GMT").unwrap().with_timezone(&Utc);
+
+        assert_eq!(retry_after(&headers("7"), now), Some(Duration::from_secs(7)));
+        assert_eq!(retry_after(&headers("Wed, 21 Oct 2026 07:28:05 GMT"), now), Some(Duration::from_secs(5)));
+        assert_eq!(retry_after(&headers("Wed, 21 Oct 2026 07:27:00 GMT"), now), Some(Duration::ZERO));
+        assert_eq!(retry_after(&headers("3600"), now), Some(MAX_BACKOFF));
+        assert_eq!(retry_after(&headers("soon"), now), None);
+        assert_eq!(retry_after(&HeaderMap::new(), now), None);
+    }
+
+    #[test]
+    fn only_throttling_and_server_errors_are_retried() {
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable_status(StatusCode::BAD_GATEWAY));
+        assert!(!is_retryable_status(StatusCode::UNAUTHORIZED));
+        assert!(!is_retryable_status(StatusCode::BAD_REQUEST));
+    }
+}
@@ -0,0 +1,546 @@
+//! Conversation import from our own JSON export and from ChatGPT's `conversations.json`.
+//!
+//! Ids and timestamps are kept, so importing the same file twice only adds what
+//! is missing: a conversation whose id (or any of whose message ids) is already
+//! in the database is reported as a duplicate and skipped.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use rusqlite::{params, Connection};
+use std::collections::{HashMap, HashSet};
+use crate::agent::{Agent, CreateAgentInput};
+use crate::chat::{Conversation, Message};
+use crate::crypto::TokenCipher;
+use crate::error::AppError;
+use crate::export::{ConversationExport, ExportedAgent, EXPORT_FORMAT_VERSION};
+
+// Agent that ChatGPT conversations are filed under unless another one is chosen
+const CHATGPT_AGENT_NAME: &str = "ChatGPT";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFormat {
+    CozeStudio,
+    Chatgpt,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub format: ImportFormat,
+    pub conversations: Vec<ImportedConversation>,
+    // Agents created (or, in a dry run, that would be) to hold the conversations
+    pub created_agents: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportedConversation {
+    pub id: String,
+    pub title: Option<String>,
+    pub agent_name: String,
+    pub message_count: usize,
+    pub duplicate: bool, // already in the database, so skipped
+}
+
+// One conversation read from a file, before it is assigned to an agent
+struct Source {
+    conversation: Conversation,
+    agent: Option<ExportedAgent>,
+    active_message_id: Option<String>,
+    // Parents always come before their replies
+    messages: Vec<Message>,
+}
+
+/// Imports every conversation in `json` under `agent_id`, or else under the agent
+/// it was exported from, an agent with the same name, or a new agent without an
+/// endpoint. A dry run reports the same result and changes nothing.
+pub fn import_conversations(
+    conn: &Connection,
+    cipher: &TokenCipher,
+    json: &str,
+    agent_id: Option<&str>,
+    dry_run: bool,
+) -> Result<ImportReport, AppError> {
+    let value: Value =
+        serde_json::from_str(json).map_err(|e| AppError::validation(format!("Not a JSON file: {}", e)))?;
+    let (format, sources) = parse(value)?;
+
+    let target = match agent_id {
+        Some(id) => Some(
+            Agent::get_by_id(conn, id)?
+                .ok_or_else(|| AppError::not_found("Agent not found"))?,
+        ),
+        None => None,
+    };
+
+    let tx = conn.unchecked_transaction()?;
+    let mut agents = AgentResolver::new(&tx, cipher)?;
+    let mut report = ImportReport {
+        dry_run,
+        format,
+        conversations: Vec::new(),
+        created_agents: Vec::new(),
+    };
+
+    for source in sources {
+        let agent = match &target {
+            Some(agent) => agent.clone(),
+            None => agents.resolve(source.agent.as_ref())?,
+        };
+        let duplicate = is_duplicate(&tx, &source)?;
+        report.conversations.push(ImportedConversation {
+            id: source.conversation.id.clone(),
+            title: source.conversation.title.clone(),
+            agent_name: agent.name.clone(),
+            message_count: source.messages.len(),
+            duplicate,
+        });
+        if !duplicate {
+            insert(&tx, source, &agent.id)?;
+        }
+    }
+
+    report.created_agents = agents.created;
+    if !dry_run {
+        tx.commit()?;
+    }
+    Ok(report)
+}
+
+fn parse(value: Value) -> Result<(ImportFormat, Vec<Source>), AppError> {
+    let items = match value {
+        Value::Array(items) => items,
+        single => vec![single],
+    };
+    let Some(first) = items.first() else {
+        return Err(AppError::validation("The file contains no conversations"));
+    };
+
+    if first.get("format_version").is_some() {
+        let sources = items
+            .into_iter()
+            .map(|item| {
+                let export: ConversationExport =
+                    serde_json::from_value(item).map_err(|e| AppError::validation(format!("Invalid conversation export: {}", e)))?;
+                if export.format_version > EXPORT_FORMAT_VERSION {
+                    return Err(AppError::validation(format!(
+                        "The export was made by a newer version of the app (format {})",
+                        export.format_version
+                    )));
+                }
+                let mut conversation = export.conversation;
+                let mut messages = export.messages;
+                // Older exports stamped messages (before 2) and conversations (before 3) in whole seconds
+                if export.format_version < 2 {
+                    for message in &mut messages {
+                        message.created_at = message.created_at.saturating_mul(1000);
+                    }
+                }
+                if export.format_version < 3 {
+                    conversation.created_at = conversation.created_at.saturating_mul(1000);
+                }
+                Ok(Source {
+                    conversation,
+                    agent: Some(export.agent),
+                    active_message_id: export.active_message_id,
+                    messages,
+                })
+            })
+            .collect::<Result<Vec<_>, AppError>>()?;
+        Ok((ImportFormat::CozeStudio, sources))
+    } else if first.get("mapping").is_some() {
+        let sources = items
+            .into_iter()
+            .map(|item| {
+                let conversation: ChatGptConversation =
+                    serde_json::from_value(item).map_err(|e| AppError::validation(format!("Invalid ChatGPT conversation: {}", e)))?;
+                Ok(conversation.into_source())
+            })
+            .collect::<Result<Vec<_>, AppError>>()?;
+        Ok((ImportFormat::Chatgpt, sources))
+    } else {
+        Err(AppError::validation("Unrecognized file: expected a conversation export or ChatGPT conversations.json"))
+    }
+}
+
+fn is_duplicate(conn: &Connection, source: &Source) -> rusqlite::Result<bool> {
+    if Conversation::get_by_id(conn, &source.conversation.id)?.is_some() {
+        return Ok(true);
+    }
+    let mut stmt = conn.prepare("SELECT EXISTS(SELECT 1 FROM messages WHERE id = ?1)")?;
+    for message in &source.messages {
+        if stmt.query_row(params![&message.id], |row| row.get::<_, bool>(0))? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn insert(conn: &Connection, source: Source, agent_id: &str) -> rusqlite::Result<()> {
+    let conversation = Conversation {
+        agent_id: agent_id.to_string(),
+        ..source.conversation
+    };
+    conversation.save(conn)?;
+
+    let mut last = None;
+    for message in source.messages {
+        let parent_id = message.parent_id.clone();
+        let mut message = Message {
+            conversation_id: conversation.id.clone(),
+            ..message
+        };
+        // A reply exported mid-stream has nothing left to finish it here
+        if message.status == "streaming" {
+            message.status = "interrupted".to_string();
+        }
+        message.save_under(conn, parent_id)?;
+        last = Some(message.id);
+    }
+
+    if let Some(active) = source.active_message_id.or(last) {
+        Conversation::set_active_message(conn, &conversation.id, &active)?;
+    }
+    Ok(())
+}
+
+/// Finds or creates the agent each imported conversation is filed under
+struct AgentResolver<'a> {
+    conn: &'a Connection,
+    cipher: &'a TokenCipher,
+    existing: Vec<Agent>,
+    created: Vec<String>,
+}
+
+impl<'a> AgentResolver<'a> {
+    fn new(conn: &'a Connection, cipher: &'a TokenCipher) -> rusqlite::Result<Self> {
+        Ok(Self {
+            conn,
+            cipher,
+            existing: Agent::get_all(conn)?,
+            created: Vec::new(),
+        })
+    }
+
+    fn resolve(&mut self, exported: Option<&ExportedAgent>) -> Result<Agent, AppError> {
+        let name = exported.map_or(CHATGPT_AGENT_NAME, |a| a.name.as_str());
+        let found = exported
+            .and_then(|a| self.existing.iter().find(|e| e.id == a.id))
+            .or_else(|| self.existing.iter().find(|e| e.name == name));
+        if let Some(agent) = found {
+            return Ok(agent.clone());
+        }
+
+        // The endpoint and token are not exported; the user fills them in later
+        let mut agent = Agent::new(CreateAgentInput {
+            name: name.to_string(),
+            api_url: String::new(),
+            auth_token: String::new(),
+            project_id: exported.map(|a| a.project_id.clone()).unwrap_or_default(),
+            history_window: None,
+            connect_timeout_secs: None,
+            read_timeout_secs: None,
+            max_retries: None,
+        });
+        agent.auth_token = self.cipher.encrypt(&agent.auth_token)?;
+        agent.save(self.conn)?;
+        self.created.push(agent.name.clone());
+        self.existing.push(agent.clone());
+        Ok(agent)
+    }
+}
+
+// The parts of ChatGPT's export we read. Each conversation is a tree of nodes keyed
+// by id; `current_node` is the leaf that was on screen.
+#[derive(Debug, Deserialize)]
+struct ChatGptConversation {
+    #[serde(alias = "conversation_id")]
+    id: String,
+    title: Option<String>,
+    create_time: Option<f64>,
+    mapping: HashMap<String, ChatGptNode>,
+    current_node: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatGptNode {
+    message: Option<ChatGptMessage>,
+    parent: Option<String>,
+    #[serde(default)]
+    children: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatGptMessage {
+    author: ChatGptAuthor,
+    create_time: Option<f64>,
+    content: ChatGptContent,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatGptAuthor {
+    role: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatGptContent {
+    #[serde(default)]
+    parts: Vec<Value>,
+}
+
+impl ChatGptConversation {
+    /// Keeps the user and assistant messages with text, re-linking each to its
+    /// nearest kept ancestor; system prompts, tool traffic and empty nodes are dropped
+    fn into_source(self) -> Source {
+        let created_at = self.create_time.map_or(0, |t| (t * 1000.0) as i64);
+        let kept: HashMap<&str, Message> = self
+            .mapping
+            .iter()
+            .filter_map(|(id, node)| {
+                let message = node.message.as_ref()?;
+                let role = message.author.role.as_str();
+                let text: Vec<&str> = message.content.parts.iter().filter_map(Value::as_str).collect();
+                let text = text.join("\n");
+                if !matches!(role, "user" | "assistant") || text.trim().is_empty() {
+                    return None;
+                }
+                let mut imported = Message::new(self.id.clone(), role.to_string(), text, None);
+                imported.id = id.clone();
+                imported.created_at = message.create_time.map_or(created_at, |t| (t * 1000.0) as i64);
+                Some((id.as_str(), imported))
+            })
+            .collect();
+
+        // Depth-first from the roots, so parents come before their replies
+        let mut roots: Vec<&str> = self
+            .mapping
+            .iter()
+            .filter(|(_, node)| node.parent.as_deref().is_none_or(|p| !self.mapping.contains_key(p)))
+            .map(|(id, _)| id.as_str())
+            .collect();
+        roots.sort_unstable();
+        let mut stack: Vec<&str> = roots.into_iter().rev().collect();
+        let mut visited = HashSet::new();
+        let mut messages = Vec::new();
+        while let Some(id) = stack.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+            let Some(node) = self.mapping.get(id) else {
+                continue;
+            };
+            if let Some(message) = kept.get(id) {
+                messages.push(Message {
+                    parent_id: self.nearest_kept(&kept, node.parent.as_deref()),
+                    ..message.clone()
+                });
+            }
+            stack.extend(node.children.iter().rev().map(String::as_str));
+        }
+
+        Source {
+            active_message_id: self.nearest_kept(&kept, self.current_node.as_deref()),
+            conversation: Conversation {
+                id: self.id.clone(),
+                created_at,
+                ..Conversation::new(String::new(), self.title.clone())
+            },
+            agent: None,
+            messages,
+        }
+    }
+
+    fn nearest_kept<'a>(&'a self, kept: &HashMap<&str, Message>, mut id: Option<&'a str>) -> Option<String> {
+        while let Some(current) = id {
+            if kept.contains_key(current) {
+                return Some(current.to_string());
+            }
+            id = self.mapping.get(current).and_then(|n| n.parent.as_deref());
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::ExportFormat;
+    use crate::test_support::{database_with_cipher, saved_agent};
+    use serde_json::json;
+
+    fn contents(messages: &[Message]) -> Vec<&str> {
+        messages.iter().map(|m| m.content.as_str()).collect()
+    }
+
+    // An export of a conversation with two versions of the answer, the second shown
+    fn exported_conversation() -> String {
+        let (source, _cipher, _dir) = database_with_cipher();
+        let analyst = saved_agent(&source, "Analyst");
+        let conversation = Conversation::new(analyst.id.clone(), Some("Weather".to_string()));
+        conversation.save(&source).unwrap();
+        let mut question = Message::new(conversation.id.clone(), "user".to_string(), "q".to_string(), None);
+        question.created_at = 1_700_000_000_000;
+        question.save(&source).unwrap();
+        for answer in ["first", "second"] {
+            Message::new(conversation.id.clone(), "assistant".to_string(), answer.to_string(), None)
+                .save_under(&source, Some(question.id.clone()))
+                .unwrap();
+        }
+        let export = ConversationExport::load(&source, &conversation.id).unwrap().unwrap();
+        ExportFormat::Json.render(&export)
+    }
+
+    #[test]
+    fn recreates_our_export_with_its_tree_and_timestamps() {
+        let (conn, cipher, _dir) = database_with_cipher();
+        let json = exported_conversation();
+
+        let report = import_conversations(&conn, &cipher, &json, None, false).unwrap();
+
+        assert_eq!(report.format, ImportFormat::CozeStudio);
+        assert_eq!(report.created_agents, vec!["Analyst"]);
+        assert!(!report.conversations[0].duplicate);
+        let id = &report.conversations[0].id;
+        let path = Message::get_by_conversation(&conn, id).unwrap();
+        assert_eq!(contents(&path), vec!["q", "second"]);
+        assert_eq!(path[0].created_at, 1_700_000_000_000);
+        assert_eq!(path[1].version, 2);
+        assert_eq!(Message::get_all(&conn, id).unwrap().len(), 3);
+        let created = &Agent::get_all(&conn).unwrap()[0];
+        assert!(crate::crypto::is_encrypted(&created.auth_token));
+    }
+
+    #[test]
+    fn second_import_is_all_duplicates() {
+        let (conn, cipher, _dir) = database_with_cipher();
+        let json = exported_conversation();
+        import_conversations(&conn, &cipher, &json, None, false).unwrap();
+
+        let report = import_conversations(&conn, &cipher, &json, None, false).unwrap();
+
+        assert!(report.conversations[0].duplicate);
+        assert!(report.created_agents.is_empty());
+        assert_eq!(Agent::get_all(&conn).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn first_format_timestamps_are_read_as_seconds() {
+        let (conn, cipher, _dir) = database_with_cipher();
+        let mut export: Value = serde_json::from_str(&exported_conversation()).unwrap();
+        export["format_version"] = json!(1);
+        export["messages"][0]["created_at"] = json!(1_700_000_000);
+        for message in export["messages"].as_array_mut().unwrap() {
+            message.as_object_mut().unwrap().remove("seq");
+        }
+
+        let report = import_conversations(&conn, &cipher, &export.to_string(), None, false).unwrap();
+
+        let path = Message::get_by_conversation(&conn, &report.conversations[0].id).unwrap();
+        assert_eq!(path[0].created_at, 1_700_000_000_000);
+        assert_eq!(path.iter().map(|m| m.seq).collect::<Vec<_>>(), vec![1, 3]);
+    }
+
+    #[test]
+    fn second_format_conversation_timestamps_are_read_as_seconds() {
+        let (conn, cipher, _dir) = database_with_cipher();
+        let mut export: Value = serde_json::from_str(&exported_conversation()).unwrap();
+        export["format_version"] = json!(2);
+        export["conversation"]["created_at"] = json!(1_700_000_000);
+
+        let report = import_conversations(&conn, &cipher, &export.to_string(), None, false).unwrap();
+
+        let conversation = Conversation::get_by_id(&conn, &report.conversations[0].id).unwrap().unwrap();
+        assert_eq!(conversation.created_at, 1_700_000_000_000);
+        let path = Message::get_by_conversation(&conn, &conversation.id).unwrap();
+        assert_eq!(path[0].created_at, 1_700_000_000_000, "messages were already milliseconds");
+    }
+
+    #[test]
+    fn replies_exported_mid_stream_are_imported_as_interrupted() {
+        let (conn, cipher, _dir) = database_with_cipher();
+        let mut export: Value = serde_json::from_str(&exported_conversation()).unwrap();
+        export["messages"][2]["status"] = json!("streaming");
+
+        let report = import_conversations(&conn, &cipher, &export.to_string(), None, false).unwrap();
+
+        let path = Message::get_by_conversation(&conn, &report.conversations[0].id).unwrap();
+        assert_eq!(path[1].content, "second");
+        assert_eq!(path[1].status, "interrupted");
+        assert_eq!(path[0].status, "complete");
+    }
+
+    #[test]
+    fn dry_run_reports_without_writing() {
+        let (conn, cipher, _dir) = database_with_cipher();
+        let report = import_conversations(&conn, &cipher, &exported_conversation(), None, true).unwrap();
+
+        assert!(report.dry_run);
+        assert_eq!(report.conversations[0].message_count, 3);
+        assert_eq!(report.created_agents, vec!["Analyst"]);
+        assert!(Agent::get_all(&conn).unwrap().is_empty());
+        assert!(Conversation::get_by_id(&conn, &report.conversations[0].id).unwrap().is_none());
+    }
+
+    #[test]
+    fn maps_to_chosen_agent_or_one_with_the_same_name() {
+        let (conn, cipher, _dir) = database_with_cipher();
+        let chosen = saved_agent(&conn, "Chosen");
+        let report = import_conversations(&conn, &cipher, &exported_conversation(), Some(&chosen.id), false).unwrap();
+        assert_eq!(report.conversations[0].agent_name, "Chosen");
+
+        let same_name = saved_agent(&conn, "Analyst");
+        import_conversations(&conn, &cipher, &exported_conversation(), None, false).unwrap();
+        assert_eq!(Conversation::get_by_agent(&conn, &same_name.id).unwrap().len(), 1);
+
+        assert!(import_conversations(&conn, &cipher, &exported_conversation(), Some("missing"), false).is_err());
+    }
+
+    #[test]
+    fn imports_chatgpt_conversations() {
+        let (conn, cipher, _dir) = database_with_cipher();
+        let json = json!([{
+            "title": "Trip planning",
+            "create_time": 1_690_000_000.5,
+            "id": "c1",
+            "current_node": "a2",
+            "mapping": {
+                "root": {"id": "root", "message": null, "parent": null, "children": ["sys"]},
+                "sys": {"id": "sys", "parent": "root", "children": ["u1"], "message": {
+                    "author": {"role": "system"}, "create_time": null,
+                    "content": {"content_type": "text", "parts": [""]}}},
+                "u1": {"id": "u1", "parent": "sys", "children": ["a1", "a2"], "message": {
+                    "author": {"role": "user"}, "create_time": 1_690_000_001.0,
+                    "content": {"content_type": "text", "parts": ["Where to go?"]}}},
+                "a1": {"id": "a1", "parent": "u1", "children": [], "message": {
+                    "author": {"role": "assistant"}, "create_time": 1_690_000_002.0,
+                    "content": {"content_type": "text", "parts": ["Kyoto"]}}},
+                "a2": {"id": "a2", "parent": "u1", "children": [], "message": {
+                    "author": {"role": "assistant"}, "create_time": 1_690_000_003.0,
+                    "content": {"content_type": "text", "parts": ["Lisbon"]}}}
+            }
+        }])
+        .to_string();
+
+        let report = import_conversations(&conn, &cipher, &json, None, false).unwrap();
+
+        assert_eq!(report.format, ImportFormat::Chatgpt);
+        assert_eq!(report.created_agents, vec![CHATGPT_AGENT_NAME]);
+        assert_eq!(report.conversations[0].message_count, 3);
+        let conversation = Conversation::get_by_id(&conn, "c1").unwrap().unwrap();
+        assert_eq!(conversation.title.as_deref(), Some("Trip planning"));
+        assert_eq!(conversation.created_at, 1_690_000_000_500);
+        let path = Message::get_by_conversation(&conn, "c1").unwrap();
+        assert_eq!(contents(&path), vec!["Where to go?", "Lisbon"]);
+        assert_eq!(path[0].parent_id, None);
+        assert_eq!(path[1].version_count, 2);
+        assert_eq!(path[1].created_at, 1_690_000_003_000);
+    }
+
+    #[test]
+    fn rejects_unknown_files() {
+        let (conn, cipher, _dir) = database_with_cipher();
+        assert!(import_conversations(&conn, &cipher, "{\"hello\": 1}", None, true).is_err());
+        assert!(import_conversations(&conn, &cipher, "[]", None, true).is_err());
+        assert!(import_conversations(&conn, &cipher, "not json", None, true).is_err());
+    }
+}
@@ -1,167 +1,420 @@
-mod db;
-mod agent;
-mod chat;
+pub mod db;
+pub mod agent;
+mod agent_bundle;
+mod backup;
+pub mod chat;
+pub mod coze;
+mod crypto;
+pub mod error;
+pub mod export;
+pub mod health;
+pub mod http;
+mod import;
+mod logging;
+mod search;
+pub mod sse;
+#[cfg(test)]
+mod test_support;
+mod usage;
 
-use tauri::AppHandle;
+use tauri::{AppHandle, Manager, State};
+use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Mutex;
 use rusqlite::params;
+use tokio_util::sync::CancellationToken;
 use agent::{Agent, CreateAgentInput};
-use chat::{Conversation, Message, send_message_to_coze_streaming};
+use agent_bundle::{AgentImportReport, BundleFormat, ConflictResolution};
+use backup::{BackupOptions, WorkspaceSummary};
+use crypto::TokenCipher;
+use db::{DbConnection, DbPool};
+use error::AppError;
+use export::{ConversationExport, ExportFormat};
+use health::ConnectionReport;
+use http::{HttpClients, RequestPolicy};
+use import::ImportReport;
+use logging::Logging;
+use search::SearchHit;
+use usage::{MessageUsage, UsageBucket, UsageGroup};
+use chat::{
+    Branch, Conversation, CozeRequest, DraftSink, Message, StreamEmitter, StreamOutcome, DRAFT_FLUSH_INTERVAL,
+    send_message_to_coze_streaming,
+};
 
 struct AppState {
-    db_initialized: Mutex<bool>,
+    db: DbPool,
+    cipher: TokenCipher,
+    http: HttpClients,
+    // In-flight chat streams keyed by conversation id
+    streams: Mutex<HashMap<String, CancellationToken>>,
+}
+
+impl AppState {
+    fn new(db: DbPool, cipher: TokenCipher) -> Self {
+        Self {
+            db,
+            cipher,
+            http: HttpClients::default(),
+            streams: Mutex::new(HashMap::new()),
+        }
+    }
+    
+    /// Borrows a connection from the shared pool
+    fn conn(&self) -> Result<DbConnection, AppError> {
+        self.db.get().map_err(AppError::from)
+    }
+}
+
+/// Removes a stream's cancellation token once the request finishes, however it ends
+struct StreamRegistration<'a> {
+    state: &'a AppState,
+    conversation_id: String,
+}
+
+impl<'a> StreamRegistration<'a> {
+    fn register(state: &'a AppState, conversation_id: &str) -> Result<(Self, CancellationToken), AppError> {
+        let mut streams = state.streams.lock().unwrap_or_else(|e| e.into_inner());
+        if streams.contains_key(conversation_id) {
+            return Err(AppError::validation("A response is already being generated for this conversation"));
+        }
+        let token = CancellationToken::new();
+        streams.insert(conversation_id.to_string(), token.clone());
+        Ok((
+            Self {
+                state,
+                conversation_id: conversation_id.to_string(),
+            },
+            token,
+        ))
+    }
+}
+
+impl Drop for StreamRegistration<'_> {
+    fn drop(&mut self) {
+        self.state.streams.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.conversation_id);
+    }
 }
 
 #[tauri::command]
-async fn initialize_db(app: AppHandle) -> Result<(), String> {
-    db::init_db(&app).map_err(|e| e.to_string())?;
-    Ok(())
+#[tracing::instrument(skip_all, err)]
+async fn initialize_db(state: State<'_, AppState>) -> Result<(), AppError> {
+    let mut conn = state.conn()?;
+    db::migrate(&mut conn).map_err(AppError::from)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+async fn create_agent(state: State<'_, AppState>, input: CreateAgentInput) -> Result<Agent, AppError> {
+    let conn = state.conn()?;
+    let mut agent = Agent::new(input);
+    agent.auth_token = state.cipher.encrypt(&agent.auth_token)?;
+    agent.save(&conn)?;
+    Ok(agent.masked(&state.cipher))
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+async fn get_agents(state: State<'_, AppState>) -> Result<Vec<Agent>, AppError> {
+    let conn = state.conn()?;
+    let agents = Agent::get_all(&conn)?;
+    Ok(agents.iter().map(|a| a.masked(&state.cipher)).collect())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, fields(id = %id), err)]
+async fn get_agent(state: State<'_, AppState>, id: String) -> Result<Option<Agent>, AppError> {
+    let conn = state.conn()?;
+    let agent = Agent::get_by_id(&conn, &id)?;
+    Ok(agent.map(|a| a.masked(&state.cipher)))
 }
 
 #[tauri::command]
-async fn create_agent(app: AppHandle, input: CreateAgentInput) -> Result<Agent, String> {
-    let conn = db::get_db_connection(&app).map_err(|e| e.to_string())?;
-    let agent = Agent::new(input);
-    agent.save(&conn).map_err(|e| e.to_string())?;
-    Ok(agent)
+#[tracing::instrument(skip_all, fields(agent_id = %agent.id), err)]
+async fn update_agent(state: State<'_, AppState>, mut agent: Agent) -> Result<(), AppError> {
+    let conn = state.conn()?;
+    
+    // The frontend only ever sees the masked token; sending it back means "unchanged"
+    if crypto::is_masked(&agent.auth_token) {
+        let existing = Agent::get_by_id(&conn, &agent.id)?
+            .ok_or_else(|| AppError::not_found("Agent not found"))?;
+        agent.auth_token = existing.auth_token;
+    } else {
+        agent.auth_token = state.cipher.encrypt(&agent.auth_token)?;
+    }
+    agent.update(&conn).map_err(AppError::from)
 }
 
 #[tauri::command]
-async fn get_agents(app: AppHandle) -> Result<Vec<Agent>, String> {
-    let conn = db::get_db_connection(&app).map_err(|e| e.to_string())?;
-    Agent::get_all(&conn).map_err(|e| e.to_string())
+#[tracing::instrument(skip_all, fields(id = %id), err)]
+async fn delete_agent(state: State<'_, AppState>, id: String) -> Result<(), AppError> {
+    let conn = state.conn()?;
+    Agent::delete(&conn, &id).map_err(AppError::from)
 }
 
+/// Checks that an agent's endpoint is reachable and answers like Coze. Takes either the
+/// settings being edited (`agent`, whose masked token means the one stored for
+/// `agent_id`) or just the id of a saved agent.
 #[tauri::command]
-async fn get_agent(app: AppHandle, id: String) -> Result<Option<Agent>, String> {
-    let conn = db::get_db_connection(&app).map_err(|e| e.to_string())?;
-    Agent::get_by_id(&conn, &id).map_err(|e| e.to_string())
+#[tracing::instrument(skip_all, fields(agent_id = ?agent_id), err)]
+async fn test_agent_connection(
+    state: State<'_, AppState>,
+    agent_id: Option<String>,
+    agent: Option<CreateAgentInput>,
+) -> Result<ConnectionReport, AppError> {
+    let stored = match &agent_id {
+        Some(id) => Some(
+            Agent::get_by_id(&*state.conn()?, id)?
+                .ok_or_else(|| AppError::not_found("Agent not found"))?,
+        ),
+        None => None,
+    };
+    let (agent, auth_token) = match (agent, stored) {
+        (Some(input), stored) => {
+            let token = if crypto::is_masked(&input.auth_token) {
+                let stored = stored
+                    .ok_or_else(|| AppError::validation("Enter the token to test an agent that is not saved yet"))?;
+                state.cipher.decrypt(&stored.auth_token)?
+            } else {
+                input.auth_token.clone()
+            };
+            (Agent::new(input), token)
+        }
+        (None, Some(stored)) => {
+            let token = state.cipher.decrypt(&stored.auth_token)?;
+            (stored, token)
+        }
+        (None, None) => return Err(AppError::validation("Pass an agent or an agent id")),
+    };
+    
+    let policy = RequestPolicy::for_agent(&agent);
+    let client = state.http.client(&policy)?;
+    Ok(health::check_connection(&client, &policy, &agent.api_url, &auth_token, &agent.project_id).await)
 }
 
+/// Writes the agents with `ids` (all agents when empty) to `path` as a JSON or TOML bundle
 #[tauri::command]
-async fn update_agent(app: AppHandle, agent: Agent) -> Result<(), String> {
-    let conn = db::get_db_connection(&app).map_err(|e| e.to_string())?;
-    agent.update(&conn).map_err(|e| e.to_string())
+#[tracing::instrument(skip_all, err)]
+async fn export_agents(
+    state: State<'_, AppState>,
+    ids: Vec<String>,
+    include_secrets: bool,
+    format: BundleFormat,
+    path: String,
+) -> Result<(), AppError> {
+    let bundle = {
+        let conn = state.conn()?;
+        agent_bundle::export_agents(&conn, &state.cipher, &ids, include_secrets, format)?
+    };
+    std::fs::write(&path, bundle).map_err(|e| AppError::file("write", &path, e))
 }
 
+/// Imports an agent bundle. Agents clashing with existing ones on id or name need an
+/// entry in `resolutions`; until every clash has one, nothing is imported.
 #[tauri::command]
-async fn delete_agent(app: AppHandle, id: String) -> Result<(), String> {
-    let conn = db::get_db_connection(&app).map_err(|e| e.to_string())?;
-    Agent::delete(&conn, &id).map_err(|e| e.to_string())
+#[tracing::instrument(skip_all, err)]
+async fn import_agents(
+    state: State<'_, AppState>,
+    path: String,
+    resolutions: Option<HashMap<String, ConflictResolution>>,
+) -> Result<AgentImportReport, AppError> {
+    let text = std::fs::read_to_string(&path).map_err(|e| AppError::file("read", &path, e))?;
+    let bundle = agent_bundle::parse_bundle(&text)?;
+    let conn = state.conn()?;
+    agent_bundle::import_agents(&conn, &state.cipher, bundle, &resolutions.unwrap_or_default())
 }
 
 #[tauri::command]
-async fn create_conversation(app: AppHandle, agent_id: String, title: Option<String>) -> Result<Conversation, String> {
-    let conn = db::get_db_connection(&app).map_err(|e| e.to_string())?;
+#[tracing::instrument(skip_all, fields(agent_id = %agent_id), err)]
+async fn create_conversation(state: State<'_, AppState>, agent_id: String, title: Option<String>) -> Result<Conversation, AppError> {
+    let conn = state.conn()?;
     let conversation = Conversation::new(agent_id, title);
-    conversation.save(&conn).map_err(|e| e.to_string())?;
+    conversation.save(&conn)?;
     Ok(conversation)
 }
 
 #[tauri::command]
-async fn get_conversations(app: AppHandle, agent_id: String) -> Result<Vec<Conversation>, String> {
-    let conn = db::get_db_connection(&app).map_err(|e| e.to_string())?;
-    Conversation::get_by_agent(&conn, &agent_id).map_err(|e| e.to_string())
+#[tracing::instrument(skip_all, fields(agent_id = %agent_id), err)]
+async fn get_conversations(state: State<'_, AppState>, agent_id: String) -> Result<Vec<Conversation>, AppError> {
+    let conn = state.conn()?;
+    Conversation::get_by_agent(&conn, &agent_id).map_err(AppError::from)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, fields(id = %id), err)]
+async fn delete_conversation(state: State<'_, AppState>, id: String) -> Result<(), AppError> {
+    let conn = state.conn()?;
+    Conversation::delete(&conn, &id).map_err(AppError::from)
 }
 
 #[tauri::command]
-async fn delete_conversation(app: AppHandle, id: String) -> Result<(), String> {
-    let conn = db::get_db_connection(&app).map_err(|e| e.to_string())?;
-    Conversation::delete(&conn, &id).map_err(|e| e.to_string())
+#[tracing::instrument(skip_all, fields(conversation_id = %conversation.id), err)]
+async fn update_conversation(state: State<'_, AppState>, conversation: Conversation) -> Result<(), AppError> {
+    let conn = state.conn()?;
+    conversation.update(&conn).map_err(AppError::from)
+}
+
+fn load_export(state: &AppState, conversation_id: &str) -> Result<ConversationExport, AppError> {
+    let conn = state.conn()?;
+    ConversationExport::load(&conn, conversation_id)?
+        .ok_or_else(|| AppError::not_found("Conversation not found"))
 }
 
 #[tauri::command]
-async fn update_conversation(app: AppHandle, conversation: Conversation) -> Result<(), String> {
-    let conn = db::get_db_connection(&app).map_err(|e| e.to_string())?;
-    conversation.update(&conn).map_err(|e| e.to_string())
+#[tracing::instrument(skip_all, fields(conversation_id = %conversation_id), err)]
+async fn export_conversation_markdown(state: State<'_, AppState>, conversation_id: String) -> Result<String, AppError> {
+    let export = load_export(&state, &conversation_id)?;
+    Ok(export::to_markdown(&export))
 }
 
+/// Writes the conversation to `path` as Markdown, JSON or HTML
 #[tauri::command]
-async fn export_conversation_markdown(app: AppHandle, conversation_id: String) -> Result<String, String> {
-    let conn = db::get_db_connection(&app).map_err(|e| e.to_string())?;
-    
-    // Get conversation details
-    let conversations = Conversation::get_by_agent(&conn, "")
-        .map_err(|e| e.to_string())?;
-    let conversation = conversations.iter()
-        .find(|c| c.id == conversation_id)
-        .ok_or("Conversation not found")?;
-    
-    // Get messages
-    let messages = Message::get_by_conversation(&conn, &conversation_id)
-        .map_err(|e| e.to_string())?;
-    
-    // Build markdown
-    let mut markdown = String::new();
-    markdown.push_str(&format!("# {}\n\n", conversation.title.as_ref().unwrap_or(&"Untitled Conversation".to_string())));
-    markdown.push_str(&format!("Created: {}\n\n", conversation.created_at));
-    markdown.push_str("---\n\n");
-    
-    for msg in messages {
-        let role = if msg.role == "user" { "**You**" } else { "**Assistant**" };
-        markdown.push_str(&format!("### {}\n\n", role));
-        markdown.push_str(&format!("{}\n\n", msg.content));
-        markdown.push_str("---\n\n");
+#[tracing::instrument(skip_all, fields(conversation_id = %conversation_id), err)]
+async fn export_conversation(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    format: ExportFormat,
+    path: String,
+) -> Result<(), AppError> {
+    let export = load_export(&state, &conversation_id)?;
+    std::fs::write(&path, format.render(&export)).map_err(|e| AppError::file("write", &path, e))
+}
+
+/// Imports the conversations in a JSON export or ChatGPT `conversations.json` file
+#[tauri::command]
+#[tracing::instrument(skip_all, fields(agent_id = ?agent_id), err)]
+async fn import_conversations(
+    state: State<'_, AppState>,
+    path: String,
+    agent_id: Option<String>,
+    dry_run: bool,
+) -> Result<ImportReport, AppError> {
+    let json = std::fs::read_to_string(&path).map_err(|e| AppError::file("read", &path, e))?;
+    let conn = state.conn()?;
+    import::import_conversations(&conn, &state.cipher, &json, agent_id.as_deref(), dry_run)
+}
+
+/// Writes a consistent snapshot of the whole database to `path`
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+async fn backup_workspace(
+    state: State<'_, AppState>,
+    path: String,
+    options: Option<BackupOptions>,
+) -> Result<WorkspaceSummary, AppError> {
+    let conn = state.conn()?;
+    backup::backup(&conn, Path::new(&path), &options.unwrap_or_default()).map_err(AppError::from)
+}
+
+/// Replaces the database with the backup at `path`
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+async fn restore_workspace(state: State<'_, AppState>, path: String) -> Result<WorkspaceSummary, AppError> {
+    if !state.streams.lock().unwrap_or_else(|e| e.into_inner()).is_empty() {
+        return Err(AppError::Cancelled("Stop the responses being generated before restoring a backup".to_string()));
     }
-    
-    Ok(markdown)
+    let mut conn = state.conn()?;
+    let summary = backup::restore(&mut conn, Path::new(&path))?;
+    // Backups made without tokens leave them blank, which is stored encrypted too
+    Agent::encrypt_legacy_tokens(&conn, &state.cipher)?;
+    // A backup taken mid-stream carries that reply's draft
+    Message::recover_drafts(&conn)?;
+    Ok(summary)
 }
 
 #[tauri::command]
-async fn save_message(app: AppHandle, conversation_id: String, role: String, content: String) -> Result<Message, String> {
-    let conn = db::get_db_connection(&app).map_err(|e| e.to_string())?;
-    let message = Message::new(conversation_id, role, content, None);
-    message.save(&conn).map_err(|e| e.to_string())?;
+#[tracing::instrument(skip_all, fields(conversation_id = %conversation_id), err)]
+async fn save_message(state: State<'_, AppState>, conversation_id: String, role: String, content: String) -> Result<Message, AppError> {
+    let conn = state.conn()?;
+    let mut message = Message::new(conversation_id, role, content, None);
+    message.save(&conn)?;
     Ok(message)
 }
 
 #[tauri::command]
-async fn get_messages(app: AppHandle, conversation_id: String) -> Result<Vec<Message>, String> {
-    let conn = db::get_db_connection(&app).map_err(|e| e.to_string())?;
-    Message::get_by_conversation(&conn, &conversation_id).map_err(|e| e.to_string())
+#[tracing::instrument(skip_all, fields(conversation_id = %conversation_id), err)]
+async fn get_messages(state: State<'_, AppState>, conversation_id: String) -> Result<Vec<Message>, AppError> {
+    let conn = state.conn()?;
+    Message::get_by_conversation(&conn, &conversation_id).map_err(AppError::from)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, fields(agent_id = ?agent_id), err)]
+async fn search_messages(
+    state: State<'_, AppState>,
+    query: String,
+    agent_id: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Vec<SearchHit>, AppError> {
+    let conn = state.conn()?;
+    search::search_messages(
+        &conn,
+        &query,
+        agent_id.as_deref(),
+        limit.unwrap_or(20).clamp(1, 200),
+        offset.unwrap_or(0).max(0),
+    )
+    .map_err(AppError::from)
+}
+
+/// Reply counts, latency and token totals for replies started in `[from, to)` (milliseconds)
+#[tauri::command]
+#[tracing::instrument(skip_all, fields(agent_id = ?agent_id), err)]
+async fn get_usage_stats(
+    state: State<'_, AppState>,
+    agent_id: Option<String>,
+    from: i64,
+    to: i64,
+    group_by: UsageGroup,
+) -> Result<Vec<UsageBucket>, AppError> {
+    let conn = state.conn()?;
+    usage::get_usage_stats(&conn, agent_id.as_deref(), from, to, group_by).map_err(AppError::from)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, fields(message_id = %message_id), err)]
+async fn get_message_usage(state: State<'_, AppState>, message_id: String) -> Result<Option<MessageUsage>, AppError> {
+    let conn = state.conn()?;
+    MessageUsage::get(&conn, &message_id).map_err(AppError::from)
 }
 
 #[tauri::command]
+#[tracing::instrument(skip_all, fields(agent_id = %agent_id, conversation_id = %conversation_id), err)]
 async fn send_chat_message(
     app: AppHandle,
+    state: State<'_, AppState>,
     agent_id: String,
     conversation_id: String,
     message: String,
-) -> Result<String, String> {
-    // Get agent details
-    let conn = db::get_db_connection(&app).map_err(|e| e.to_string())?;
-    let agent = Agent::get_by_id(&conn, &agent_id)
-        .map_err(|e| e.to_string())?
-        .ok_or("Agent not found")?;
+) -> Result<String, AppError> {
+    let (agent, history) = {
+        // Get agent details
+        let conn = state.conn()?;
+        let agent = Agent::get_by_id(&conn, &agent_id)?
+            .ok_or_else(|| AppError::not_found("Agent not found"))?;
+        
+        // Earlier turns, read before the new user message is stored
+        let history = Message::get_recent(&conn, &conversation_id, agent.history_window)?;
+        (agent, history)
+    };
     
-    // Save user message
-    let user_msg = Message::new(conversation_id.clone(), "user".to_string(), message.clone(), None);
-    user_msg.save(&conn).map_err(|e| e.to_string())?;
+    let (_registration, cancel) = StreamRegistration::register(&state, &conversation_id)?;
     
-    // Send to Coze API with streaming
-    let (response, title, tool_calls) = send_message_to_coze_streaming(
-        &app,
-        &agent.api_url,
-        &agent.auth_token,
-        &agent.project_id,
-        &message,
-    )
-    .await
-    .map_err(|e| e.to_string())?;
+    // Save user message
+    let mut user_msg = Message::new(conversation_id.clone(), "user".to_string(), message.clone(), None);
+    user_msg.save(&*state.conn()?)?;
     
-    // Save assistant response
-    let assistant_msg = Message::new(conversation_id.clone(), "assistant".to_string(), response.clone(), tool_calls);
-    assistant_msg.save(&conn).map_err(|e| e.to_string())?;
+    let outcome = stream_reply(&app, &state, &agent, &user_msg, &history, &cancel).await?;
+    let response = outcome.content.clone();
+    let conn = state.conn()?;
 
     // Update conversation title if provided OR if it's currently untitled
-    let mut final_title = title;
+    let mut final_title = outcome.title;
     
     // If no title from AI, check if we should auto-generate from the first user message
     if final_title.is_none() {
-        let mut stmt = conn.prepare("SELECT title FROM conversations WHERE id = ?1")
-            .map_err(|e| e.to_string())?;
-        let current_title: Option<String> = stmt.query_row(params![conversation_id], |row| row.get(0))
-            .map_err(|e| e.to_string())?;
+        let mut stmt = conn.prepare("SELECT title FROM conversations WHERE id = ?1")?;
+        let current_title: Option<String> = stmt.query_row(params![conversation_id], |row| row.get(0))?;
             
         if current_title.is_none() || current_title == Some("Untitled Conversation".to_string()) {
             // Generate title from message (first 30 chars)
@@ -174,22 +427,284 @@ async fn send_chat_message(
     }
 
     if let Some(new_title) = final_title {
-        let mut stmt = conn.prepare("UPDATE conversations SET title = ?1 WHERE id = ?2")
-            .map_err(|e| e.to_string())?;
-        stmt.execute(params![new_title, conversation_id])
-            .map_err(|e| e.to_string())?;
+        let mut stmt = conn.prepare("UPDATE conversations SET title = ?1 WHERE id = ?2")?;
+        stmt.execute(params![new_title, conversation_id])?;
     }
     
     Ok(response)
 }
 
+/// Asks the agent again for the last user message; the new reply becomes another
+/// version of the same turn and the previous one stays selectable
+#[tauri::command]
+#[tracing::instrument(skip_all, fields(conversation_id = %conversation_id), err)]
+async fn regenerate_response(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    conversation_id: String,
+) -> Result<String, AppError> {
+    let (agent, user_msg, history) = {
+        let conn = state.conn()?;
+        let conversation = Conversation::get_by_id(&conn, &conversation_id)?
+            .ok_or_else(|| AppError::not_found("Conversation not found"))?;
+        let agent = Agent::get_by_id(&conn, &conversation.agent_id)?
+            .ok_or_else(|| AppError::not_found("Agent not found"))?;
+        
+        let mut path = Message::get_by_conversation(&conn, &conversation_id)?;
+        let last_user = path
+            .iter()
+            .rposition(|m| m.role == "user")
+            .ok_or_else(|| AppError::validation("There is no message to regenerate a reply for"))?;
+        path.truncate(last_user + 1);
+        let user_msg = path.pop().ok_or_else(|| AppError::validation("There is no message to regenerate a reply for"))?;
+        let skip = path.len().saturating_sub(agent.history_window.max(0) as usize);
+        (agent, user_msg, path.split_off(skip))
+    };
+    
+    let (_registration, cancel) = StreamRegistration::register(&state, &conversation_id)?;
+    let outcome = stream_reply(&app, &state, &agent, &user_msg, &history, &cancel).await?;
+    Ok(outcome.content)
+}
+
+/// Shows another version of the turn `message_id` belongs to and returns the resulting path
+#[tauri::command]
+#[tracing::instrument(skip_all, fields(message_id = %message_id), err)]
+async fn switch_message_version(
+    state: State<'_, AppState>,
+    message_id: String,
+    version: i64,
+) -> Result<Vec<Message>, AppError> {
+    let conn = state.conn()?;
+    let conversation_id = Message::select_version(&conn, &message_id, version)?
+        .ok_or_else(|| AppError::not_found("Message version not found"))?;
+    Message::get_by_conversation(&conn, &conversation_id).map_err(AppError::from)
+}
+
+/// Rephrases an earlier user message: the new text becomes a sibling of the original,
+/// starting a branch that gets its own reply, while the original thread is kept
+#[tauri::command]
+#[tracing::instrument(skip_all, fields(message_id = %message_id), err)]
+async fn edit_message(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    message_id: String,
+    new_content: String,
+) -> Result<String, AppError> {
+    let (agent, original, history) = {
+        let conn = state.conn()?;
+        let original = Message::get_by_id(&conn, &message_id)?
+            .ok_or_else(|| AppError::not_found("Message not found"))?;
+        if original.role != "user" {
+            return Err(AppError::validation("Only user messages can be edited"));
+        }
+        let conversation = Conversation::get_by_id(&conn, &original.conversation_id)?
+            .ok_or_else(|| AppError::not_found("Conversation not found"))?;
+        let agent = Agent::get_by_id(&conn, &conversation.agent_id)?
+            .ok_or_else(|| AppError::not_found("Agent not found"))?;
+        
+        // History is what led up to the original message
+        let history = match &original.parent_id {
+            Some(parent_id) => Message::get_path(&conn, parent_id, agent.history_window.max(0))?,
+            None => Vec::new(),
+        };
+        (agent, original, history)
+    };
+    
+    let (_registration, cancel) = StreamRegistration::register(&state, &original.conversation_id)?;
+    
+    let mut edited = Message::new(original.conversation_id.clone(), "user".to_string(), new_content, None);
+    edited
+        .save_under(&*state.conn()?, original.parent_id.clone())?;
+    
+    let outcome = stream_reply(&app, &state, &agent, &edited, &history, &cancel).await?;
+    Ok(outcome.content)
+}
+
+/// Copies the conversation up to `up_to_message_id` into a new conversation,
+/// optionally under another agent
+#[tauri::command]
+#[tracing::instrument(skip_all, fields(conversation_id = %conversation_id, up_to_message_id = %up_to_message_id, target_agent_id = ?target_agent_id), err)]
+async fn fork_conversation(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    up_to_message_id: String,
+    target_agent_id: Option<String>,
+) -> Result<Conversation, AppError> {
+    let conn = state.conn()?;
+    if let Some(agent_id) = &target_agent_id {
+        Agent::get_by_id(&conn, agent_id)?
+            .ok_or_else(|| AppError::not_found("Agent not found"))?;
+    }
+    Conversation::fork(&conn, &conversation_id, &up_to_message_id, target_agent_id.as_deref())?
+        .ok_or_else(|| AppError::not_found("Message not found in this conversation"))
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, fields(conversation_id = %conversation_id), err)]
+async fn get_branches(state: State<'_, AppState>, conversation_id: String) -> Result<Vec<Branch>, AppError> {
+    let conn = state.conn()?;
+    Message::get_branches(&conn, &conversation_id).map_err(AppError::from)
+}
+
+/// Shows the path ending at `message_id` and returns it
+#[tauri::command]
+#[tracing::instrument(skip_all, fields(conversation_id = %conversation_id, message_id = %message_id), err)]
+async fn select_branch(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    message_id: String,
+) -> Result<Vec<Message>, AppError> {
+    let conn = state.conn()?;
+    if !Message::select_branch(&conn, &conversation_id, &message_id)? {
+        return Err(AppError::not_found("Message not found in this conversation"));
+    }
+    Message::get_by_conversation(&conn, &conversation_id).map_err(AppError::from)
+}
+
+/// Streams the agent's answer to `user_msg` and stores it as a reply to that message.
+/// The reply is saved as a draft from the start and rewritten as it grows, so a crash
+/// or a dropped connection keeps what had arrived. A stopped stream keeps whatever
+/// arrived before the stop.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        request_id = %uuid::Uuid::new_v4(),
+        agent_id = %agent.id,
+        conversation_id = %user_msg.conversation_id,
+        message_id = tracing::field::Empty,
+    )
+)]
+async fn stream_reply(
+    app: &AppHandle,
+    state: &AppState,
+    agent: &Agent,
+    user_msg: &Message,
+    history: &[Message],
+    cancel: &CancellationToken,
+) -> Result<StreamOutcome, AppError> {
+    let conversation_id = &user_msg.conversation_id;
+    let request = CozeRequest::new(&agent.project_id, history, &user_msg.content);
+    
+    let auth_token = state.cipher.decrypt(&agent.auth_token)?;
+    let policy = RequestPolicy::for_agent(agent);
+    let client = state.http.client(&policy)?;
+    
+    // The reply's id is fixed up front so stream events can refer to it
+    let mut assistant_msg = Message::new(conversation_id.clone(), "assistant".to_string(), String::new(), None);
+    assistant_msg.status = "streaming".to_string();
+    assistant_msg
+        .save_under(&*state.conn()?, Some(user_msg.id.clone()))?;
+    tracing::Span::current().record("message_id", assistant_msg.id.as_str());
+    tracing::info!(history = history.len(), "stream started");
+    let sink = DraftSink::new(app, &state.db, &assistant_msg.id, DRAFT_FLUSH_INTERVAL);
+    let emitter = StreamEmitter::new(&sink, conversation_id, &assistant_msg.id);
+    
+    // Send to Coze API with streaming
+    let result = send_message_to_coze_streaming(
+        &emitter,
+        &client,
+        &policy,
+        &agent.api_url,
+        &auth_token,
+        &request,
+        cancel,
+    )
+    .await;
+    
+    // The stream can run for minutes, so only take a connection back once it is done.
+    // Should that fail, the draft is marked interrupted on the next start.
+    let conn = state.conn()?;
+    let (content, tool_calls, status) = match &result {
+        Ok(outcome) => (
+            outcome.content.clone(),
+            outcome.tool_calls.clone(),
+            if outcome.cancelled { "interrupted" } else { "complete" },
+        ),
+        Err(_) => {
+            let (content, tool_calls) = sink.snapshot();
+            (content, tool_calls, "error")
+        }
+    };
+    let received_nothing = content.is_empty() && tool_calls.is_none();
+    if received_nothing && status != "complete" {
+        Message::discard_draft(&conn, &assistant_msg.id)?;
+    } else {
+        Message::finish(&conn, &assistant_msg.id, &content, tool_calls.as_deref(), status)?;
+        if let Ok(outcome) = &result {
+            MessageUsage::new(&assistant_msg.id, &agent.id, conversation_id, &content, &outcome.stats).save(&conn)?;
+        }
+    }
+    match &result {
+        Ok(outcome) => tracing::info!(
+            status,
+            duration_ms = outcome.stats.duration_ms,
+            first_token_ms = outcome.stats.first_token_ms,
+            response_bytes = outcome.stats.response_bytes,
+            total_tokens = outcome.stats.usage.and_then(|u| u.total_tokens),
+            "stream finished"
+        ),
+        Err(AppError::Cancelled(_)) => tracing::info!("stream stopped before a response"),
+        Err(e) => tracing::warn!(status, kept_chars = content.chars().count(), error = %e, "stream failed"),
+    }
+    
+    result
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, fields(conversation_id = %conversation_id), err)]
+async fn stop_generation(state: State<'_, AppState>, conversation_id: String) -> Result<bool, AppError> {
+    let streams = state.streams.lock().unwrap_or_else(|e| e.into_inner());
+    match streams.get(&conversation_id) {
+        Some(token) => {
+            token.cancel();
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// The newest lines of the log files, to attach to a bug report
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+async fn get_recent_logs(logging: State<'_, Logging>, lines: Option<usize>) -> Result<String, AppError> {
+    logging.recent(lines.unwrap_or(500).clamp(1, 10_000))
+}
+
+/// Sets verbosity, e.g. `debug` or `info,tauri_app_lib::chat=trace`; kept for later starts
+#[tauri::command]
+#[tracing::instrument(skip(logging), err)]
+async fn set_log_level(logging: State<'_, Logging>, level: String) -> Result<(), AppError> {
+    logging.set_filter(&level)?;
+    tracing::info!("log level changed");
+    Ok(())
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_dialog::init())
         .setup(|app| {
-            // Initialize database on startup
-            db::init_db(&app.handle()).expect("Failed to initialize database");
+            let logging = Logging::init(&app.path().app_data_dir()?.join("logs"))?;
+            app.manage(logging);
+            tracing::info!(version = env!("CARGO_PKG_VERSION"), "starting");
+            
+            // Initialize database on startup; a failed migration stops the app
+            // rather than letting it run against a half-upgraded schema
+            let pool = db::init_db(app.handle())?;
+            
+            // Tokens are encrypted with a key kept next to, not inside, the database
+            let key_path = app.path().app_data_dir()?.join("coze_studio.key");
+            let cipher = TokenCipher::load_or_create(&key_path)?;
+            Agent::encrypt_legacy_tokens(&*pool.get()?, &cipher)?;
+            
+            // Nothing streams yet, so any draft reply is left over from a crash
+            let recovered = Message::recover_drafts(&*pool.get()?)?;
+            if recovered > 0 {
+                tracing::warn!(recovered, "marked replies left streaming by a crash as interrupted");
+            }
+            
+            app.manage(AppState::new(pool, cipher));
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -199,14 +714,33 @@ pub fn run() {
             get_agent,
             update_agent,
             delete_agent,
+            test_agent_connection,
+            export_agents,
+            import_agents,
             create_conversation,
             get_conversations,
             delete_conversation,
             update_conversation,
             export_conversation_markdown,
+            export_conversation,
+            import_conversations,
+            backup_workspace,
+            restore_workspace,
             save_message,
             get_messages,
+            search_messages,
+            get_usage_stats,
+            get_message_usage,
             send_chat_message,
+            stop_generation,
+            regenerate_response,
+            switch_message_version,
+            edit_message,
+            get_branches,
+            select_branch,
+            fork_conversation,
+            get_recent_logs,
+            set_log_level,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,253 @@
+//! Log setup. Events go to stderr and to a daily file under `logs/` in the app
+//! data dir, with anything that looks like a token scrubbed on the way out.
+//!
+//! Verbosity is an `EnvFilter` directive (`info`, `debug`,
+//! `info,tauri_app_lib::chat=trace`, ...) taken from `COZE_STUDIO_LOG`, else the
+//! one last saved with `set_log_level`, else `info`.
+
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::{self, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+use crate::error::AppError;
+
+pub const LOG_FILTER_ENV: &str = "COZE_STUDIO_LOG";
+pub const DEFAULT_FILTER: &str = "info";
+
+const LOG_FILE_PREFIX: &str = "coze_studio";
+const LOG_FILE_SUFFIX: &str = "log";
+
+// Daily files kept before the oldest is deleted
+const MAX_LOG_FILES: usize = 7;
+
+// Holds the directive saved with `set_log_level`, next to the log files
+const FILTER_FILE: &str = "log_filter";
+
+// Credentials by key (`auth_token: "…"`, `"authorization":"…"`), bearer headers
+// and Coze personal/service access tokens
+static SECRETS: LazyLock<[(Regex, &str); 3]> = LazyLock::new(|| {
+    [
+        (
+            Regex::new(
+                r#"(?i)\b(auth_token|authorization|token)(\\?"?\s*[:=]\s*)(Some\()?(?:\\?"(?:bearer\s+)?[^"\\]*\\?"|(?:bearer\s+)?[^\s",})\\]+)"#,
+            )
+            .unwrap(),
+            r#"${1}${2}${3}"[redacted]""#,
+        ),
+        (Regex::new(r"(?i)\bbearer\s+[A-Za-z0-9._~+/=-]+").unwrap(), "Bearer [redacted]"),
+        (Regex::new(r"\b(pat|sat)_[A-Za-z0-9]{8,}").unwrap(), "${1}_[redacted]"),
+    ]
+});
+
+/// Blanks out tokens in a formatted log line
+pub fn redact(text: &str) -> Cow<'_, str> {
+    let mut text = Cow::Borrowed(text);
+    for (pattern, replacement) in SECRETS.iter() {
+        if let Cow::Owned(replaced) = pattern.replace_all(&text, *replacement) {
+            text = Cow::Owned(replaced);
+        }
+    }
+    text
+}
+
+pub struct Logging {
+    dir: PathBuf,
+    filter: reload::Handle<EnvFilter, Registry>,
+    // Flushes the file writer when the app exits
+    _guard: WorkerGuard,
+}
+
+impl Logging {
+    /// Installs the global subscriber writing to `dir`; call once, before anything logs
+    pub fn init(dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        fs::create_dir_all(dir)?;
+        let file = RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix(LOG_FILE_PREFIX)
+            .filename_suffix(LOG_FILE_SUFFIX)
+            .max_log_files(MAX_LOG_FILES)
+            .build(dir)?;
+        let (file, guard) = tracing_appender::non_blocking(file);
+        let (filter, handle) = reload::Layer::new(initial_filter(dir));
+
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(fmt::layer().with_ansi(false).with_writer(Redacting(file)))
+            .with(fmt::layer().with_writer(Redacting(io::stderr)))
+            .try_init()?;
+
+        Ok(Self { dir: dir.to_path_buf(), filter: handle, _guard: guard })
+    }
+
+    /// Switches verbosity now and for later starts
+    pub fn set_filter(&self, directives: &str) -> Result<(), AppError> {
+        let filter = parse_filter(directives)?;
+        self.filter.reload(filter).map_err(|e| AppError::validation(e.to_string()))?;
+        save_filter(&self.dir, directives)
+    }
+
+    /// The last `count` lines logged, oldest first
+    pub fn recent(&self, count: usize) -> Result<String, AppError> {
+        recent_lines(&self.dir, count)
+    }
+}
+
+fn parse_filter(directives: &str) -> Result<EnvFilter, AppError> {
+    EnvFilter::try_new(directives.trim())
+        .map_err(|e| AppError::validation(format!("Invalid log level \"{}\": {}", directives, e)))
+}
+
+fn save_filter(dir: &Path, directives: &str) -> Result<(), AppError> {
+    let path = dir.join(FILTER_FILE);
+    fs::write(&path, directives.trim()).map_err(|e| AppError::file("write", &path.to_string_lossy(), e))
+}
+
+// An unreadable or invalid setting falls through to the next source
+fn initial_filter(dir: &Path) -> EnvFilter {
+    [std::env::var(LOG_FILTER_ENV).ok(), fs::read_to_string(dir.join(FILTER_FILE)).ok()]
+        .into_iter()
+        .flatten()
+        .find_map(|directives| parse_filter(&directives).ok())
+        .unwrap_or_else(|| EnvFilter::new(DEFAULT_FILTER))
+}
+
+fn recent_lines(dir: &Path, count: usize) -> Result<String, AppError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(String::new()),
+        Err(e) => return Err(AppError::file("read", &dir.to_string_lossy(), e)),
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name().and_then(|n| n.to_str()).is_some_and(|name| {
+                name.starts_with(&format!("{}.", LOG_FILE_PREFIX)) && name.ends_with(&format!(".{}", LOG_FILE_SUFFIX))
+            })
+        })
+        .collect();
+    // Names carry the date, so they sort oldest first
+    files.sort();
+
+    let mut lines = VecDeque::with_capacity(count);
+    for path in files.iter().rev() {
+        let bytes = fs::read(path).map_err(|e| AppError::file("read", &path.to_string_lossy(), e))?;
+        for line in String::from_utf8_lossy(&bytes).lines().rev() {
+            if lines.len() == count {
+                break;
+            }
+            lines.push_front(line.to_string());
+        }
+        if lines.len() == count {
+            break;
+        }
+    }
+    Ok(Vec::from(lines).join("\n"))
+}
+
+// Wraps a writer so nothing that looks like a credential reaches the logs
+struct Redacting<M>(M);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+struct RedactingWriter<W>(W);
+
+impl<W: Write> Write for RedactingWriter<W> {
+    // Formatting layers hand over each event whole, so patterns never straddle writes
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        self.0.write_all(redact(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Captured;
+    use tracing_subscriber::filter::LevelFilter;
+
+    #[test]
+    fn redacts_tokens_by_key_header_and_prefix() {
+        assert_eq!(
+            redact(r#"Agent { id: "a1", auth_token: "enc:v1:abc", name: "Analyst" }"#),
+            r#"Agent { id: "a1", auth_token: "[redacted]", name: "Analyst" }"#
+        );
+        assert_eq!(redact(r#"{"auth_token":"pat_secret"}"#), r#"{"auth_token":"[redacted]"}"#);
+        assert_eq!(redact(r#"auth_token: Some("abc")"#), r#"auth_token: Some("[redacted]")"#);
+        assert_eq!(redact("auth_token=abc next=1"), r#"auth_token="[redacted]" next=1"#);
+        assert_eq!(redact("header Authorization: Bearer abc.def"), r#"header Authorization: "[redacted]""#);
+        assert_eq!(redact("sent Bearer abc.def to host"), "sent Bearer [redacted] to host");
+        assert_eq!(redact("body mentions pat_AbCdEf123456"), "body mentions pat_[redacted]");
+    }
+
+    #[test]
+    fn formatted_events_are_redacted() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(fmt::layer().with_ansi(false).with_writer(Redacting(move || writer.clone())));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("stream", agent_id = "a1");
+            let _entered = span.enter();
+            tracing::warn!(auth_token = "pat_AbCdEf123456", "request rejected");
+        });
+
+        let output = captured.output();
+        assert!(output.contains("request rejected"));
+        assert!(output.contains("agent_id=\"a1\""));
+        assert!(!output.contains("AbCdEf123456"), "{}", output);
+    }
+
+    #[test]
+    fn leaves_other_text_alone() {
+        let line = "stream finished total_tokens=57 input_tokens=41 status=complete";
+        assert!(matches!(redact(line), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn reads_the_last_lines_across_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("coze_studio.2024-03-01.log"), "one\ntwo\nthree\n").unwrap();
+        fs::write(dir.path().join("coze_studio.2024-03-02.log"), "four\nfive\n").unwrap();
+        fs::write(dir.path().join(FILTER_FILE), "debug").unwrap();
+
+        assert_eq!(recent_lines(dir.path(), 3).unwrap(), "three\nfour\nfive");
+        assert_eq!(recent_lines(dir.path(), 100).unwrap(), "one\ntwo\nthree\nfour\nfive");
+        assert_eq!(recent_lines(&dir.path().join("missing"), 10).unwrap(), "");
+    }
+
+    #[test]
+    fn saved_level_is_validated_and_used_on_start() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(parse_filter("nonsense=[").unwrap_err().code(), "validation");
+
+        save_filter(dir.path(), "warn,tauri_app_lib=debug\n").unwrap();
+        if std::env::var(LOG_FILTER_ENV).is_err() {
+            assert_eq!(initial_filter(dir.path()).max_level_hint(), Some(LevelFilter::DEBUG));
+        }
+        fs::write(dir.path().join(FILTER_FILE), "nonsense=[").unwrap();
+        if std::env::var(LOG_FILTER_ENV).is_err() {
+            assert_eq!(initial_filter(dir.path()).max_level_hint(), Some(LevelFilter::INFO));
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use serde::{Deserialize, Serialize};
+use rusqlite::{params, Connection, Result as SqlResult, Row, ToSql};
+
+// The trigram index cannot match anything shorter than this
+const MIN_INDEXED_TERM_CHARS: usize = 3;
+
+// Snippet length in index tokens; with trigrams that is roughly characters
+const SNIPPET_TOKENS: i64 = 64;
+
+// Private-use characters mark matches inside snippets until the text is HTML-escaped
+const MATCH_START: char = '\u{E000}';
+const MATCH_END: char = '\u{E001}';
+
+// Characters of context kept around a match in fallback snippets
+const SNIPPET_CONTEXT_CHARS: usize = 40;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub message_id: String,
+    pub conversation_id: String,
+    pub conversation_title: Option<String>,
+    pub agent_id: String,
+    pub agent_name: String,
+    pub role: String,
+    // HTML-escaped excerpt with matches wrapped in <mark></mark>
+    pub snippet: String,
+    pub created_at: i64,
+}
+
+/// Ranked search over message text and tool call inputs/outputs, optionally limited to one agent
+pub fn search_messages(
+    conn: &Connection,
+    query: &str,
+    agent_id: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> SqlResult<Vec<SearchHit>> {
+    let terms: Vec<&str> = query.split_whitespace().collect();
+    if terms.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if terms.iter().all(|t| t.chars().count() >= MIN_INDEXED_TERM_CHARS) {
+        search_index(conn, &terms, agent_id, limit, offset)
+    } else {
+        search_substring(conn, &terms, agent_id, limit, offset)
+    }
+}
+
+fn search_index(
+    conn: &Connection,
+    terms: &[&str],
+    agent_id: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> SqlResult<Vec<SearchHit>> {
+    // Quote every term so user input can never be parsed as FTS5 query syntax
+    let match_expr = terms
+        .iter()
+        .map(|t| format!("\"{}\"", t.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut stmt = conn.prepare(
+        "SELECT m.id, m.conversation_id, c.title, c.agent_id, a.name, m.role,
+                snippet(messages_fts, -1, ?5, ?6, '…', ?7), m.created_at
+         FROM messages_fts
+         JOIN messages m ON m.rowid = messages_fts.rowid
+         JOIN conversations c ON c.id = m.conversation_id
+         JOIN agents a ON a.id = c.agent_id
+         WHERE messages_fts MATCH ?1 AND (?2 IS NULL OR c.agent_id = ?2)
+         ORDER BY bm25(messages_fts)
+         LIMIT ?3 OFFSET ?4"
+    )?;
+
+    let hits = stmt.query_map(
+        params![
+            match_expr,
+            agent_id,
+            limit,
+            offset,
+            MATCH_START.to_string(),
+            MATCH_END.to_string(),
+            SNIPPET_TOKENS
+        ],
+        |row| hit_from_row(row, |snippet| snippet),
+    )?
+    .collect::<SqlResult<Vec<_>, _>>()?;
+
+    Ok(hits)
+}
+
+// Short terms fall back to a plain substring scan, newest first
+fn search_substring(
+    conn: &Connection,
+    terms: &[&str],
+    agent_id: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> SqlResult<Vec<SearchHit>> {
+    // One condition per term, bound from ?4 on, so paging happens in SQL
+    let conditions = (0..terms.len())
+        .map(|i| format!("instr(lower(m.content || ifnull(m.tool_calls, '')), lower(?{})) > 0", i + 4))
+        .collect::<Vec<_>>()
+        .join(" AND ");
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT m.id, m.conversation_id, c.title, c.agent_id, a.name, m.role,
+                m.content, m.created_at
+         FROM messages m
+         JOIN conversations c ON c.id = m.conversation_id
+         JOIN agents a ON a.id = c.agent_id
+         WHERE {conditions}
+           AND (?1 IS NULL OR c.agent_id = ?1)
+         ORDER BY m.created_at DESC, m.seq DESC
+         LIMIT ?2 OFFSET ?3"
+    ))?;
+
+    let mut values: Vec<&dyn ToSql> = vec![&agent_id, &limit, &offset];
+    values.extend(terms.iter().map(|t| t as &dyn ToSql));
+
+    // Snippets come from the message text; a match only in tool calls shows its opening
+    let hits = stmt.query_map(values.as_slice(), |row| {
+        hit_from_row(row, |content| {
+            let lower = content.to_lowercase();
+            let term = terms.iter().find(|t| lower.contains(&t.to_lowercase())).unwrap_or(&terms[0]);
+            mark_first_match(&content, term)
+        })
+    })?
+    .collect::<SqlResult<Vec<_>, _>>()?;
+
+    Ok(hits)
+}
+
+fn hit_from_row(row: &Row, make_snippet: impl FnOnce(String) -> String) -> SqlResult<SearchHit> {
+    let raw_snippet: String = row.get(6)?;
+    Ok(SearchHit {
+        message_id: row.get(0)?,
+        conversation_id: row.get(1)?,
+        conversation_title: row.get(2)?,
+        agent_id: row.get(3)?,
+        agent_name: row.get(4)?,
+        role: row.get(5)?,
+        snippet: highlight_html(&make_snippet(raw_snippet)),
+        created_at: row.get(7)?,
+    })
+}
+
+/// Excerpt around the first case-insensitive occurrence of `term`, with match markers
+fn mark_first_match(text: &str, term: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let needle: Vec<char> = term.to_lowercase().chars().collect();
+    let lowered: Vec<char> = chars.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+
+    let Some(start) = lowered.windows(needle.len()).position(|w| w == needle.as_slice()) else {
+        return chars.iter().take(SNIPPET_CONTEXT_CHARS * 2).collect();
+    };
+    let end = start + needle.len();
+    let from = start.saturating_sub(SNIPPET_CONTEXT_CHARS);
+    let to = (end + SNIPPET_CONTEXT_CHARS).min(chars.len());
+
+    let mut snippet = String::new();
+    if from > 0 {
+        snippet.push('…');
+    }
+    snippet.extend(&chars[from..start]);
+    snippet.push(MATCH_START);
+    snippet.extend(&chars[start..end]);
+    snippet.push(MATCH_END);
+    snippet.extend(&chars[end..to]);
+    if to < chars.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+/// Escapes the excerpt for HTML and turns the match markers into <mark> tags
+fn highlight_html(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            '\n' => html.push(' '),
+            _ => html.push(c),
+        }
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{Conversation, Message, ToolCall};
+    use crate::test_support::setup_conversation;
+
+    fn save(conn: &Connection, conversation: &Conversation, content: &str, tool_calls: Option<Vec<ToolCall>>) -> Message {
+        let mut message = Message::new(conversation.id.clone(), "assistant".to_string(), content.to_string(), tool_calls);
+        message.save(conn).unwrap();
+        message
+    }
+
+    #[test]
+    fn finds_message_content_with_highlighted_snippet() {
+        let (conn, conversation) = setup_conversation(Some("Weather"));
+        let message = save(&conn, &conversation, "The forecast for <Beijing> is sunny", None);
+        save(&conn, &conversation, "Unrelated answer", None);
+
+        let hits = search_messages(&conn, "forecast", None, 10, 0).unwrap();
+
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message_id, message.id);
+        assert_eq!(hits[0].agent_name, "Analyst");
+        assert_eq!(hits[0].conversation_title.as_deref(), Some("Weather"));
+        assert_eq!(hits[0].snippet, "The <mark>forecast</mark> for &lt;Beijing&gt; is sunny");
+    }
+
+    #[test]
+    fn finds_tool_call_output() {
+        let (conn, conversation) = setup_conversation(Some("Weather"));
+        let message = save(&conn, &conversation, "Done", Some(vec![ToolCall {
+            id: "call_1".to_string(),
+            tool_name: "get_weather".to_string(),
+            tool_input: "{\"city\": \"Hangzhou\"}".to_string(),
+            tool_output: Some("drizzle all afternoon".to_string()),
+            status: "success".to_string(),
+        }]));
+
+        assert_eq!(search_messages(&conn, "drizzle", None, 10, 0).unwrap()[0].message_id, message.id);
+        assert_eq!(search_messages(&conn, "Hangzhou", None, 10, 0).unwrap()[0].message_id, message.id);
+    }
+
+    #[test]
+    fn deleted_messages_leave_the_index() {
+        let (conn, conversation) = setup_conversation(Some("Weather"));
+        save(&conn, &conversation, "ephemeral content", None);
+
+        Conversation::delete(&conn, &conversation.id).unwrap();
+
+        assert!(search_messages(&conn, "ephemeral", None, 10, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn query_syntax_is_treated_as_text() {
+        let (conn, conversation) = setup_conversation(Some("Weather"));
+        save(&conn, &conversation, "use a OR b AND \"quotes\"", None);
+
+        assert_eq!(search_messages(&conn, "\"quotes\" OR", None, 10, 0).unwrap().len(), 1);
+        assert!(search_messages(&conn, "NEAR(", None, 10, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn short_terms_fall_back_to_substring_search() {
+        let (conn, conversation) = setup_conversation(Some("Weather"));
+        save(&conn, &conversation, "明天天气怎么样", None);
+
+        let hits = search_messages(&conn, "天气", None, 10, 0).unwrap();
+
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snippet, "明天<mark>天气</mark>怎么样");
+    }
+
+    #[test]
+    fn short_term_results_are_paged_newest_first() {
+        let (conn, conversation) = setup_conversation(Some("Weather"));
+        let older = save(&conn, &conversation, "雨 older", None);
+        let newer = save(&conn, &conversation, "雨 newer", None);
+        save(&conn, &conversation, "晴 unrelated", None);
+
+        let first = search_messages(&conn, "雨", None, 1, 0).unwrap();
+        let second = search_messages(&conn, "雨", None, 1, 1).unwrap();
+
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].message_id, newer.id);
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].message_id, older.id);
+        assert!(search_messages(&conn, "雨", None, 10, 2).unwrap().is_empty());
+        assert_eq!(search_messages(&conn, "雨 older", None, 10, 0).unwrap()[0].message_id, older.id);
+    }
+
+    #[test]
+    fn short_term_snippets_leave_out_tool_call_json() {
+        let (conn, conversation) = setup_conversation(Some("Weather"));
+        save(&conn, &conversation, "Done", Some(vec![ToolCall {
+            id: "call_1".to_string(),
+            tool_name: "get_weather".to_string(),
+            tool_input: "{\"city\": \"杭州\"}".to_string(),
+            tool_output: None,
+            status: "success".to_string(),
+        }]));
+
+        let hits = search_messages(&conn, "杭州", None, 10, 0).unwrap();
+
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snippet, "Done");
+    }
+
+    #[test]
+    fn filters_by_agent() {
+        let (conn, conversation) = setup_conversation(Some("Weather"));
+        save(&conn, &conversation, "searchable text", None);
+
+        assert_eq!(search_messages(&conn, "searchable", Some(&conversation.agent_id), 10, 0).unwrap().len(), 1);
+        assert!(search_messages(&conn, "searchable", Some("other-agent"), 10, 0).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,272 @@
+//! Incremental decoder for `text/event-stream` responses, following the
+//! WHATWG Server-Sent Events parsing rules.
+//!
+//! Input is buffered as bytes and only split on line terminators, so a
+//! multi-byte UTF-8 character split across network chunks is decoded intact.
+
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    /// Value of the `event:` field; `None` means the default "message" type
+    pub event: Option<String>,
+    /// All `data:` lines of the event joined with `\n`
+    pub data: String,
+    /// Last event id seen so far in the stream, if any
+    pub id: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    // A CR ended the previous chunk, so an LF opening the next one belongs to it
+    after_cr: bool,
+    started: bool,
+    event_type: String,
+    data: String,
+    last_event_id: Option<String>,
+    retry_ms: Option<u64>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reconnection time the server asked for with `retry:`, in milliseconds
+    pub fn retry_ms(&self) -> Option<u64> {
+        self.retry_ms
+    }
+
+    /// Feeds the next chunk of the response and returns every event it completed
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        let mut chunk = chunk;
+        if self.after_cr && !chunk.is_empty() {
+            if chunk[0] == b'\n' {
+                chunk = &chunk[1..];
+            }
+            self.after_cr = false;
+        }
+        self.buffer.extend_from_slice(chunk);
+
+        if !self.started {
+            if self.buffer.len() < BOM.len() && BOM.starts_with(&self.buffer) {
+                return Vec::new();
+            }
+            if self.buffer.starts_with(BOM) {
+                self.buffer.drain(..BOM.len());
+            }
+            self.started = true;
+        }
+
+        let mut events = Vec::new();
+        let mut line_start = 0;
+        let mut i = 0;
+        while i < self.buffer.len() {
+            match self.buffer[i] {
+                b'\n' => {
+                    let line = self.buffer[line_start..i].to_vec();
+                    events.extend(self.process_line(&line));
+                    line_start = i + 1;
+                }
+                b'\r' => {
+                    let line = self.buffer[line_start..i].to_vec();
+                    events.extend(self.process_line(&line));
+                    if i + 1 < self.buffer.len() {
+                        if self.buffer[i + 1] == b'\n' {
+                            i += 1;
+                        }
+                    } else {
+                        self.after_cr = true;
+                    }
+                    line_start = i + 1;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        self.buffer.drain(..line_start);
+        events
+    }
+
+    /// Ends the stream. Unlike the spec, which discards an event left without its
+    /// closing blank line, a pending event is still delivered: some servers close
+    /// the connection right after the last `data:` line.
+    pub fn finish(&mut self) -> Vec<SseEvent> {
+        let mut events = Vec::new();
+        if !self.buffer.is_empty() {
+            let line = std::mem::take(&mut self.buffer);
+            events.extend(self.process_line(&line));
+        }
+        events.extend(self.dispatch());
+        events
+    }
+
+    fn process_line(&mut self, line: &[u8]) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line[0] == b':' {
+            return None; // comment
+        }
+
+        let (field, value) = match line.iter().position(|&b| b == b':') {
+            Some(colon) => {
+                let value = &line[colon + 1..];
+                (&line[..colon], value.strip_prefix(b" ").unwrap_or(value))
+            }
+            None => (line, &[][..]),
+        };
+        let value = String::from_utf8_lossy(value);
+
+        match field {
+            b"event" => self.event_type = value.into_owned(),
+            b"data" => {
+                self.data.push_str(&value);
+                self.data.push('\n');
+            }
+            b"id" if !value.contains('\0') => self.last_event_id = Some(value.into_owned()),
+            b"retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                self.retry_ms = value.parse().ok();
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event_type = std::mem::take(&mut self.event_type);
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let mut data = std::mem::take(&mut self.data);
+        data.pop(); // trailing '\n' added after the last data line
+        Some(SseEvent {
+            event: (!event_type.is_empty()).then_some(event_type),
+            data,
+            id: self.last_event_id.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANSWER_STREAM: &[u8] = include_bytes!("../tests/fixtures/coze_answer.sse");
+    const TOOL_CALL_STREAM: &[u8] = include_bytes!("../tests/fixtures/coze_tool_calls.sse");
+    const MULTILINE_CRLF_STREAM: &[u8] = include_bytes!("../tests/fixtures/coze_multiline_crlf.sse");
+
+    fn decode_in_chunks(input: &[u8], chunk_size: usize) -> Vec<SseEvent> {
+        let mut decoder = SseDecoder::new();
+        let mut events = Vec::new();
+        for chunk in input.chunks(chunk_size) {
+            events.extend(decoder.push(chunk));
+        }
+        events.extend(decoder.finish());
+        events
+    }
+
+    fn data(events: &[SseEvent]) -> Vec<&str> {
+        events.iter().map(|e| e.data.as_str()).collect()
+    }
+
+    #[test]
+    fn decodes_recorded_answer_stream() {
+        let events = decode_in_chunks(ANSWER_STREAM, ANSWER_STREAM.len());
+
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].event.as_deref(), Some("message"));
+        assert_eq!(events[0].id.as_deref(), Some("1"));
+        assert_eq!(
+            events[1].data,
+            r#"{"type":"answer","content":{"answer":"今天杭州天气晴朗。"},"session_id":"c1"}"#
+        );
+        assert_eq!(events[3].id.as_deref(), Some("4"));
+    }
+
+    #[test]
+    fn multi_byte_characters_survive_any_chunking() {
+        let whole = decode_in_chunks(TOOL_CALL_STREAM, TOOL_CALL_STREAM.len());
+
+        for chunk_size in 1..=7 {
+            assert_eq!(decode_in_chunks(TOOL_CALL_STREAM, chunk_size), whole, "chunk size {}", chunk_size);
+        }
+        assert!(whole[2].data.contains("杭州今天晴，22°C。"));
+    }
+
+    #[test]
+    fn joins_multi_line_data_with_crlf_line_endings() {
+        for chunk_size in [1, 2, 3, MULTILINE_CRLF_STREAM.len()] {
+            let mut decoder = SseDecoder::new();
+            let mut events = Vec::new();
+            for chunk in MULTILINE_CRLF_STREAM.chunks(chunk_size) {
+                events.extend(decoder.push(chunk));
+            }
+
+            assert_eq!(
+                data(&events),
+                vec![
+                    "{\"type\":\"answer\",\n \"content\":{\"answer\":\"multi-line 数据\"}}",
+                    "{\"type\":\"message_end\"}",
+                ],
+                "chunk size {}",
+                chunk_size
+            );
+            assert_eq!(decoder.retry_ms(), Some(3000));
+        }
+    }
+
+    #[test]
+    fn handles_bare_cr_line_endings() {
+        let events = decode_in_chunks(b"data: a\rdata: b\r\rdata: c\r\r", 1);
+        assert_eq!(data(&events), vec!["a\nb", "c"]);
+    }
+
+    #[test]
+    fn ignores_comments_and_unknown_fields() {
+        let events = decode_in_chunks(b": ping\nfoo: bar\ndata:x\n\n:\n\n", 4);
+        assert_eq!(data(&events), vec!["x"]);
+        assert_eq!(events[0].event, None);
+    }
+
+    #[test]
+    fn event_type_resets_but_id_persists() {
+        let events = decode_in_chunks(b"event: title\nid: 7\ndata: a\n\ndata: b\n\n", 64);
+
+        assert_eq!(events[0].event.as_deref(), Some("title"));
+        assert_eq!(events[1].event, None);
+        assert_eq!(events[1].id.as_deref(), Some("7"));
+    }
+
+    #[test]
+    fn blank_line_without_data_dispatches_nothing() {
+        assert!(decode_in_chunks(b"event: ping\n\nid: 2\n\n", 64).is_empty());
+    }
+
+    #[test]
+    fn field_without_colon_and_empty_data() {
+        let events = decode_in_chunks(b"data\n\ndata:\ndata:\n\n", 64);
+        assert_eq!(data(&events), vec!["", "\n"]);
+    }
+
+    #[test]
+    fn strips_leading_bom_even_when_split() {
+        let events = decode_in_chunks(b"\xEF\xBB\xBFdata: first\n\n", 1);
+        assert_eq!(data(&events), vec!["first"]);
+    }
+
+    #[test]
+    fn invalid_retry_is_ignored() {
+        let mut decoder = SseDecoder::new();
+        decoder.push(b"retry: 10s\n\nretry: 250\n\nretry: soon\n\n");
+        assert_eq!(decoder.retry_ms(), Some(250));
+    }
+
+    #[test]
+    fn finish_delivers_event_missing_its_blank_line() {
+        let events = decode_in_chunks(b"data: {\"type\":\"answer\"}", 5);
+        assert_eq!(data(&events), vec!["{\"type\":\"answer\"}"]);
+    }
+}
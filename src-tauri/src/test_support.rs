@@ -0,0 +1,73 @@
+//! Fixtures shared by the unit tests
+
+use rusqlite::Connection;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use crate::agent::{Agent, CreateAgentInput};
+use crate::chat::Conversation;
+use crate::crypto::TokenCipher;
+use crate::db;
+
+/// A migrated in-memory database
+pub fn database() -> Connection {
+    let mut conn = Connection::open_in_memory().unwrap();
+    db::migrate(&mut conn).unwrap();
+    conn
+}
+
+/// Like [`database`], with a cipher whose key lives in the returned directory
+pub fn database_with_cipher() -> (Connection, TokenCipher, tempfile::TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let cipher = TokenCipher::load_or_create(&dir.path().join("test.key")).unwrap();
+    (database(), cipher, dir)
+}
+
+/// An agent on a placeholder endpoint with default settings; override fields with `..`
+pub fn agent_input(name: &str) -> CreateAgentInput {
+    CreateAgentInput {
+        name: name.to_string(),
+        api_url: "https://example.com/stream_run".to_string(),
+        auth_token: "token".to_string(),
+        project_id: "p1".to_string(),
+        history_window: None,
+        connect_timeout_secs: None,
+        read_timeout_secs: None,
+        max_retries: None,
+    }
+}
+
+pub fn saved_agent(conn: &Connection, name: &str) -> Agent {
+    let agent = Agent::new(agent_input(name));
+    agent.save(conn).unwrap();
+    agent
+}
+
+/// A migrated database holding an "Analyst" agent and one conversation with it
+pub fn setup_conversation(title: Option<&str>) -> (Connection, Conversation) {
+    let conn = database();
+    let agent = saved_agent(&conn, "Analyst");
+    let conversation = Conversation::new(agent.id, title.map(str::to_string));
+    conversation.save(&conn).unwrap();
+    (conn, conversation)
+}
+
+/// A log writer that keeps everything written to it
+#[derive(Clone, Default)]
+pub struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Captured {
+    pub fn output(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+use serde::{Deserialize, Serialize};
+use rusqlite::{params, Connection, OptionalExtension, Result as SqlResult, Row};
+use crate::chat::ReplyStats;
+
+/// Timing, size and token counts of one assistant reply
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageUsage {
+    pub message_id: String,
+    pub agent_id: String,
+    pub conversation_id: String,
+    pub started_at: i64, // milliseconds since epoch
+    pub first_token_ms: Option<i64>,
+    pub duration_ms: i64,
+    pub response_bytes: i64,
+    pub response_chars: i64,
+    pub input_tokens: Option<i64>,
+    pub output_tokens: Option<i64>,
+    pub total_tokens: Option<i64>,
+}
+
+impl MessageUsage {
+    pub fn new(message_id: &str, agent_id: &str, conversation_id: &str, content: &str, stats: &ReplyStats) -> Self {
+        let usage = stats.usage.unwrap_or_default();
+        Self {
+            message_id: message_id.to_string(),
+            agent_id: agent_id.to_string(),
+            conversation_id: conversation_id.to_string(),
+            started_at: stats.started_at,
+            first_token_ms: stats.first_token_ms,
+            duration_ms: stats.duration_ms,
+            response_bytes: stats.response_bytes,
+            response_chars: content.chars().count() as i64,
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            // Some deployments only report the two parts
+            total_tokens: usage.total_tokens.or(match (usage.input_tokens, usage.output_tokens) {
+                (Some(input), Some(output)) => Some(input + output),
+                _ => None,
+            }),
+        }
+    }
+
+    pub fn save(&self, conn: &Connection) -> SqlResult<()> {
+        conn.execute(
+            "INSERT OR REPLACE INTO message_usage (message_id, agent_id, conversation_id, started_at, first_token_ms,
+                duration_ms, response_bytes, response_chars, input_tokens, output_tokens, total_tokens)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                self.message_id,
+                self.agent_id,
+                self.conversation_id,
+                self.started_at,
+                self.first_token_ms,
+                self.duration_ms,
+                self.response_bytes,
+                self.response_chars,
+                self.input_tokens,
+                self.output_tokens,
+                self.total_tokens,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn get(conn: &Connection, message_id: &str) -> SqlResult<Option<Self>> {
+        conn.query_row(
+            "SELECT message_id, agent_id, conversation_id, started_at, first_token_ms, duration_ms,
+                    response_bytes, response_chars, input_tokens, output_tokens, total_tokens
+             FROM message_usage WHERE message_id = ?1",
+            params![message_id],
+            Self::from_row,
+        )
+        .optional()
+    }
+
+    fn from_row(row: &Row) -> SqlResult<Self> {
+        Ok(Self {
+            message_id: row.get(0)?,
+            agent_id: row.get(1)?,
+            conversation_id: row.get(2)?,
+            started_at: row.get(3)?,
+            first_token_ms: row.get(4)?,
+            duration_ms: row.get(5)?,
+            response_bytes: row.get(6)?,
+            response_chars: row.get(7)?,
+            input_tokens: row.get(8)?,
+            output_tokens: row.get(9)?,
+            total_tokens: row.get(10)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageGroup {
+    Day,
+    Agent,
+    Conversation,
+}
+
+impl UsageGroup {
+    // Grouping key, and the label shown next to it
+    fn columns(self) -> (&'static str, &'static str) {
+        match self {
+            UsageGroup::Day => ("strftime('%Y-%m-%d', u.started_at / 1000, 'unixepoch')", "NULL"),
+            UsageGroup::Agent => ("u.agent_id", "a.name"),
+            UsageGroup::Conversation => ("u.conversation_id", "c.title"),
+        }
+    }
+}
+
+/// Totals and averages over the replies in one day, agent or conversation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageBucket {
+    pub key: String, // UTC date as YYYY-MM-DD, agent id or conversation id
+    // Agent name or conversation title; missing once the record is deleted
+    pub label: Option<String>,
+    pub replies: i64,
+    pub input_tokens: Option<i64>,
+    pub output_tokens: Option<i64>,
+    pub total_tokens: Option<i64>,
+    pub response_bytes: i64,
+    pub response_chars: i64,
+    pub avg_first_token_ms: Option<f64>,
+    pub avg_duration_ms: f64,
+    pub max_duration_ms: i64,
+}
+
+/// Aggregates replies started in `[from, to)` (milliseconds), optionally for one agent.
+/// Days come out in order; agents and conversations busiest first.
+pub fn get_usage_stats(
+    conn: &Connection,
+    agent_id: Option<&str>,
+    from: i64,
+    to: i64,
+    group_by: UsageGroup,
+) -> SqlResult<Vec<UsageBucket>> {
+    let (key, label) = group_by.columns();
+    let order = match group_by {
+        UsageGroup::Day => "key",
+        UsageGroup::Agent | UsageGroup::Conversation => "COUNT(*) DESC, key",
+    };
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {key} AS key, {label},
+                COUNT(*), SUM(u.input_tokens), SUM(u.output_tokens), SUM(u.total_tokens),
+                SUM(u.response_bytes), SUM(u.response_chars),
+                AVG(u.first_token_ms), AVG(u.duration_ms), MAX(u.duration_ms)
+         FROM message_usage u
+         LEFT JOIN agents a ON a.id = u.agent_id
+         LEFT JOIN conversations c ON c.id = u.conversation_id
+         WHERE u.started_at >= ?1 AND u.started_at < ?2 AND (?3 IS NULL OR u.agent_id = ?3)
+         GROUP BY key
+         ORDER BY {order}"
+    ))?;
+
+    let buckets = stmt.query_map(params![from, to, agent_id], |row| {
+        Ok(UsageBucket {
+            key: row.get(0)?,
+            label: row.get(1)?,
+            replies: row.get(2)?,
+            input_tokens: row.get(3)?,
+            output_tokens: row.get(4)?,
+            total_tokens: row.get(5)?,
+            response_bytes: row.get(6)?,
+            response_chars: row.get(7)?,
+            avg_first_token_ms: row.get(8)?,
+            avg_duration_ms: row.get(9)?,
+            max_duration_ms: row.get(10)?,
+        })
+    })?;
+    buckets.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::Conversation;
+    use crate::coze::TokenUsage;
+    use crate::test_support::setup_conversation;
+
+    // 2024-03-01T00:00:00Z
+    const DAY_ONE: i64 = 1_709_251_200_000;
+    const DAY_MS: i64 = 86_400_000;
+
+    fn record(conn: &Connection, id: &str, agent_id: &str, conversation_id: &str, started_at: i64, usage: Option<TokenUsage>) {
+        let stats = ReplyStats {
+            started_at,
+            first_token_ms: Some(200),
+            duration_ms: 1000,
+            response_bytes: 300,
+            usage,
+        };
+        MessageUsage::new(id, agent_id, conversation_id, "晴天", &stats).save(conn).unwrap();
+    }
+
+    fn tokens(input: i64, output: i64) -> Option<TokenUsage> {
+        Some(TokenUsage { input_tokens: Some(input), output_tokens: Some(output), total_tokens: None })
+    }
+
+    #[test]
+    fn records_one_row_per_reply() {
+        let (conn, conversation) = setup_conversation(Some("Weather"));
+        record(&conn, "m1", &conversation.agent_id, &conversation.id, DAY_ONE, tokens(10, 5));
+
+        let usage = MessageUsage::get(&conn, "m1").unwrap().unwrap();
+        assert_eq!(usage.response_chars, 2);
+        assert_eq!(usage.response_bytes, 300);
+        assert_eq!(usage.total_tokens, Some(15), "total is derived from the parts");
+        assert_eq!(MessageUsage::get(&conn, "missing").unwrap(), None);
+    }
+
+    #[test]
+    fn groups_by_utc_day_within_the_range() {
+        let (conn, conversation) = setup_conversation(Some("Weather"));
+        let (agent, id) = (&conversation.agent_id, &conversation.id);
+        record(&conn, "m1", agent, id, DAY_ONE + 1, tokens(10, 5));
+        record(&conn, "m2", agent, id, DAY_ONE + DAY_MS - 1, None);
+        record(&conn, "m3", agent, id, DAY_ONE + DAY_MS, tokens(1, 1));
+        record(&conn, "m4", agent, id, DAY_ONE + 2 * DAY_MS, tokens(1, 1));
+
+        let days = get_usage_stats(&conn, None, DAY_ONE, DAY_ONE + 2 * DAY_MS, UsageGroup::Day).unwrap();
+
+        assert_eq!(days.iter().map(|d| d.key.as_str()).collect::<Vec<_>>(), vec!["2024-03-01", "2024-03-02"]);
+        assert_eq!(days[0].replies, 2);
+        assert_eq!(days[0].total_tokens, Some(15), "replies without token counts are skipped");
+        assert_eq!(days[0].response_bytes, 600);
+        assert_eq!(days[0].avg_duration_ms, 1000.0);
+        assert_eq!(days[0].avg_first_token_ms, Some(200.0));
+        assert_eq!(days[0].label, None);
+    }
+
+    #[test]
+    fn groups_by_agent_and_conversation_with_labels() {
+        let (conn, conversation) = setup_conversation(Some("Weather"));
+        let other = Conversation::new(conversation.agent_id.clone(), Some("Travel".to_string()));
+        other.save(&conn).unwrap();
+        record(&conn, "m1", &conversation.agent_id, &conversation.id, DAY_ONE, None);
+        record(&conn, "m2", &conversation.agent_id, &other.id, DAY_ONE, None);
+        record(&conn, "m3", &conversation.agent_id, &other.id, DAY_ONE, None);
+        record(&conn, "m4", "deleted-agent", "deleted-conversation", DAY_ONE, None);
+
+        let by_conversation = get_usage_stats(&conn, None, DAY_ONE, DAY_ONE + DAY_MS, UsageGroup::Conversation).unwrap();
+        assert_eq!(by_conversation[0].label.as_deref(), Some("Travel"));
+        assert_eq!(by_conversation[0].replies, 2);
+        assert!(by_conversation.iter().any(|b| b.key == "deleted-conversation" && b.label.is_none()));
+
+        let by_agent = get_usage_stats(&conn, None, DAY_ONE, DAY_ONE + DAY_MS, UsageGroup::Agent).unwrap();
+        assert_eq!(by_agent.len(), 2);
+        assert_eq!(by_agent[0].label.as_deref(), Some("Analyst"));
+        assert_eq!(by_agent[0].replies, 3);
+
+        let one_agent =
+            get_usage_stats(&conn, Some(&conversation.agent_id), DAY_ONE, DAY_ONE + DAY_MS, UsageGroup::Agent).unwrap();
+        assert_eq!(one_agent.len(), 1);
+    }
+}
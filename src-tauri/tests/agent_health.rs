@@ -0,0 +1,158 @@
+mod common;
+
+use common::{answer, message_end, sse_event, Chunk, MockCoze, Reply};
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tauri_app_lib::health::{check_connection, CheckStatus, ConnectionReport};
+use tauri_app_lib::http::{HttpClients, RequestPolicy};
+
+fn fast_policy() -> RequestPolicy {
+    RequestPolicy {
+        connect_timeout: Duration::from_secs(2),
+        read_timeout: Duration::from_secs(2),
+        max_retries: 0,
+    }
+}
+
+async fn check(url: &str) -> ConnectionReport {
+    check_with(url, fast_policy()).await
+}
+
+async fn check_with(url: &str, policy: RequestPolicy) -> ConnectionReport {
+    let client = HttpClients::default().client(&policy).unwrap();
+    check_connection(&client, &policy, url, "pat_test_token", "project-1").await
+}
+
+#[tokio::test]
+async fn healthy_endpoint_passes_every_step() {
+    let server = MockCoze::start(vec![Reply::sse(&format!("{}{}", answer("pong"), message_end()))]).await;
+
+    let report = check(&server.url).await;
+
+    assert!(report.ok, "{:?}", report);
+    assert_eq!(report.http_status, Some(200));
+    assert!(report.first_byte_ms.is_some());
+    assert_eq!(report.dns.status, CheckStatus::Passed);
+    assert_eq!(report.connect.status, CheckStatus::Passed);
+    assert_eq!(report.tls.status, CheckStatus::Skipped);
+    assert!(!report.tls.inferred);
+    assert_eq!(report.stream.status, CheckStatus::Passed);
+
+    let requests = server.requests();
+    assert_eq!(requests[0].header("authorization"), Some("Bearer pat_test_token"));
+    assert_eq!(requests[0].json()["project_id"], "project-1");
+}
+
+#[tokio::test]
+async fn rejected_token_is_an_auth_failure() {
+    let server = MockCoze::start(vec![Reply::status(401, r#"{"msg":"invalid token"}"#)]).await;
+
+    let report = check(&server.url).await;
+
+    assert!(!report.ok);
+    assert_eq!(report.http_status, Some(401));
+    assert_eq!(report.http.status, CheckStatus::Passed);
+    assert_eq!(report.auth.status, CheckStatus::Failed);
+    assert!(report.auth.detail.contains("invalid token"));
+    assert_eq!(report.stream.status, CheckStatus::Skipped);
+}
+
+#[tokio::test]
+async fn forbidden_token_is_an_auth_failure() {
+    let server = MockCoze::start(vec![Reply::status(403, r#"{"msg":"no access to this project"}"#)]).await;
+
+    let report = check(&server.url).await;
+
+    assert!(!report.ok);
+    assert_eq!(report.http_status, Some(403));
+    assert_eq!(report.auth.status, CheckStatus::Failed);
+    assert!(report.auth.detail.contains("403"), "{}", report.auth.detail);
+    assert!(report.auth.detail.contains("no access"));
+    assert_eq!(report.stream.status, CheckStatus::Skipped);
+}
+
+#[tokio::test]
+async fn silent_stream_times_out() {
+    let server = MockCoze::start(vec![Reply::sse_events(&[&answer("too late")], Duration::from_secs(5))]).await;
+    let policy = RequestPolicy { read_timeout: Duration::from_millis(300), ..fast_policy() };
+
+    let started = Instant::now();
+    let report = check_with(&server.url, policy).await;
+
+    assert!(!report.ok);
+    assert_eq!(report.auth.status, CheckStatus::Passed);
+    assert_eq!(report.stream.status, CheckStatus::Failed);
+    assert!(report.stream.detail.contains("before the timeout"), "{}", report.stream.detail);
+    assert_eq!(report.first_byte_ms, None);
+    assert!(started.elapsed() < Duration::from_secs(3));
+}
+
+#[tokio::test]
+async fn probe_stops_at_the_first_event() {
+    let mut reply = Reply::sse(&answer("pong"));
+    reply.chunks.push(Chunk { delay: Duration::from_secs(5), bytes: message_end().into_bytes() });
+    let server = MockCoze::start(vec![reply]).await;
+
+    let started = Instant::now();
+    let report = check(&server.url).await;
+
+    assert!(report.ok, "{:?}", report);
+    assert!(started.elapsed() < Duration::from_secs(3));
+}
+
+#[tokio::test]
+async fn server_error_fails_the_http_step() {
+    let server = MockCoze::start(vec![Reply::status(502, "bad gateway")]).await;
+
+    let report = check(&server.url).await;
+
+    assert_eq!(report.http.status, CheckStatus::Failed);
+    assert!(report.http.detail.contains("502"));
+    assert_eq!(report.auth.status, CheckStatus::Skipped);
+}
+
+#[tokio::test]
+async fn non_coze_stream_fails_the_stream_step() {
+    let server = MockCoze::start(vec![Reply::sse(&sse_event(&json!({"hello": "world"})))]).await;
+
+    let report = check(&server.url).await;
+
+    assert!(!report.ok);
+    assert_eq!(report.auth.status, CheckStatus::Passed);
+    assert_eq!(report.stream.status, CheckStatus::Failed);
+    assert!(report.stream.detail.contains("1 unrecognised"));
+}
+
+#[tokio::test]
+async fn in_stream_error_is_reported() {
+    let error = sse_event(&json!({"type": "error", "content": {"error": {"code": 4200, "error_msg": "project not found"}}}));
+    let server = MockCoze::start(vec![Reply::sse(&error)]).await;
+
+    let report = check(&server.url).await;
+
+    assert_eq!(report.stream.status, CheckStatus::Failed);
+    assert!(report.stream.detail.contains("project not found"), "{}", report.stream.detail);
+}
+
+#[tokio::test]
+async fn refused_connection_stops_before_the_probe() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/stream_run", listener.local_addr().unwrap());
+    drop(listener);
+
+    let report = check(&url).await;
+
+    assert_eq!(report.dns.status, CheckStatus::Passed);
+    assert_eq!(report.connect.status, CheckStatus::Failed);
+    assert_eq!(report.http.status, CheckStatus::Skipped);
+    assert_eq!(report.http_status, None);
+}
+
+#[tokio::test]
+async fn malformed_url_fails_first() {
+    let report = check("localhost/stream_run").await;
+
+    assert!(!report.ok);
+    assert_eq!(report.url.status, CheckStatus::Failed);
+    assert_eq!(report.dns.status, CheckStatus::Skipped);
+}
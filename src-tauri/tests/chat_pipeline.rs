@@ -0,0 +1,459 @@
+mod common;
+
+use common::{answer, message_end, save_conversation, sse_event, MockCoze, RecordingSink, Reply};
+use rusqlite::Connection;
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tauri_app_lib::chat::{
+    send_message_to_coze, send_message_to_coze_streaming, CozeRequest, DraftSink, Message, StreamEmitter, StreamEvent,
+    StreamOutcome,
+};
+use tauri_app_lib::coze::TokenUsage;
+use tauri_app_lib::db;
+use tauri_app_lib::error::AppError;
+use tauri_app_lib::http::{HttpClients, RequestPolicy};
+use tokio_util::sync::CancellationToken;
+
+const ANSWER_STREAM: &str = include_str!("fixtures/coze_answer.sse");
+const TOOL_CALL_STREAM: &str = include_str!("fixtures/coze_tool_calls.sse");
+
+fn fast_policy() -> RequestPolicy {
+    RequestPolicy {
+        connect_timeout: Duration::from_secs(2),
+        read_timeout: Duration::from_secs(2),
+        max_retries: 2,
+    }
+}
+
+async fn run(
+    server: &MockCoze,
+    policy: RequestPolicy,
+    request: &CozeRequest,
+    cancel: &CancellationToken,
+) -> (RecordingSink, Result<StreamOutcome, AppError>) {
+    let sink = RecordingSink::default();
+    let result = run_into(&sink, server, policy, request, cancel).await;
+    (sink, result)
+}
+
+async fn run_into(
+    sink: &RecordingSink,
+    server: &MockCoze,
+    policy: RequestPolicy,
+    request: &CozeRequest,
+    cancel: &CancellationToken,
+) -> Result<StreamOutcome, AppError> {
+    let emitter = StreamEmitter::new(sink, "conv-1", "msg-1");
+    let client = HttpClients::default().client(&policy).unwrap();
+    send_message_to_coze_streaming(&emitter, &client, &policy, &server.url, "pat_test_token", request, cancel).await
+}
+
+async fn run_default(server: &MockCoze) -> (RecordingSink, Result<StreamOutcome, AppError>) {
+    let request = CozeRequest::new("project-1", &[], "hello");
+    run(server, fast_policy(), &request, &CancellationToken::new()).await
+}
+
+#[tokio::test]
+async fn streams_answer_chunks_and_title() {
+    let server = MockCoze::start(vec![Reply::sse(ANSWER_STREAM)]).await;
+
+    let (sink, result) = run_default(&server).await;
+    let outcome = result.unwrap();
+
+    assert_eq!(outcome.content, "你好，今天杭州天气晴朗。");
+    assert_eq!(outcome.title.as_deref(), Some("杭州天气"));
+    assert!(!outcome.cancelled);
+    assert_eq!(sink.types(), vec!["content", "content", "done"]);
+
+    let content = sink.of_type("content");
+    assert_eq!(content[1].content.as_deref(), Some("今天杭州天气晴朗。"));
+    assert_eq!(content[1].full_content.as_deref(), Some("你好，今天杭州天气晴朗。"));
+    assert!(sink.events().iter().all(|e| e.conversation_id == "conv-1" && e.message_id == "msg-1"));
+
+    let stats = outcome.stats;
+    assert_eq!(stats.response_bytes, ANSWER_STREAM.len() as i64);
+    assert!(stats.first_token_ms.is_some_and(|ms| ms <= stats.duration_ms));
+    assert_eq!(
+        stats.usage,
+        Some(TokenUsage { input_tokens: Some(41), output_tokens: Some(16), total_tokens: Some(57) })
+    );
+}
+
+#[tokio::test]
+async fn sends_authorization_and_settled_history() {
+    let server = MockCoze::start(vec![Reply::sse(&message_end())]).await;
+    let mut failed = Message::new("conv-1".to_string(), "assistant".to_string(), "half an ans".to_string(), None);
+    failed.status = "error".to_string();
+    let history = vec![
+        Message::new("conv-1".to_string(), "user".to_string(), "first question".to_string(), None),
+        failed,
+        Message::new("conv-1".to_string(), "assistant".to_string(), "first answer".to_string(), None),
+    ];
+    let request = CozeRequest::new("project-1", &history, "follow-up");
+
+    run(&server, fast_policy(), &request, &CancellationToken::new()).await.1.unwrap();
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].path, "/stream_run");
+    assert_eq!(requests[0].header("authorization"), Some("Bearer pat_test_token"));
+
+    let body = requests[0].json();
+    assert_eq!(body["project_id"], "project-1");
+    assert!(body.get("session_id").is_none());
+    assert_eq!(body["content"]["query"]["prompt"][0]["content"]["text"], "follow-up");
+    assert_eq!(body["content"]["query"]["history"][0]["content"], "first question");
+    assert_eq!(body["content"]["query"]["history"][1]["content"], "first answer");
+    assert_eq!(body["content"]["query"]["history"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn decodes_events_split_across_arbitrary_chunks() {
+    let server = MockCoze::start(vec![Reply::sse_split(ANSWER_STREAM, 5)]).await;
+
+    let outcome = run_default(&server).await.1.unwrap();
+
+    assert_eq!(outcome.content, "你好，今天杭州天气晴朗。");
+    assert_eq!(outcome.title.as_deref(), Some("杭州天气"));
+}
+
+#[tokio::test]
+async fn reports_tool_calls_and_results() {
+    let server = MockCoze::start(vec![Reply::sse(TOOL_CALL_STREAM)]).await;
+
+    let (sink, result) = run_default(&server).await;
+    let outcome = result.unwrap();
+
+    assert_eq!(sink.types(), vec!["tool_call", "tool_result", "content", "done"]);
+    assert_eq!(sink.of_type("tool_call")[0].tool_call.as_ref().unwrap().status, "running");
+
+    let tool_calls = outcome.tool_calls.unwrap();
+    assert_eq!(tool_calls.len(), 1);
+    assert_eq!(tool_calls[0].id, "call_1");
+    assert_eq!(tool_calls[0].tool_name, "get_weather");
+    assert!(tool_calls[0].tool_input.contains("杭州"));
+    assert_eq!(tool_calls[0].tool_output.as_deref(), Some("{\"weather\":\"晴\",\"temp\":22}"));
+    assert_eq!(tool_calls[0].status, "success");
+    assert_eq!(outcome.content, "杭州今天晴，22°C。");
+}
+
+#[tokio::test]
+async fn failed_tool_response_is_marked_as_error() {
+    let stream = [
+        sse_event(&json!({"type": "tool_request", "content": {"tool_request": {
+            "tool_call_id": "call_9", "tool_name": "search", "parameters": {}}}})),
+        sse_event(&json!({"type": "tool_response", "content": {"tool_response": {
+            "tool_call_id": "call_9", "code": "500", "result": "backend down"}}})),
+        message_end(),
+    ]
+    .concat();
+    let server = MockCoze::start(vec![Reply::sse(&stream)]).await;
+
+    let outcome = run_default(&server).await.1.unwrap();
+
+    let tool_calls = outcome.tool_calls.unwrap();
+    assert_eq!(tool_calls[0].status, "error");
+    assert_eq!(tool_calls[0].tool_output.as_deref(), Some("backend down"));
+}
+
+#[tokio::test]
+async fn legacy_tool_call_arrays_are_reported() {
+    let stream = [
+        sse_event(&json!({"content": {"tool_calls": [{"id": "t1", "name": "lookup", "args": {"k": 1}}]}})),
+        answer("looked up"),
+        message_end(),
+    ]
+    .concat();
+    let server = MockCoze::start(vec![Reply::sse(&stream)]).await;
+
+    let (sink, result) = run_default(&server).await;
+
+    assert_eq!(sink.types(), vec!["tool_call", "content", "done"]);
+    let tool_calls = result.unwrap().tool_calls.unwrap();
+    assert_eq!(tool_calls[0].tool_name, "lookup");
+    assert_eq!(tool_calls[0].tool_input, "{\"k\":1}");
+}
+
+#[tokio::test]
+async fn error_inside_the_stream_fails_the_reply() {
+    let stream = [
+        answer("half an "),
+        sse_event(&json!({"type": "error", "content": {"error": {"code": 4011, "error_msg": "quota exceeded"}}})),
+        answer("never shown"),
+    ]
+    .concat();
+    let server = MockCoze::start(vec![Reply::sse(&stream)]).await;
+
+    let (sink, result) = run_default(&server).await;
+
+    let error = result.unwrap_err();
+    assert_eq!(error, AppError::Agent("quota exceeded".to_string()));
+    assert_eq!(error.to_string(), "The agent reported an error: quota exceeded");
+    assert_eq!(sink.types(), vec!["content", "error"]);
+    assert_eq!(sink.of_type("error")[0].full_content.as_deref(), Some("half an "));
+}
+
+#[tokio::test]
+async fn retries_server_errors_then_streams() {
+    let server = MockCoze::start(vec![
+        Reply::status(503, "{\"msg\":\"busy\"}").header("Retry-After", "0"),
+        Reply::status(429, "{\"msg\":\"slow down\"}").header("Retry-After", "0"),
+        Reply::sse(&[answer("recovered"), message_end()].concat()),
+    ])
+    .await;
+
+    let (sink, result) = run_default(&server).await;
+
+    assert_eq!(result.unwrap().content, "recovered");
+    assert_eq!(server.requests().len(), 3);
+    assert_eq!(sink.types(), vec!["retrying", "retrying", "content", "done"]);
+
+    let retries = sink.of_type("retrying");
+    assert_eq!(retries[0].attempt, Some(1));
+    assert_eq!(retries[1].attempt, Some(2));
+    assert_eq!(retries[1].max_retries, Some(2));
+    assert_eq!(retries[0].retry_in_ms, Some(0));
+}
+
+#[tokio::test]
+async fn gives_up_after_the_retry_budget() {
+    let server = MockCoze::start(vec![Reply::status(502, "bad gateway").header("Retry-After", "0")]).await;
+
+    let (sink, result) = run_default(&server).await;
+
+    let error = result.unwrap_err();
+    assert_eq!(error, AppError::Upstream { status: 502, body: "bad gateway".to_string() });
+    assert!(error.is_retryable());
+    assert_eq!(server.requests().len(), 3);
+    assert_eq!(sink.types(), vec!["retrying", "retrying", "error"]);
+}
+
+#[tokio::test]
+async fn client_errors_are_not_retried() {
+    let server = MockCoze::start(vec![Reply::status(401, "{\"msg\":\"invalid token\"}")]).await;
+
+    let (sink, result) = run_default(&server).await;
+
+    let error = result.unwrap_err();
+    assert_eq!(error.code(), "auth");
+    assert!(error.to_string().contains("401"));
+    assert!(error.to_string().contains("invalid token"));
+    assert_eq!(server.requests().len(), 1);
+    assert_eq!(sink.types(), vec!["error"]);
+}
+
+#[tokio::test]
+async fn disconnect_mid_stream_is_reported() {
+    let server = MockCoze::start(vec![Reply::sse(&answer("partial ")).disconnect()]).await;
+
+    let (sink, result) = run_default(&server).await;
+
+    assert!(result.is_err());
+    assert_eq!(sink.types(), vec!["content", "error"]);
+    assert_eq!(sink.of_type("error")[0].full_content.as_deref(), Some("partial "));
+}
+
+#[tokio::test]
+async fn stream_closed_without_final_blank_line_keeps_last_event() {
+    let server = MockCoze::start(vec![Reply::sse("data: {\"type\":\"answer\",\"content\":{\"answer\":\"tail\"}}")]).await;
+
+    let outcome = run_default(&server).await.1.unwrap();
+
+    assert_eq!(outcome.content, "tail");
+}
+
+#[tokio::test]
+async fn stalled_stream_hits_the_read_timeout() {
+    let server = MockCoze::start(vec![Reply::sse_events(
+        &[&answer("first"), &answer("never arrives")],
+        Duration::from_millis(500),
+    )])
+    .await;
+    let policy = RequestPolicy {
+        read_timeout: Duration::from_millis(200),
+        max_retries: 0,
+        ..fast_policy()
+    };
+    let request = CozeRequest::new("project-1", &[], "hello");
+
+    let (sink, result) = run(&server, policy, &request, &CancellationToken::new()).await;
+
+    assert!(result.is_err());
+    assert_eq!(sink.types().last().map(String::as_str), Some("error"));
+}
+
+#[tokio::test]
+async fn cancelling_keeps_what_already_arrived() {
+    let first = answer("before stop");
+    let second = answer(" after stop");
+    let end = message_end();
+    let server = MockCoze::start(vec![Reply::sse_events(&[&first, &second, &end], Duration::from_millis(300))]).await;
+    let request = CozeRequest::new("project-1", &[], "hello");
+    let cancel = CancellationToken::new();
+    let sink = RecordingSink::default();
+
+    // Stops as soon as the first chunk is shown, well before the second is sent
+    let stop = async {
+        sink.wait_for("content").await;
+        cancel.cancel();
+    };
+    let (result, ()) = tokio::join!(run_into(&sink, &server, fast_policy(), &request, &cancel), stop);
+    let outcome = result.unwrap();
+
+    assert!(outcome.cancelled);
+    assert_eq!(outcome.content, "before stop");
+    assert_eq!(sink.types(), vec!["content", "cancelled"]);
+}
+
+#[tokio::test]
+async fn cancelling_during_backoff_skips_the_request() {
+    let server = MockCoze::start(vec![Reply::status(503, "busy").header("Retry-After", "5")]).await;
+    let request = CozeRequest::new("project-1", &[], "hello");
+    let cancel = CancellationToken::new();
+    let sink = RecordingSink::default();
+
+    let stop = async {
+        sink.wait_for("retrying").await;
+        cancel.cancel();
+    };
+    let (result, ()) = tokio::join!(run_into(&sink, &server, fast_policy(), &request, &cancel), stop);
+
+    assert_eq!(result.unwrap_err().code(), "cancelled");
+    assert_eq!(server.requests().len(), 1);
+    assert_eq!(sink.types(), vec!["retrying", "cancelled"]);
+}
+
+async fn run_blocking(server: &MockCoze) -> Result<StreamOutcome, AppError> {
+    let policy = fast_policy();
+    let client = HttpClients::default().client(&policy).unwrap();
+    let request = CozeRequest::new("project-1", &[], "hello");
+    send_message_to_coze(&client, &policy, &server.url, "pat_test_token", &request).await
+}
+
+#[tokio::test]
+async fn blocking_reply_matches_the_streamed_one() {
+    for body in [ANSWER_STREAM, TOOL_CALL_STREAM] {
+        let server = MockCoze::start(vec![Reply::status(503, "busy"), Reply::sse_split(body, 7)]).await;
+        let blocking = run_blocking(&server).await.unwrap();
+        let streamed = run_default(&server).await.1.unwrap();
+
+        assert_eq!(server.requests().len(), 3, "the first attempt is retried");
+        assert_eq!(blocking.content, streamed.content);
+        assert_eq!(blocking.title, streamed.title);
+        assert_eq!(blocking.tool_calls, streamed.tool_calls);
+        assert_eq!(blocking.stats.usage, streamed.stats.usage);
+        assert_eq!(blocking.stats.response_bytes, body.len() as i64);
+    }
+}
+
+#[tokio::test]
+async fn blocking_reply_reports_agent_and_status_errors() {
+    let stream = [
+        answer("half an "),
+        sse_event(&json!({"type": "error", "content": {"error": {"code": 4011, "error_msg": "quota exceeded"}}})),
+    ]
+    .concat();
+    let server = MockCoze::start(vec![Reply::sse(&stream)]).await;
+    assert_eq!(run_blocking(&server).await.unwrap_err(), AppError::Agent("quota exceeded".to_string()));
+
+    let server = MockCoze::start(vec![Reply::status(401, "bad token")]).await;
+    assert_eq!(run_blocking(&server).await.unwrap_err().code(), "auth");
+}
+
+#[tokio::test]
+async fn streamed_reply_round_trips_through_the_database() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    db::migrate(&mut conn).unwrap();
+    let conversation = save_conversation(&conn);
+
+    let server = MockCoze::start(vec![Reply::sse(TOOL_CALL_STREAM)]).await;
+    let outcome = run_default(&server).await.1.unwrap();
+
+    let mut reply = Message::new(conversation.id.clone(), "assistant".to_string(), outcome.content, outcome.tool_calls);
+    reply.save(&conn).unwrap();
+
+    let stored = Message::get_by_conversation(&conn, &conversation.id).unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].id, reply.id);
+    assert_eq!(stored[0].content, "杭州今天晴，22°C。");
+    assert_eq!(stored[0].status, "complete");
+    let tool_calls = stored[0].tool_calls.as_ref().unwrap();
+    assert_eq!(tool_calls[0].tool_name, "get_weather");
+    assert_eq!(tool_calls[0].status, "success");
+}
+
+#[tokio::test]
+async fn draft_row_follows_the_stream() {
+    let dir = tempfile::tempdir().unwrap();
+    let pool = db::open_pool(&dir.path().join("drafts.db")).unwrap();
+    let mut conn = pool.get().unwrap();
+    db::migrate(&mut conn).unwrap();
+    let conversation = save_conversation(&conn);
+    let mut draft = Message::new(conversation.id.clone(), "assistant".to_string(), String::new(), None);
+    draft.status = "streaming".to_string();
+    draft.save(&conn).unwrap();
+
+    // The stream breaks off before it finishes, as if the app had died
+    let before_end = &TOOL_CALL_STREAM[..TOOL_CALL_STREAM.find("event: message\nid: 4").unwrap()];
+    let server = MockCoze::start(vec![Reply::sse(before_end).disconnect()]).await;
+    let recorder = RecordingSink::default();
+    let sink = DraftSink::new(&recorder, &pool, &draft.id, Duration::ZERO);
+    let emitter = StreamEmitter::new(&sink, &conversation.id, &draft.id);
+    let policy = fast_policy();
+    let client = HttpClients::default().client(&policy).unwrap();
+    let request = CozeRequest::new("project-1", &[], "weather?");
+    let result = send_message_to_coze_streaming(
+        &emitter,
+        &client,
+        &policy,
+        &server.url,
+        "pat_test_token",
+        &request,
+        &CancellationToken::new(),
+    )
+    .await;
+    assert!(result.is_err());
+    assert!(!recorder.events().is_empty());
+
+    // Drafts are written off the stream; wait for the last one
+    sink.flush().await;
+    let stored = Message::get_by_id(&conn, &draft.id).unwrap().unwrap();
+    assert_eq!(stored.status, "streaming");
+    assert_eq!(stored.content, sink.snapshot().0);
+    assert!(!stored.content.is_empty());
+    assert_eq!(stored.tool_calls.unwrap()[0].tool_name, "get_weather");
+
+    assert_eq!(Message::recover_drafts(&conn).unwrap(), 1);
+    assert_eq!(Message::get_by_id(&conn, &draft.id).unwrap().unwrap().status, "interrupted");
+}
+
+#[tokio::test]
+async fn locked_database_does_not_hold_up_the_stream() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("locked.db");
+    let pool = db::open_pool(&path).unwrap();
+    let mut conn = pool.get().unwrap();
+    db::migrate(&mut conn).unwrap();
+    let conversation = save_conversation(&conn);
+    let mut draft = Message::new(conversation.id.clone(), "assistant".to_string(), String::new(), None);
+    draft.status = "streaming".to_string();
+    draft.save(&conn).unwrap();
+
+    // Another writer, such as a restore or an import, holds the database
+    let holder = Connection::open(&path).unwrap();
+    holder.execute_batch("BEGIN EXCLUSIVE;").unwrap();
+    let recorder = RecordingSink::default();
+    let sink = DraftSink::new(&recorder, &pool, &draft.id, Duration::ZERO);
+    let emitter = StreamEmitter::new(&sink, &conversation.id, &draft.id);
+    let started = Instant::now();
+    for part in ["one", "one two", "one two three"] {
+        emitter.emit(StreamEvent { full_content: Some(part.to_string()), ..emitter.event("content") });
+    }
+    assert!(started.elapsed() < Duration::from_secs(1), "took {:?}", started.elapsed());
+    assert_eq!(recorder.types().len(), 3);
+
+    holder.execute_batch("COMMIT;").unwrap();
+    sink.flush().await;
+    assert_eq!(Message::get_by_id(&conn, &draft.id).unwrap().unwrap().content, "one two three");
+}
@@ -0,0 +1,291 @@
+//! In-process stand-in for a Coze `stream_run` endpoint.
+//!
+//! Each incoming request is answered with the next scripted [`Reply`]; once the
+//! script runs out the last reply is repeated. Requests are recorded so tests can
+//! check what the client sent.
+
+#![allow(dead_code)]
+
+use rusqlite::Connection;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri_app_lib::agent::{Agent, CreateAgentInput};
+use tauri_app_lib::chat::{Conversation, StreamEvent, StreamSink};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+
+/// One scripted HTTP response
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub chunks: Vec<Chunk>,
+    /// Drop the connection after the chunks instead of ending the body
+    pub disconnect: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub delay: Duration,
+    pub bytes: Vec<u8>,
+}
+
+impl Reply {
+    /// A 200 `text/event-stream` response sending `body` in a single chunk
+    pub fn sse(body: &str) -> Self {
+        Self {
+            status: 200,
+            headers: vec![("Content-Type".to_string(), "text/event-stream".to_string())],
+            chunks: vec![Chunk { delay: Duration::ZERO, bytes: body.as_bytes().to_vec() }],
+            disconnect: false,
+        }
+    }
+
+    /// Like [`Reply::sse`], but one chunk per SSE event with `delay` before each
+    pub fn sse_events(events: &[&str], delay: Duration) -> Self {
+        Self {
+            chunks: events
+                .iter()
+                .map(|e| Chunk { delay, bytes: e.as_bytes().to_vec() })
+                .collect(),
+            ..Self::sse("")
+        }
+    }
+
+    /// Splits the body into chunks of `size` bytes, cutting through lines and characters
+    pub fn sse_split(body: &str, size: usize) -> Self {
+        Self {
+            chunks: body
+                .as_bytes()
+                .chunks(size)
+                .map(|c| Chunk { delay: Duration::ZERO, bytes: c.to_vec() })
+                .collect(),
+            ..Self::sse("")
+        }
+    }
+
+    pub fn status(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            ..Self::sse(body)
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn disconnect(mut self) -> Self {
+        self.disconnect = true;
+        self
+    }
+}
+
+/// A request as the mock server received it
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).expect("request body is JSON")
+    }
+}
+
+pub struct MockCoze {
+    pub url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockCoze {
+    pub async fn start(script: Vec<Reply>) -> Self {
+        assert!(!script.is_empty(), "script needs at least one reply");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/stream_run", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            let mut served = 0;
+            while let Ok((socket, _)) = listener.accept().await {
+                let reply = script[served.min(script.len() - 1)].clone();
+                served += 1;
+                let recorded = recorded.clone();
+                tokio::spawn(async move {
+                    let _ = serve(socket, reply, recorded).await;
+                });
+            }
+        });
+
+        Self { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn serve(
+    mut socket: TcpStream,
+    reply: Reply,
+    recorded: Arc<Mutex<Vec<RecordedRequest>>>,
+) -> std::io::Result<()> {
+    let request = read_request(&mut socket).await?;
+    recorded.lock().unwrap().push(request);
+
+    let mut head = format!("HTTP/1.1 {} Mock\r\n", reply.status);
+    for (name, value) in &reply.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n");
+    socket.write_all(head.as_bytes()).await?;
+
+    for chunk in &reply.chunks {
+        tokio::time::sleep(chunk.delay).await;
+        if chunk.bytes.is_empty() {
+            continue;
+        }
+        socket.write_all(format!("{:x}\r\n", chunk.bytes.len()).as_bytes()).await?;
+        socket.write_all(&chunk.bytes).await?;
+        socket.write_all(b"\r\n").await?;
+        socket.flush().await?;
+    }
+
+    if !reply.disconnect {
+        socket.write_all(b"0\r\n\r\n").await?;
+    }
+    socket.shutdown().await
+}
+
+async fn read_request(socket: &mut TcpStream) -> std::io::Result<RecordedRequest> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        let n = socket.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(n, v)| (n.trim().to_string(), v.trim().to_string()))
+        .collect();
+
+    let content_length = headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, v)| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = buf[header_end + 4..].to_vec();
+    while body.len() < content_length {
+        let n = socket.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+
+    Ok(RecordedRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).to_string(),
+    })
+}
+
+/// Collects emitted stream events in place of the app window
+#[derive(Default)]
+pub struct RecordingSink {
+    events: Mutex<Vec<StreamEvent>>,
+    emitted: Notify,
+}
+
+impl RecordingSink {
+    /// Resolves once an event of `event_type` has been emitted
+    pub async fn wait_for(&self, event_type: &str) {
+        loop {
+            let emitted = self.emitted.notified();
+            tokio::pin!(emitted);
+            // Registered before checking, so an event emitted in between still wakes us
+            emitted.as_mut().enable();
+            if self.events.lock().unwrap().iter().any(|e| e.event_type == event_type) {
+                return;
+            }
+            emitted.await;
+        }
+    }
+
+    pub fn events(&self) -> Vec<StreamEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    pub fn types(&self) -> Vec<String> {
+        self.events().into_iter().map(|e| e.event_type).collect()
+    }
+
+    pub fn of_type(&self, event_type: &str) -> Vec<StreamEvent> {
+        self.events().into_iter().filter(|e| e.event_type == event_type).collect()
+    }
+}
+
+impl StreamSink for RecordingSink {
+    fn emit(&self, event: StreamEvent) {
+        self.events.lock().unwrap().push(event);
+        self.emitted.notify_waiters();
+    }
+}
+
+/// Saves a "Weather" agent and an untitled conversation with it
+pub fn save_conversation(conn: &Connection) -> Conversation {
+    let agent = Agent::new(CreateAgentInput {
+        name: "Weather".to_string(),
+        api_url: "http://localhost/stream_run".to_string(),
+        auth_token: "token".to_string(),
+        project_id: "project-1".to_string(),
+        history_window: None,
+        connect_timeout_secs: None,
+        read_timeout_secs: None,
+        max_retries: None,
+    });
+    agent.save(conn).unwrap();
+    let conversation = Conversation::new(agent.id, None);
+    conversation.save(conn).unwrap();
+    conversation
+}
+
+/// An SSE event carrying `payload` as its data
+pub fn sse_event(payload: &serde_json::Value) -> String {
+    format!("event: message\ndata: {}\n\n", payload)
+}
+
+pub fn answer(text: &str) -> String {
+    sse_event(&serde_json::json!({"type": "answer", "content": {"answer": text}}))
+}
+
+pub fn message_end() -> String {
+    sse_event(&serde_json::json!({"type": "message_end", "content": {"message_end": {"code": "0"}}}))
+}
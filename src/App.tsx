@@ -1,13 +1,16 @@
-import { useState, useEffect } from 'react';
+import { useState, useEffect, useRef } from 'react';
 import { invoke } from '@tauri-apps/api/core';
 import { listen, UnlistenFn } from '@tauri-apps/api/event';
+import { open, save } from '@tauri-apps/plugin-dialog';
 import './App.css';
 import Sidebar from './components/Sidebar';
 import ChatArea from './components/ChatArea';
 import AgentModal from './components/AgentModal';
 import ContextPanel from './components/ContextPanel';
-import { Agent, CreateAgentInput, Message, Conversation, StreamEvent } from './types';
+import { Agent, CreateAgentInput, Message, Conversation, StreamEvent, ExportFormat, ImportReport, BackupOptions, WorkspaceSummary, AgentImportReport, ConflictResolution } from './types';
 import RightSidebar from './components/RightSidebar';
+import UsagePanel from './components/UsagePanel';
+import { errorMessage, isCancelled } from './errors';
 
 function App() {
   const [agents, setAgents] = useState<Agent[]>([]);
@@ -17,8 +20,16 @@ function App() {
   const [messages, setMessages] = useState<Message[]>([]);
   const [isModalOpen, setIsModalOpen] = useState(false);
   const [editingAgent, setEditingAgent] = useState<Agent | null>(null);
-  const [isLoading, setIsLoading] = useState(false);
+  const [streamingConversations, setStreamingConversations] = useState<Set<string>>(new Set());
   const [showContext, setShowContext] = useState(false);
+  const [streamStatus, setStreamStatus] = useState<Record<string, string>>({});
+  const currentConversationRef = useRef<Conversation | null>(null);
+
+  const isLoading = currentConversation ? streamingConversations.has(currentConversation.id) : false;
+
+  useEffect(() => {
+    currentConversationRef.current = currentConversation;
+  }, [currentConversation]);
 
   // Load agents on mount
   useEffect(() => {
@@ -98,6 +109,10 @@ function App() {
         api_url: input.api_url,
         auth_token: input.auth_token,
         project_id: input.project_id,
+        history_window: input.history_window ?? editingAgent.history_window,
+        connect_timeout_secs: input.connect_timeout_secs ?? null,
+        read_timeout_secs: input.read_timeout_secs ?? null,
+        max_retries: input.max_retries ?? null,
       };
 
       await invoke('update_agent', { agent: updatedAgent });
@@ -201,41 +216,52 @@ function App() {
     }
   };
 
-  const handleSendMessage = async (content: string) => {
-    if (!selectedAgent || !currentConversation) return;
-
-    setIsLoading(true);
+  const setConversationStreaming = (conversationId: string, streaming: boolean) => {
+    setStreamingConversations((prev) => {
+      const next = new Set(prev);
+      if (streaming) {
+        next.add(conversationId);
+      } else {
+        next.delete(conversationId);
+      }
+      return next;
+    });
+  };
 
-    // Create optimistic user message
-    const userMessage: Message = {
-      id: `temp-${Date.now()}`,
-      conversation_id: currentConversation.id,
-      role: 'user',
-      content,
-      created_at: Date.now(),
-    };
+  // Streams a reply into a placeholder assistant message while `request` runs.
+  // `show` places the placeholder among the messages on screen.
+  const streamReply = async (
+    conversation: Conversation,
+    show: (prev: Message[], placeholder: Message) => Message[],
+    request: () => Promise<unknown>,
+  ) => {
+    setConversationStreaming(conversation.id, true);
 
     // Create placeholder assistant message for streaming
     const streamingAssistantMessage: Message = {
       id: `streaming-${Date.now()}`,
-      conversation_id: currentConversation.id,
+      conversation_id: conversation.id,
       role: 'assistant',
       content: '',
       created_at: Date.now(),
     };
 
-    setMessages((prev) => [...prev, userMessage, streamingAssistantMessage]);
+    setMessages((prev) => show(prev, streamingAssistantMessage));
 
-    // Set up event listener for streaming
+    // Set up event listener for this conversation's stream
     let unlisten: UnlistenFn | null = null;
 
     try {
-      unlisten = await listen<StreamEvent>('chat-stream', (event) => {
+      unlisten = await listen<StreamEvent>(`chat-stream:${conversation.id}`, (event) => {
         const payload = event.payload;
 
         switch (payload.event_type) {
           case 'content':
             // Update the streaming message with accumulated content
+            setStreamStatus((prev) => {
+              const { [conversation.id]: _, ...rest } = prev;
+              return rest;
+            });
             if (payload.full_content !== undefined) {
               setMessages((prev) =>
                 prev.map((msg) =>
@@ -276,7 +302,15 @@ function App() {
             }
             break;
 
+          case 'retrying':
+            setStreamStatus((prev) => ({
+              ...prev,
+              [conversation.id]: `Retrying (${payload.attempt}/${payload.max_retries})…`,
+            }));
+            break;
+
           case 'done':
+          case 'cancelled':
             // Streaming complete - will reload messages for final state
             break;
 
@@ -286,48 +320,341 @@ function App() {
         }
       });
 
-      // Send message to backend (this will trigger streaming events)
-      await invoke<string>('send_chat_message', {
-        agentId: selectedAgent.id,
-        conversationId: currentConversation.id,
-        message: content,
-      });
-
-      // Reload messages to get the actual saved messages with correct IDs
-      await loadMessages(currentConversation.id);
+      // Run the backend command (this will trigger streaming events)
+      try {
+        await request();
+      } finally {
+        // Reload messages to get the actual saved messages with correct IDs (a failed
+        // reply is kept as far as it got), unless the user has moved on meanwhile
+        if (currentConversationRef.current?.id === conversation.id) {
+          await loadMessages(conversation.id);
+        }
+      }
 
       // Reload conversations to update any auto-generated titles
-      if (selectedAgent) {
-        const loadedConversations = await loadConversations(selectedAgent.id);
-        const updated = loadedConversations.find(c => c.id === currentConversation.id);
-        if (updated) {
-          setCurrentConversation(updated);
-        }
+      const loadedConversations = await loadConversations(conversation.agent_id);
+      const updated = loadedConversations.find(c => c.id === conversation.id);
+      if (updated && currentConversationRef.current?.id === conversation.id) {
+        setCurrentConversation(updated);
       }
-    } catch (error) {
-      console.error('Failed to send message:', error);
-      alert('Failed to send message. Please check your agent configuration.');
-      // Remove the streaming message on error
-      setMessages((prev) => prev.filter((msg) => msg.id !== streamingAssistantMessage.id));
     } finally {
       // Clean up the event listener
       if (unlisten) {
         unlisten();
       }
-      setIsLoading(false);
+      setConversationStreaming(conversation.id, false);
+      setStreamStatus((prev) => {
+        const { [conversation.id]: _, ...rest } = prev;
+        return rest;
+      });
+    }
+  };
+
+  const handleSendMessage = async (content: string) => {
+    if (!selectedAgent || !currentConversation) return;
+
+    const agent = selectedAgent;
+    const conversation = currentConversation;
+
+    // Create optimistic user message
+    const userMessage: Message = {
+      id: `temp-${Date.now()}`,
+      conversation_id: conversation.id,
+      role: 'user',
+      content,
+      created_at: Date.now(),
+    };
+
+    try {
+      await streamReply(
+        conversation,
+        (prev, placeholder) => [...prev, userMessage, placeholder],
+        () => invoke<string>('send_chat_message', {
+          agentId: agent.id,
+          conversationId: conversation.id,
+          message: content,
+        }),
+      );
+    } catch (error) {
+      if (!isCancelled(error)) {
+        console.error('Failed to send message:', error);
+        alert(`Failed to send message: ${errorMessage(error)}`);
+      }
+      if (currentConversationRef.current?.id === conversation.id) {
+        await loadMessages(conversation.id);
+      }
+    }
+  };
+
+  const handleRegenerate = async () => {
+    if (!currentConversation) return;
+
+    const conversation = currentConversation;
+    try {
+      await streamReply(
+        conversation,
+        // The new version replaces the reply after the last user message
+        (prev, placeholder) => {
+          const lastUser = prev.map((m) => m.role).lastIndexOf('user');
+          return [...prev.slice(0, lastUser + 1), placeholder];
+        },
+        () => invoke<string>('regenerate_response', { conversationId: conversation.id }),
+      );
+    } catch (error) {
+      if (!isCancelled(error)) {
+        console.error('Failed to regenerate response:', error);
+        alert(`Failed to regenerate the response: ${errorMessage(error)}`);
+      }
+      if (currentConversationRef.current?.id === conversation.id) {
+        await loadMessages(conversation.id);
+      }
+    }
+  };
+
+  const handleEditMessage = async (messageId: string, content: string) => {
+    if (!currentConversation) return;
+
+    const conversation = currentConversation;
+    const editedMessage: Message = {
+      id: `temp-${Date.now()}`,
+      conversation_id: conversation.id,
+      role: 'user',
+      content,
+      created_at: Date.now(),
+    };
+
+    try {
+      await streamReply(
+        conversation,
+        // The edit replaces the original message and everything after it
+        (prev, placeholder) => {
+          const index = prev.findIndex((m) => m.id === messageId);
+          return [...prev.slice(0, index < 0 ? prev.length : index), editedMessage, placeholder];
+        },
+        () => invoke<string>('edit_message', { messageId, newContent: content }),
+      );
+    } catch (error) {
+      if (!isCancelled(error)) {
+        console.error('Failed to edit message:', error);
+        alert(`Failed to edit the message: ${errorMessage(error)}`);
+      }
+      if (currentConversationRef.current?.id === conversation.id) {
+        await loadMessages(conversation.id);
+      }
+    }
+  };
+
+  const handleForkConversation = async (messageId: string, agentId: string) => {
+    if (!currentConversation) return;
+
+    try {
+      const fork = await invoke<Conversation>('fork_conversation', {
+        conversationId: currentConversation.id,
+        upToMessageId: messageId,
+        targetAgentId: agentId,
+      });
+
+      const targetAgent = agents.find((a) => a.id === fork.agent_id);
+      if (targetAgent && targetAgent.id !== selectedAgent?.id) {
+        setSelectedAgent(targetAgent);
+        setConversations(await invoke<Conversation[]>('get_conversations', { agentId: targetAgent.id }));
+      } else {
+        setConversations((prev) => [fork, ...prev]);
+      }
+      setCurrentConversation(fork);
+    } catch (error) {
+      console.error('Failed to fork conversation:', error);
+      alert(`Failed to fork the conversation: ${errorMessage(error)}`);
+    }
+  };
+
+  const handleSwitchVersion = async (messageId: string, version: number) => {
+    try {
+      const path = await invoke<Message[]>('switch_message_version', { messageId, version });
+      setMessages(path);
+    } catch (error) {
+      console.error('Failed to switch version:', error);
+    }
+  };
+
+  const handleStopGeneration = async () => {
+    if (!currentConversation) return;
+
+    try {
+      await invoke<boolean>('stop_generation', { conversationId: currentConversation.id });
+    } catch (error) {
+      console.error('Failed to stop generation:', error);
+    }
+  };
+
+  const handleExportConversation = async (conversation: Conversation, format: ExportFormat) => {
+    const extension = format === 'markdown' ? 'md' : format;
+    const path = await save({
+      title: 'Export conversation',
+      defaultPath: `${conversation.title || 'conversation'}.${extension}`,
+      filters: [{ name: format === 'markdown' ? 'Markdown' : format.toUpperCase(), extensions: [extension] }],
+    });
+    if (!path) return;
+
+    try {
+      await invoke('export_conversation', { conversationId: conversation.id, format, path });
+    } catch (error) {
+      console.error('Failed to export conversation:', error);
+      alert(`Failed to export the conversation: ${errorMessage(error)}`);
+    }
+  };
+
+  const handleImportConversations = async () => {
+    // A conversation export, or ChatGPT's conversations.json
+    const path = await open({ title: 'Import conversations', filters: [{ name: 'JSON', extensions: ['json'] }] });
+    if (!path) return;
+
+    try {
+      // Preview first, then import for real once confirmed
+      const preview = await invoke<ImportReport>('import_conversations', { path, agentId: null, dryRun: true });
+      const fresh = preview.conversations.filter((c) => !c.duplicate);
+      const summary = [
+        `${fresh.length} conversation(s) to import, ${preview.conversations.length - fresh.length} already present.`,
+        ...(preview.created_agents.length > 0 ? [`New agents: ${preview.created_agents.join(', ')}`] : []),
+      ].join('\n');
+      if (fresh.length === 0) {
+        alert(summary);
+        return;
+      }
+      if (!window.confirm(`${summary}\n\nImport now?`)) return;
+
+      await invoke<ImportReport>('import_conversations', { path, agentId: null, dryRun: false });
+      await loadAgents();
+      if (selectedAgent) {
+        await loadConversations(selectedAgent.id);
+      }
+    } catch (error) {
+      console.error('Failed to import conversations:', error);
+      alert(`Failed to import conversations: ${errorMessage(error)}`);
+    }
+  };
+
+  const handleExportAgents = async () => {
+    const path = await save({
+      title: 'Export agents',
+      defaultPath: 'agents.json',
+      filters: [
+        { name: 'JSON', extensions: ['json'] },
+        { name: 'TOML', extensions: ['toml'] },
+      ],
+    });
+    if (!path) return;
+    const includeSecrets = window.confirm('Include agent tokens in plain text? Only share such a file with people who may use them.');
+
+    try {
+      await invoke('export_agents', {
+        ids: [],
+        includeSecrets,
+        format: path.endsWith('.toml') ? 'toml' : 'json',
+        path,
+      });
+    } catch (error) {
+      console.error('Failed to export agents:', error);
+      alert(`Failed to export agents: ${errorMessage(error)}`);
+    }
+  };
+
+  const handleImportAgents = async () => {
+    const path = await open({ title: 'Import agents', filters: [{ name: 'Agent bundle', extensions: ['json', 'toml'] }] });
+    if (!path) return;
+
+    try {
+      const report = await invoke<AgentImportReport>('import_agents', { path, resolutions: null });
+      if (!report.applied) {
+        // Ask how to handle each agent that clashes with an existing one
+        const resolutions: Record<string, ConflictResolution> = {};
+        for (const agent of report.agents.filter((a) => a.action === 'unresolved')) {
+          const answer = window.prompt(
+            `An agent with the same ${agent.conflict} as "${agent.name}" already exists.\n` +
+              'Type overwrite, keep_both or skip:',
+            'skip',
+          );
+          if (answer === null) return;
+          resolutions[agent.id] = ['overwrite', 'keep_both'].includes(answer.trim())
+            ? (answer.trim() as ConflictResolution)
+            : 'skip';
+        }
+        await invoke<AgentImportReport>('import_agents', { path, resolutions });
+      }
+      await loadAgents();
+    } catch (error) {
+      console.error('Failed to import agents:', error);
+      alert(`Failed to import agents: ${errorMessage(error)}`);
+    }
+  };
+
+  const handleBackup = async () => {
+    const path = await save({
+      title: 'Back up workspace',
+      defaultPath: 'coze_studio-backup.db.gz',
+      filters: [
+        { name: 'Compressed backup', extensions: ['gz'] },
+        { name: 'Database', extensions: ['db'] },
+      ],
+    });
+    if (!path) return;
+    const options: BackupOptions = {
+      compress: path.endsWith('.gz'),
+      include_tokens: window.confirm('Include agent tokens? They stay encrypted and only work on this computer.'),
+    };
+
+    try {
+      const summary = await invoke<WorkspaceSummary>('backup_workspace', { path, options });
+      alert(`Backed up ${summary.agents} agent(s), ${summary.conversations} conversation(s) and ${summary.messages} message(s).`);
+    } catch (error) {
+      console.error('Failed to back up workspace:', error);
+      alert(`Failed to back up the workspace: ${errorMessage(error)}`);
+    }
+  };
+
+  const handleRestore = async () => {
+    const path = await open({ title: 'Restore workspace', filters: [{ name: 'Backup', extensions: ['gz', 'db'] }] });
+    if (!path) return;
+    if (!window.confirm('Restoring replaces all agents and conversations with the backup. Continue?')) return;
+
+    try {
+      await invoke<WorkspaceSummary>('restore_workspace', { path });
+      setSelectedAgent(null);
+      await loadAgents();
+    } catch (error) {
+      console.error('Failed to restore workspace:', error);
+      alert(`Failed to restore the workspace: ${errorMessage(error)}`);
+    }
+  };
+
+  const handleCopyLogs = async () => {
+    try {
+      const logs = await invoke<string>('get_recent_logs', { lines: 1000 });
+      await navigator.clipboard.writeText(logs);
+      alert('Copied the recent log to the clipboard. Tokens are left out; paste it into your bug report.');
+    } catch (error) {
+      console.error('Failed to copy logs:', error);
+      alert(`Failed to copy the logs: ${errorMessage(error)}`);
     }
   };
 
   return (
     <div className="app">
-      <Sidebar />
+      <Sidebar onBackup={handleBackup} onRestore={handleRestore} onCopyLogs={handleCopyLogs} />
       <main className="main-canvas">
         <ChatArea
           agent={selectedAgent}
+          agents={agents}
           messages={messages}
           onSendMessage={handleSendMessage}
+          onStopGeneration={handleStopGeneration}
+          onRegenerate={handleRegenerate}
+          onSwitchVersion={handleSwitchVersion}
+          onEditMessage={handleEditMessage}
+          onForkConversation={handleForkConversation}
           onCreateConversation={handleCreateConversation}
           isLoading={isLoading}
+          statusText={currentConversation ? streamStatus[currentConversation.id] : undefined}
         />
       </main>
 
@@ -346,16 +673,19 @@ function App() {
         onSelectConversation={handleSelectConversation}
         onCreateConversation={handleCreateConversation}
         onDeleteConversation={handleDeleteConversation}
+        onExportConversation={handleExportConversation}
+        onImportConversations={handleImportConversations}
+        onExportAgents={handleExportAgents}
+        onImportAgents={handleImportAgents}
+        onShowUsage={() => setShowContext(true)}
       />
 
       <ContextPanel
         isOpen={showContext}
         onClose={() => setShowContext(false)}
-        title={selectedAgent ? `Context: ${selectedAgent.name}` : 'Context'}
+        title="Usage"
       >
-        <div className="context-placeholder">
-          <p className="text-secondary text-sm">Relevant files and execution details will appear here.</p>
-        </div>
+        <UsagePanel isOpen={showContext} selectedAgent={selectedAgent} />
       </ContextPanel>
 
       <AgentModal
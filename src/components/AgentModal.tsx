@@ -1,6 +1,18 @@
 import { useState, useEffect } from 'react';
+import { invoke } from '@tauri-apps/api/core';
 import './AgentModal.css';
-import { Agent } from '../types';
+import { Agent, ConnectionCheck, ConnectionReport } from '../types';
+import { errorMessage } from '../errors';
+
+const CHECK_LABELS: [keyof ConnectionReport, string][] = [
+    ['url', 'URL'],
+    ['dns', 'DNS'],
+    ['connect', 'Connect'],
+    ['tls', 'TLS'],
+    ['http', 'HTTP'],
+    ['auth', 'Auth'],
+    ['stream', 'Stream'],
+];
 
 interface AgentModalProps {
     isOpen: boolean;
@@ -14,34 +26,77 @@ export default function AgentModal({ isOpen, onClose, onSave, editAgent }: Agent
     const [apiUrl, setApiUrl] = useState('');
     const [authToken, setAuthToken] = useState('');
     const [projectId, setProjectId] = useState('');
+    const [historyWindow, setHistoryWindow] = useState(10);
+    const [connectTimeout, setConnectTimeout] = useState('');
+    const [readTimeout, setReadTimeout] = useState('');
+    const [maxRetries, setMaxRetries] = useState('');
+    const [isTesting, setIsTesting] = useState(false);
+    const [report, setReport] = useState<ConnectionReport | null>(null);
+    const [testError, setTestError] = useState<string | null>(null);
 
     useEffect(() => {
+        setReport(null);
+        setTestError(null);
         if (editAgent) {
             setName(editAgent.name);
             setApiUrl(editAgent.api_url || '');
             setAuthToken(editAgent.auth_token || '');
             setProjectId(editAgent.project_id || '');
+            setHistoryWindow(editAgent.history_window ?? 10);
+            setConnectTimeout(editAgent.connect_timeout_secs?.toString() ?? '');
+            setReadTimeout(editAgent.read_timeout_secs?.toString() ?? '');
+            setMaxRetries(editAgent.max_retries?.toString() ?? '');
         } else {
             setName('');
             setApiUrl('');
             setAuthToken('');
             setProjectId('');
+            setHistoryWindow(10);
+            setConnectTimeout('');
+            setReadTimeout('');
+            setMaxRetries('');
         }
     }, [editAgent, isOpen]);
 
     if (!isOpen) return null;
 
+    // Empty override fields fall back to the app defaults
+    const optionalNumber = (value: string) => (value.trim() === '' ? null : Math.max(0, parseInt(value, 10) || 0));
+
+    const currentInput = () => ({
+        name,
+        api_url: apiUrl,
+        auth_token: authToken,
+        project_id: projectId,
+        history_window: historyWindow,
+        connect_timeout_secs: optionalNumber(connectTimeout),
+        read_timeout_secs: optionalNumber(readTimeout),
+        max_retries: optionalNumber(maxRetries),
+    });
+
     const handleSubmit = (e: React.FormEvent) => {
         e.preventDefault();
-        onSave({
-            name,
-            api_url: apiUrl,
-            auth_token: authToken,
-            project_id: projectId,
-        });
+        onSave(currentInput());
         onClose();
     };
 
+    const handleTestConnection = async () => {
+        setIsTesting(true);
+        setReport(null);
+        setTestError(null);
+        try {
+            // A masked token stands for the one saved with the agent being edited
+            setReport(await invoke<ConnectionReport>('test_agent_connection', {
+                agentId: editAgent?.id ?? null,
+                agent: currentInput(),
+            }));
+        } catch (error) {
+            setTestError(errorMessage(error));
+        } finally {
+            setIsTesting(false);
+        }
+    };
+
     return (
         <div className="modal-overlay" onClick={onClose}>
             <div className="modal-content" onClick={(e) => e.stopPropagation()}>
@@ -83,9 +138,13 @@ export default function AgentModal({ isOpen, onClose, onSave, editAgent }: Agent
                             type="password"
                             value={authToken}
                             onChange={(e) => setAuthToken(e.target.value)}
+                            onFocus={(e) => editAgent && authToken === editAgent.auth_token && e.target.select()}
                             placeholder="Your Personal Access Token"
                             required
                         />
+                        {editAgent && (
+                            <small className="text-secondary">Saved tokens are stored encrypted. Leave unchanged to keep the current one.</small>
+                        )}
                     </div>
                     <div className="form-group">
                         <label>Bot ID / Project ID</label>
@@ -97,7 +156,81 @@ export default function AgentModal({ isOpen, onClose, onSave, editAgent }: Agent
                             required
                         />
                     </div>
+                    <div className="form-group">
+                        <label>History Window (messages)</label>
+                        <input
+                            type="number"
+                            min={0}
+                            value={historyWindow}
+                            onChange={(e) => setHistoryWindow(Math.max(0, parseInt(e.target.value, 10) || 0))}
+                            placeholder="Earlier messages sent with each question"
+                        />
+                    </div>
+                    <div className="form-group">
+                        <label>Connect Timeout (s)</label>
+                        <input
+                            type="number"
+                            min={1}
+                            value={connectTimeout}
+                            onChange={(e) => setConnectTimeout(e.target.value)}
+                            placeholder="Default: 10"
+                        />
+                    </div>
+                    <div className="form-group">
+                        <label>Read Timeout (s)</label>
+                        <input
+                            type="number"
+                            min={1}
+                            value={readTimeout}
+                            onChange={(e) => setReadTimeout(e.target.value)}
+                            placeholder="Default: 120"
+                        />
+                    </div>
+                    <div className="form-group">
+                        <label>Max Retries</label>
+                        <input
+                            type="number"
+                            min={0}
+                            max={10}
+                            value={maxRetries}
+                            onChange={(e) => setMaxRetries(e.target.value)}
+                            placeholder="Default: 3"
+                        />
+                    </div>
+                    {(report || testError) && (
+                        <div className="connection-report">
+                            {testError && <div className="connection-check failed">{testError}</div>}
+                            {report && CHECK_LABELS.map(([key, label]) => {
+                                const check = report[key] as ConnectionCheck;
+                                return (
+                                    <div key={key} className={`connection-check ${check.status}`}>
+                                        <span className="connection-check-label">{label}</span>
+                                        <span className="connection-check-detail">
+                                            {check.detail}
+                                            {check.inferred && ' (inferred)'}
+                                            {check.duration_ms != null && ` (${check.duration_ms} ms)`}
+                                        </span>
+                                    </div>
+                                );
+                            })}
+                            {report?.first_byte_ms != null && (
+                                <div className="connection-check passed">
+                                    <span className="connection-check-label">First byte</span>
+                                    <span className="connection-check-detail">{report.first_byte_ms} ms</span>
+                                </div>
+                            )}
+                        </div>
+                    )}
                     <div className="modal-actions">
+                        <button
+                            type="button"
+                            className="btn-secondary"
+                            onClick={handleTestConnection}
+                            disabled={isTesting || !apiUrl || !authToken}
+                            title="Sends a one-word message to the agent, which uses a few tokens and may start a conversation in Coze"
+                        >
+                            {isTesting ? 'Testing…' : 'Test Connection'}
+                        </button>
                         <button type="button" className="btn-secondary" onClick={onClose}>
                             Cancel
                         </button>
@@ -5,17 +5,31 @@ import { Message, Agent } from '../types';
 
 interface ChatAreaProps {
     agent: Agent | null;
+    agents: Agent[];
     messages: Message[];
     isLoading: boolean;
+    statusText?: string;
     onSendMessage: (message: string) => Promise<void>;
+    onStopGeneration: () => void;
+    onRegenerate: () => void;
+    onSwitchVersion: (messageId: string, version: number) => void;
+    onEditMessage: (messageId: string, content: string) => void;
+    onForkConversation: (messageId: string, agentId: string) => void;
     onCreateConversation: () => void;
 }
 
 export default function ChatArea({
     agent,
+    agents,
     messages,
     isLoading,
+    statusText,
     onSendMessage,
+    onStopGeneration,
+    onRegenerate,
+    onSwitchVersion,
+    onEditMessage,
+    onForkConversation,
     onCreateConversation,
 }: ChatAreaProps) {
     const [input, setInput] = useState('');
@@ -115,7 +129,7 @@ export default function ChatArea({
                         <div className="chat-agent-name">{agent.name}</div>
                         <div className="chat-status-badge">
                             <div className="status-dot-green"></div>
-                            <span>Ready</span>
+                            <span>{statusText || (isLoading ? 'Responding…' : 'Ready')}</span>
                         </div>
                     </div>
                     <div className="chat-header-actions">
@@ -147,12 +161,21 @@ export default function ChatArea({
                             <p>How can I help you today?</p>
                         </div>
                     ) : (
-                        messages.map((message) => (
+                        messages.map((message, index) => (
                             <MessageBubble
                                 key={message.id}
                                 message={message}
                                 isStreaming={isLoading && message.id.startsWith('streaming-')}
                                 agentName={agent?.name}
+                                onRegenerate={
+                                    !isLoading && message.role === 'assistant' && index === messages.length - 1
+                                        ? onRegenerate
+                                        : undefined
+                                }
+                                onSwitchVersion={isLoading ? undefined : onSwitchVersion}
+                                onEdit={isLoading || message.id.startsWith('temp-') ? undefined : onEditMessage}
+                                forkTargets={agents}
+                                onFork={isLoading ? undefined : onForkConversation}
                             />
                         ))
                     )}
@@ -176,16 +199,29 @@ export default function ChatArea({
                             rows={1}
                             disabled={isLoading}
                         />
-                        <button
-                            onClick={handleSubmit}
-                            className="composer-send-btn"
-                            disabled={!input.trim() || isLoading}
-                        >
-                            <svg width="20" height="20" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="3" strokeLinecap="round" strokeLinejoin="round">
-                                <line x1="12" y1="19" x2="12" y2="5"></line>
-                                <polyline points="5 12 12 5 19 12"></polyline>
-                            </svg>
-                        </button>
+                        {isLoading ? (
+                            <button
+                                type="button"
+                                onClick={onStopGeneration}
+                                className="composer-send-btn"
+                                title="Stop generating"
+                            >
+                                <svg width="20" height="20" viewBox="0 0 24 24" fill="currentColor">
+                                    <rect x="6" y="6" width="12" height="12" rx="2"></rect>
+                                </svg>
+                            </button>
+                        ) : (
+                            <button
+                                onClick={handleSubmit}
+                                className="composer-send-btn"
+                                disabled={!input.trim()}
+                            >
+                                <svg width="20" height="20" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="3" strokeLinecap="round" strokeLinejoin="round">
+                                    <line x1="12" y1="19" x2="12" y2="5"></line>
+                                    <polyline points="5 12 12 5 19 12"></polyline>
+                                </svg>
+                            </button>
+                        )}
                     </div>
                 </div>
             </div>
@@ -42,7 +42,7 @@ export default function ConversationPanel({
     };
 
     const formatDate = (timestamp: number) => {
-        const date = new Date(timestamp * 1000);
+        const date = new Date(timestamp);
         const now = new Date();
         const diffMs = now.getTime() - date.getTime();
         const diffMins = Math.floor(diffMs / 60000);
@@ -4,7 +4,7 @@ import { Prism as SyntaxHighlighter } from 'react-syntax-highlighter';
 import { vscDarkPlus, vs } from 'react-syntax-highlighter/dist/esm/styles/prism';
 import ToolCallChain from './ToolCallChain';
 import './MessageBubble.css';
-import { Message } from '../types';
+import { Agent, Message } from '../types';
 import html2canvas from 'html2canvas';
 import { saveAs } from 'file-saver';
 
@@ -12,13 +12,22 @@ interface MessageBubbleProps {
     message: Message;
     isStreaming?: boolean;
     agentName?: string;
+    onRegenerate?: () => void;
+    onSwitchVersion?: (messageId: string, version: number) => void;
+    onEdit?: (messageId: string, content: string) => void;
+    forkTargets?: Agent[];
+    onFork?: (messageId: string, agentId: string) => void;
 }
 
 
-export default function MessageBubble({ message, isStreaming = false, agentName }: MessageBubbleProps) {
+export default function MessageBubble({ message, isStreaming = false, agentName, onRegenerate, onSwitchVersion, onEdit, forkTargets = [], onFork }: MessageBubbleProps) {
 
     const [showActions, setShowActions] = useState(false);
+    const [isEditing, setIsEditing] = useState(false);
+    const [draft, setDraft] = useState(message.content);
     const isUser = message.role === 'user';
+    const version = message.version;
+    const versionCount = message.version_count ?? 1;
     const messageRef = useRef<HTMLDivElement>(null);
 
     const copyToClipboard = (text: string) => {
@@ -120,6 +129,24 @@ export default function MessageBubble({ message, isStreaming = false, agentName
                                 <button className="btn-icon-xs" onClick={exportAsImage} title="Image">
                                     <svg width="14" height="14" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2.5"><rect x="3" y="3" width="18" height="18" rx="2" ry="2"></rect><circle cx="8.5" cy="8.5" r="1.5"></circle><polyline points="21 15 16 10 5 21"></polyline></svg>
                                 </button>
+                                {onRegenerate && (
+                                    <button className="btn-icon-xs" onClick={onRegenerate} title="Regenerate">
+                                        <svg width="14" height="14" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2.5"><polyline points="23 4 23 10 17 10"></polyline><path d="M20.49 15a9 9 0 11-2.12-9.36L23 10"></path></svg>
+                                    </button>
+                                )}
+                                {onFork && forkTargets.length > 0 && (
+                                    <select
+                                        className="message-fork-select"
+                                        value=""
+                                        title="Continue from here in a new conversation"
+                                        onChange={(e) => e.target.value && onFork(message.id, e.target.value)}
+                                    >
+                                        <option value="">Fork…</option>
+                                        {forkTargets.map((agent) => (
+                                            <option key={agent.id} value={agent.id}>{agent.name}</option>
+                                        ))}
+                                    </select>
+                                )}
                             </div>
                         )}
                     </div>
@@ -132,6 +159,18 @@ export default function MessageBubble({ message, isStreaming = false, agentName
                                 <button className="btn-icon-xs" onClick={() => copyToClipboard(message.content)}>
                                     <svg width="14" height="14" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2.5"><rect x="9" y="9" width="13" height="13" rx="2" ry="2"></rect><path d="M5 15H4a2 2 0 01-2-2V4a2 2 0 012-2h9a2 2 0 012 2v1"></path></svg>
                                 </button>
+                                {onEdit && (
+                                    <button
+                                        className="btn-icon-xs"
+                                        onClick={() => {
+                                            setDraft(message.content);
+                                            setIsEditing(true);
+                                        }}
+                                        title="Edit"
+                                    >
+                                        <svg width="14" height="14" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2.5"><path d="M12 20h9"></path><path d="M16.5 3.5a2.12 2.12 0 013 3L7 19l-4 1 1-4 12.5-12.5z"></path></svg>
+                                    </button>
+                                )}
                             </div>
                         )}
                     </div>
@@ -142,7 +181,30 @@ export default function MessageBubble({ message, isStreaming = false, agentName
                 )}
 
                 <div className="message-content">
-                    {!isUser && isStreaming && !message.content ? (
+                    {isEditing && onEdit ? (
+                        <div className="message-edit">
+                            <textarea
+                                className="message-edit-input"
+                                value={draft}
+                                onChange={(e) => setDraft(e.target.value)}
+                                rows={Math.min(10, draft.split('\n').length + 1)}
+                                autoFocus
+                            />
+                            <div className="message-edit-actions">
+                                <button className="btn btn-secondary" onClick={() => setIsEditing(false)}>Cancel</button>
+                                <button
+                                    className="btn btn-primary"
+                                    disabled={!draft.trim() || draft.trim() === message.content}
+                                    onClick={() => {
+                                        setIsEditing(false);
+                                        onEdit(message.id, draft.trim());
+                                    }}
+                                >
+                                    Send
+                                </button>
+                            </div>
+                        </div>
+                    ) : !isUser && isStreaming && !message.content ? (
                         <div className="thinking-bubble">
                             <span className="thinking-dot"></span>
                             <span className="thinking-dot"></span>
@@ -152,6 +214,36 @@ export default function MessageBubble({ message, isStreaming = false, agentName
                         <>
                             {renderContent(message.content)}
                             {isStreaming && <span className="typing-cursor">▋</span>}
+                            {message.status === 'interrupted' && (
+                                <div className="message-interrupted text-secondary text-sm">Generation stopped</div>
+                            )}
+                            {message.status === 'error' && (
+                                <div className="message-interrupted text-secondary text-sm">The reply failed before it finished</div>
+                            )}
+                            {message.status === 'streaming' && !isStreaming && (
+                                <div className="message-interrupted text-secondary text-sm">Still generating…</div>
+                            )}
+                            {version !== undefined && versionCount > 1 && (
+                                <div className="message-versions text-secondary text-sm">
+                                    <button
+                                        className="btn-icon-xs"
+                                        disabled={!onSwitchVersion || version <= 1}
+                                        onClick={() => onSwitchVersion?.(message.id, version - 1)}
+                                        title="Previous version"
+                                    >
+                                        ‹
+                                    </button>
+                                    <span>{version} / {versionCount}</span>
+                                    <button
+                                        className="btn-icon-xs"
+                                        disabled={!onSwitchVersion || version >= versionCount}
+                                        onClick={() => onSwitchVersion?.(message.id, version + 1)}
+                                        title="Next version"
+                                    >
+                                        ›
+                                    </button>
+                                </div>
+                            )}
                         </>
                     )}
                 </div>
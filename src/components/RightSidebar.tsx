@@ -1,6 +1,6 @@
 import React from 'react';
 import './RightSidebar.css';
-import { Conversation, Agent } from '../types';
+import { Conversation, Agent, ExportFormat } from '../types';
 
 interface RightSidebarProps {
     agents: Agent[];
@@ -14,6 +14,11 @@ interface RightSidebarProps {
     onSelectConversation: (conversation: Conversation) => void;
     onCreateConversation: () => void;
     onDeleteConversation: (id: string) => void;
+    onExportConversation: (conversation: Conversation, format: ExportFormat) => void;
+    onImportConversations: () => void;
+    onExportAgents: () => void;
+    onImportAgents: () => void;
+    onShowUsage: () => void;
 }
 
 const RightSidebar: React.FC<RightSidebarProps> = ({
@@ -28,12 +33,38 @@ const RightSidebar: React.FC<RightSidebarProps> = ({
     onSelectConversation,
     onCreateConversation,
     onDeleteConversation,
+    onExportConversation,
+    onImportConversations,
+    onExportAgents,
+    onImportAgents,
+    onShowUsage,
 }) => {
     return (
         <div className="right-sidebar">
             <div className="agents-section">
                 <div className="section-header">
                     <h3>Agents</h3>
+                    <button className="btn-icon-sm" onClick={onShowUsage} title="Usage">
+                        <svg width="14" height="14" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2.5">
+                            <line x1="18" y1="20" x2="18" y2="10"></line>
+                            <line x1="12" y1="20" x2="12" y2="4"></line>
+                            <line x1="6" y1="20" x2="6" y2="14"></line>
+                        </svg>
+                    </button>
+                    <button className="btn-icon-sm" onClick={onImportAgents} title="Import Agents">
+                        <svg width="14" height="14" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2.5">
+                            <path d="M21 15v4a2 2 0 01-2 2H5a2 2 0 01-2-2v-4"></path>
+                            <polyline points="7 10 12 15 17 10"></polyline>
+                            <line x1="12" y1="15" x2="12" y2="3"></line>
+                        </svg>
+                    </button>
+                    <button className="btn-icon-sm" onClick={onExportAgents} title="Export Agents">
+                        <svg width="14" height="14" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2.5">
+                            <path d="M21 15v4a2 2 0 01-2 2H5a2 2 0 01-2-2v-4"></path>
+                            <polyline points="17 8 12 3 7 8"></polyline>
+                            <line x1="12" y1="3" x2="12" y2="15"></line>
+                        </svg>
+                    </button>
                     <button className="btn-icon-sm" onClick={onAddAgent} title="Add Agent">
                         <svg width="14" height="14" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2.5">
                             <line x1="12" y1="5" x2="12" y2="19"></line>
@@ -76,6 +107,13 @@ const RightSidebar: React.FC<RightSidebarProps> = ({
             <div className="history-section">
                 <div className="section-header">
                     <h3>History</h3>
+                    <button className="btn-icon-sm" onClick={onImportConversations} title="Import Conversations">
+                        <svg width="14" height="14" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2.5">
+                            <path d="M21 15v4a2 2 0 01-2 2H5a2 2 0 01-2-2v-4"></path>
+                            <polyline points="7 10 12 15 17 10"></polyline>
+                            <line x1="12" y1="15" x2="12" y2="3"></line>
+                        </svg>
+                    </button>
                     <button className="btn-icon-sm" onClick={onCreateConversation} title="New Chat">
                         <svg width="14" height="14" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2.5">
                             <line x1="12" y1="5" x2="12" y2="19"></line>
@@ -94,6 +132,20 @@ const RightSidebar: React.FC<RightSidebarProps> = ({
                             <div className="conv-title truncate">{conv.title || 'Untitled Conversation'}</div>
                             <div className="conv-meta">
                                 <span>{new Date(conv.created_at).toLocaleTimeString([], { hour: '2-digit', minute: '2-digit' })}</span>
+                                <select
+                                    className="conv-export-select"
+                                    value=""
+                                    title="Export"
+                                    onClick={(e) => e.stopPropagation()}
+                                    onChange={(e) => {
+                                        if (e.target.value) onExportConversation(conv, e.target.value as ExportFormat);
+                                    }}
+                                >
+                                    <option value="">Export…</option>
+                                    <option value="markdown">Markdown</option>
+                                    <option value="json">JSON</option>
+                                    <option value="html">HTML</option>
+                                </select>
                                 <button
                                     className="btn-delete-conv"
                                     onClick={(e) => { e.stopPropagation(); onDeleteConversation(conv.id); }}
@@ -1,7 +1,13 @@
 import './Sidebar.css';
 import ThemeToggle from './ThemeToggle';
 
-export default function Sidebar() {
+interface SidebarProps {
+    onBackup: () => void;
+    onRestore: () => void;
+    onCopyLogs: () => void;
+}
+
+export default function Sidebar({ onBackup, onRestore, onCopyLogs }: SidebarProps) {
     return (
         <div className="sidebar-slim">
             <div className="sidebar-logo">
@@ -36,6 +42,27 @@ export default function Sidebar() {
 
             <div className="sidebar-footer-actions">
                 <ThemeToggle />
+                <button className="nav-item" title="Back Up Workspace" onClick={onBackup}>
+                    <svg width="20" height="20" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2" strokeLinecap="round" strokeLinejoin="round">
+                        <ellipse cx="12" cy="5" rx="9" ry="3"></ellipse>
+                        <path d="M21 12c0 1.66-4 3-9 3s-9-1.34-9-3"></path>
+                        <path d="M3 5v14c0 1.66 4 3 9 3s9-1.34 9-3V5"></path>
+                    </svg>
+                </button>
+                <button className="nav-item" title="Restore Workspace" onClick={onRestore}>
+                    <svg width="20" height="20" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2" strokeLinecap="round" strokeLinejoin="round">
+                        <polyline points="1 4 1 10 7 10"></polyline>
+                        <path d="M3.51 15a9 9 0 1 0 2.13-9.36L1 10"></path>
+                    </svg>
+                </button>
+                <button className="nav-item" title="Copy Recent Logs" onClick={onCopyLogs}>
+                    <svg width="20" height="20" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2" strokeLinecap="round" strokeLinejoin="round">
+                        <path d="M14 2H6a2 2 0 0 0-2 2v16a2 2 0 0 0 2 2h12a2 2 0 0 0 2-2V8z"></path>
+                        <polyline points="14 2 14 8 20 8"></polyline>
+                        <line x1="8" y1="13" x2="16" y2="13"></line>
+                        <line x1="8" y1="17" x2="16" y2="17"></line>
+                    </svg>
+                </button>
                 <button className="nav-item" title="Settings">
                     <svg width="20" height="20" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2" strokeLinecap="round" strokeLinejoin="round">
                         <circle cx="12" cy="12" r="3"></circle>
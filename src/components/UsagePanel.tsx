@@ -0,0 +1,124 @@
+import { useEffect, useState } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+import './UsagePanel.css';
+import { Agent, UsageBucket, UsageGroup } from '../types';
+import { errorMessage } from '../errors';
+
+interface UsagePanelProps {
+    isOpen: boolean;
+    selectedAgent: Agent | null;
+}
+
+const RANGES = [
+    { days: 1, label: '24 hours' },
+    { days: 7, label: '7 days' },
+    { days: 30, label: '30 days' },
+];
+
+const GROUPS: { value: UsageGroup; label: string }[] = [
+    { value: 'day', label: 'Day' },
+    { value: 'agent', label: 'Agent' },
+    { value: 'conversation', label: 'Conversation' },
+];
+
+const DAY_MS = 24 * 60 * 60 * 1000;
+
+function formatMs(ms: number | null): string {
+    if (ms === null) return '–';
+    return ms >= 1000 ? `${(ms / 1000).toFixed(1)}s` : `${Math.round(ms)}ms`;
+}
+
+function formatCount(count: number | null): string {
+    return count === null ? '–' : count.toLocaleString();
+}
+
+export default function UsagePanel({ isOpen, selectedAgent }: UsagePanelProps) {
+    const [days, setDays] = useState(7);
+    const [groupBy, setGroupBy] = useState<UsageGroup>('day');
+    const [onlySelected, setOnlySelected] = useState(false);
+    const [buckets, setBuckets] = useState<UsageBucket[]>([]);
+    const [error, setError] = useState<string | null>(null);
+
+    const agentId = onlySelected && selectedAgent ? selectedAgent.id : null;
+
+    useEffect(() => {
+        if (!isOpen) return;
+        const to = Date.now();
+        invoke<UsageBucket[]>('get_usage_stats', { agentId, from: to - days * DAY_MS, to, groupBy })
+            .then((result) => {
+                setBuckets(result);
+                setError(null);
+            })
+            .catch((e) => setError(errorMessage(e)));
+    }, [isOpen, days, groupBy, agentId]);
+
+    const totals = buckets.reduce(
+        (sum, b) => ({
+            replies: sum.replies + b.replies,
+            tokens: sum.tokens + (b.total_tokens ?? 0),
+        }),
+        { replies: 0, tokens: 0 },
+    );
+
+    return (
+        <div className="usage-panel">
+            <div className="usage-filters">
+                <select value={days} onChange={(e) => setDays(Number(e.target.value))}>
+                    {RANGES.map((r) => (
+                        <option key={r.days} value={r.days}>Last {r.label}</option>
+                    ))}
+                </select>
+                <select value={groupBy} onChange={(e) => setGroupBy(e.target.value as UsageGroup)}>
+                    {GROUPS.map((g) => (
+                        <option key={g.value} value={g.value}>By {g.label.toLowerCase()}</option>
+                    ))}
+                </select>
+                {selectedAgent && (
+                    <label className="usage-scope text-sm">
+                        <input
+                            type="checkbox"
+                            checked={onlySelected}
+                            onChange={(e) => setOnlySelected(e.target.checked)}
+                        />
+                        Only {selectedAgent.name}
+                    </label>
+                )}
+            </div>
+
+            {error && <p className="usage-error text-sm">{error}</p>}
+
+            <div className="usage-summary">
+                <div>
+                    <span className="usage-figure">{totals.replies.toLocaleString()}</span>
+                    <span className="text-secondary text-sm">replies</span>
+                </div>
+                <div>
+                    <span className="usage-figure">{totals.tokens.toLocaleString()}</span>
+                    <span className="text-secondary text-sm">tokens</span>
+                </div>
+            </div>
+
+            {buckets.length === 0 ? (
+                <p className="text-secondary text-sm">No replies in this period.</p>
+            ) : (
+                <ul className="usage-list">
+                    {buckets.map((b) => (
+                        <li key={b.key} className="usage-row">
+                            <div className="usage-row-title truncate">
+                                {b.label ?? (groupBy === 'day' ? b.key : `Deleted ${groupBy}`)}
+                            </div>
+                            <div className="usage-row-stats text-sm text-secondary">
+                                <span>{b.replies} replies</span>
+                                <span>{formatCount(b.total_tokens)} tokens</span>
+                                <span title="Average time to first token">TTFT {formatMs(b.avg_first_token_ms)}</span>
+                                <span title={`Slowest ${formatMs(b.max_duration_ms)}`}>
+                                    avg {formatMs(b.avg_duration_ms)}
+                                </span>
+                            </div>
+                        </li>
+                    ))}
+                </ul>
+            )}
+        </div>
+    );
+}
@@ -0,0 +1,27 @@
+import { AppError } from './types';
+
+export function isAppError(error: unknown): error is AppError {
+    return typeof error === 'object' && error !== null && 'code' in error && 'message' in error;
+}
+
+// The user stopped the request, so there is nothing to report
+export function isCancelled(error: unknown): boolean {
+    return isAppError(error) && error.code === 'cancelled';
+}
+
+// Text to show for a failed command, with a hint when the kind of failure suggests one
+export function errorMessage(error: unknown): string {
+    if (!isAppError(error)) {
+        return String(error);
+    }
+    switch (error.code) {
+        case 'auth':
+            return `${error.message}. Check the agent's token.`;
+        case 'rate_limited':
+            return error.retry_after_secs !== undefined
+                ? `${error.message}. Try again in ${error.retry_after_secs}s.`
+                : `${error.message}. Try again later.`;
+        default:
+            return error.retryable ? `${error.message}. Try again.` : error.message;
+    }
+}
@@ -2,8 +2,12 @@ export interface Agent {
     id: string;
     name: string;
     api_url: string;
-    auth_token: string;
+    auth_token: string; // masked; send it back unchanged to keep the saved token
     project_id: string;
+    history_window: number;
+    connect_timeout_secs?: number | null;
+    read_timeout_secs?: number | null;
+    max_retries?: number | null;
     created_at: number;
 }
 
@@ -12,13 +16,19 @@ export interface CreateAgentInput {
     api_url: string;
     auth_token: string;
     project_id: string;
+    history_window?: number;
+    connect_timeout_secs?: number | null;
+    read_timeout_secs?: number | null;
+    max_retries?: number | null;
 }
 
 export interface Conversation {
     id: string;
     agent_id: string;
     title: string | null;
-    created_at: number;
+    created_at: number;      // milliseconds since the epoch
+    forked_from_conversation_id?: string | null;
+    forked_from_message_id?: string | null;
 }
 
 export interface ToolCall {
@@ -34,14 +44,134 @@ export interface Message {
     conversation_id: string;
     role: 'user' | 'assistant';
     content: string;
-    created_at: number;
+    created_at: number;      // milliseconds since the epoch
+    seq?: number;            // position in the conversation, in save order
     tool_calls?: ToolCall[];
+    status?: 'complete' | 'interrupted' | 'error' | 'streaming';
+    parent_id?: string | null;
+    version?: number;        // 1-based among the versions of this turn
+    version_count?: number;  // how many versions this turn has
+}
+
+// One path through a conversation's message tree, ending at `leaf_id`
+export interface Branch {
+    leaf_id: string;
+    message_count: number;
+    last_user_message: string | null;
+    updated_at: number;
+    active: boolean;
 }
 
 export interface StreamEvent {
-    event_type: 'content' | 'tool_call' | 'tool_result' | 'done' | 'error';
+    event_type: 'content' | 'tool_call' | 'tool_result' | 'retrying' | 'done' | 'error' | 'cancelled';
+    conversation_id: string;
+    message_id: string;
     content?: string;
     tool_call?: ToolCall;
     full_content?: string;
     tool_calls?: ToolCall[];
+    attempt?: number;
+    max_retries?: number;
+    retry_in_ms?: number;
+}
+
+export interface SearchHit {
+    message_id: string;
+    conversation_id: string;
+    conversation_title: string | null;
+    agent_id: string;
+    agent_name: string;
+    role: 'user' | 'assistant';
+    snippet: string; // HTML-escaped, matches wrapped in <mark>
+    created_at: number;
+}
+
+export type ExportFormat = 'markdown' | 'json' | 'html';
+
+export interface ImportedConversation {
+    id: string;
+    title: string | null;
+    agent_name: string;
+    message_count: number;
+    duplicate: boolean;
+}
+
+export interface ImportReport {
+    dry_run: boolean;
+    format: 'coze_studio' | 'chatgpt';
+    conversations: ImportedConversation[];
+    created_agents: string[];
+}
+
+export interface BackupOptions {
+    compress: boolean;
+    include_tokens: boolean;
+}
+
+export interface WorkspaceSummary {
+    schema_version: number;
+    agents: number;
+    conversations: number;
+    messages: number;
+}
+
+export type ConflictResolution = 'skip' | 'overwrite' | 'keep_both';
+
+export interface AgentImport {
+    id: string;
+    name: string;
+    conflict: 'id' | 'name' | null;
+    existing_id: string | null;
+    action: 'created' | 'updated' | 'skipped' | 'unresolved' | 'would_create' | 'would_update';
+}
+
+export interface AgentImportReport {
+    applied: boolean;
+    agents: AgentImport[];
+}
+
+export interface ConnectionCheck {
+    status: 'passed' | 'failed' | 'skipped';
+    detail: string;
+    duration_ms: number | null;
+    inferred: boolean;
+}
+
+export interface ConnectionReport {
+    ok: boolean;
+    url: ConnectionCheck;
+    dns: ConnectionCheck;
+    connect: ConnectionCheck;
+    tls: ConnectionCheck;
+    http: ConnectionCheck;
+    auth: ConnectionCheck;
+    stream: ConnectionCheck;
+    http_status: number | null;
+    first_byte_ms: number | null;
+}
+
+// What every backend command rejects with
+export interface AppError {
+    code: 'not_found' | 'validation' | 'auth' | 'rate_limited' | 'network' | 'upstream' | 'agent' | 'database' | 'io' | 'cancelled';
+    message: string;
+    retryable: boolean;        // the same request may succeed later
+    status?: number;           // HTTP status, for upstream and rate_limited
+    body?: string;             // start of the upstream error response
+    retry_after_secs?: number;
+}
+
+export type UsageGroup = 'day' | 'agent' | 'conversation';
+
+export interface UsageBucket {
+    key: string; // UTC date (YYYY-MM-DD), agent id or conversation id
+    label: string | null; // agent name or conversation title, null once deleted
+    replies: number;
+    input_tokens: number | null;
+    output_tokens: number | null;
+    total_tokens: number | null;
+    response_bytes: number;
+    response_chars: number;
+    avg_first_token_ms: number | null;
+    avg_duration_ms: number;
+    max_duration_ms: number;
 }